@@ -0,0 +1,87 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use enes::bus::Bus;
+use enes::cpu::CPU;
+
+// Counts X up to 0xff, doing some arithmetic and a zero page store on every
+// iteration, then hits BRK:
+//
+//   0600: LDX #$00
+//   0602: INX
+//   0603: TXA
+//   0604: ADC #$01
+//   0606: STA $10
+//   0608: CPX #$FF
+//   060a: BNE $0602
+//   060c: BRK
+const COUNTER_LOOP: [u8; 13] = [
+    0xa2, 0x00, 0xe8, 0x8a, 0x69, 0x01, 0x85, 0x10, 0xe0, 0xff, 0xd0, 0xf6, 0x00,
+];
+
+// Same as COUNTER_LOOP but walks a pointer through memory with the indexed
+// and indirect addressing modes:
+//
+//   0600: LDA #$00
+//   0602: STA $20
+//   0604: LDA #$03
+//   0606: STA $21
+//   0608: LDX #$00
+//   060a: TXA
+//   060b: STA $0300,X
+//   060e: LDA ($20,X)
+//   0610: ADC $0300,X
+//   0613: INX
+//   0614: CPX #$FF
+//   0616: BNE $060a
+//   0618: BRK
+const INDEXED_LOOP: [u8; 25] = [
+    0xa9, 0x00, 0x85, 0x20, 0xa9, 0x03, 0x85, 0x21, 0xa2, 0x00, 0x8a, 0x9d, 0x00, 0x03,
+    0xa1, 0x20, 0x7d, 0x00, 0x03, 0xe8, 0xe0, 0xff, 0xd0, 0xf2, 0x00,
+];
+
+fn setup(program: &[u8]) -> CPU {
+    let mut cpu = CPU::new(Bus::new());
+    cpu.stop_on_brk = true;
+    cpu.load(program.to_vec());
+    cpu
+}
+
+fn restart(cpu: &mut CPU) {
+    cpu.register_a = 0;
+    cpu.register_x = 0;
+    cpu.register_y = 0;
+    cpu.status = 0;
+    cpu.program_counter = 0x0600;
+}
+
+fn count_instructions(program: &[u8]) -> u64 {
+    let mut cpu = setup(program);
+    restart(&mut cpu);
+    let mut executed = 0;
+    cpu.run_with_callback(|_| executed += 1);
+    executed
+}
+
+fn bench_program(c: &mut Criterion, name: &str, program: &[u8]) {
+    let mut group = c.benchmark_group("cpu");
+    group.throughput(Throughput::Elements(count_instructions(program)));
+
+    let mut cpu = setup(program);
+    group.bench_function(name, |b| {
+        b.iter(|| {
+            restart(&mut cpu);
+            cpu.run();
+        })
+    });
+    group.finish();
+}
+
+fn counter_loop(c: &mut Criterion) {
+    bench_program(c, "counter_loop", &COUNTER_LOOP);
+}
+
+fn indexed_loop(c: &mut Criterion) {
+    bench_program(c, "indexed_loop", &INDEXED_LOOP);
+}
+
+criterion_group!(benches, counter_loop, indexed_loop);
+criterion_main!(benches);
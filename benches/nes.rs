@@ -0,0 +1,59 @@
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use enes::filter::{Filter, FilterChain};
+use enes::nes::{self, Nes, DEFAULT_PALETTE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use enes::snake::SNAKE_CODE;
+
+// Whole frames of the snake game, the way the frontend runs them. The snake
+// keeps turning so that it lives longer, and the game starts over when it
+// dies.
+fn run_frame(c: &mut Criterion) {
+    let mut nes = Nes::builder().seed(1).stop_on_brk(true).build().unwrap();
+    nes.load_rom(SNAKE_CODE).unwrap();
+    let keys = [0x77, 0x64, 0x73, 0x61];
+    let mut frame = 0;
+
+    let mut group = c.benchmark_group("nes");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("snake_run_frame", |b| {
+        b.iter(|| {
+            if frame % 8 == 0 {
+                nes.set_input(keys[frame / 8 % keys.len()]);
+            }
+            frame += 1;
+            if nes.run_frame() != Ok(true) {
+                nes.power_cycle();
+            }
+        })
+    });
+    group.finish();
+}
+
+// Converting the screen memory to RGB and running it through the filters,
+// upscaled, which the frontend does for every frame that changed
+fn screen_and_filters(c: &mut Criterion) {
+    let mut nes = Nes::new(1);
+    nes.load_rom(SNAKE_CODE).unwrap();
+    for _ in 0..30 {
+        nes.run_frame().unwrap();
+    }
+    let mut screen_state = [0u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+
+    let mut group = c.benchmark_group("nes");
+    group.throughput(Throughput::Elements(1));
+    for (name, filters) in [
+        ("screen_scanlines", &[Filter::Scanlines][..]),
+        ("screen_all_filters", &[Filter::Scanlines, Filter::Glow, Filter::Curvature][..]),
+    ] {
+        let mut chain = FilterChain::new(filters, SCREEN_WIDTH, SCREEN_HEIGHT);
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                nes::read_screen(nes.cpu(), &DEFAULT_PALETTE, &mut screen_state);
+                chain.apply(&screen_state).len()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, run_frame, screen_and_filters);
+criterion_main!(benches);
@@ -1,4 +1,9 @@
+use crate::cheats::Cheats;
 use crate::cpu::Mem;
+use crate::replay::XorShift;
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
 
 //  _______________ $10000  _______________
 // | PRG-ROM       |       |               |
@@ -32,16 +37,142 @@ const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
 
+// What the RAM holds at power on. Real consoles come up with a mostly random
+// pattern that varies between units, and games that read RAM before writing
+// it (for a random seed, or by mistake) behave differently depending on it.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RamPattern {
+    Zero,
+    Ff,
+    // $00 in even pages, $FF in odd ones
+    Alternate,
+    // From the seed of the session, so that replays see the same RAM
+    Random,
+}
+
+impl FromStr for RamPattern {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text {
+            "zero" => Ok(RamPattern::Zero),
+            "ff" => Ok(RamPattern::Ff),
+            "alternate" => Ok(RamPattern::Alternate),
+            "random" => Ok(RamPattern::Random),
+            _ => Err(format!("Unknown RAM pattern '{}', expected zero, ff, alternate or random", text)),
+        }
+    }
+}
+
+impl fmt::Display for RamPattern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            RamPattern::Zero => "zero",
+            RamPattern::Ff => "ff",
+            RamPattern::Alternate => "alternate",
+            RamPattern::Random => "random",
+        };
+        write!(f, "{}", name)
+    }
+}
+
 pub struct Bus {
-    cpu_vram: [u8; 2048]
+    cpu_vram: [u8; 2048],
+    pub cheats: Cheats,
+    nmi_pending: bool,
+    irq_line: bool,
 }
 
 impl Bus {
     pub fn new() -> Self{
         Bus {
-            cpu_vram: [0; 2048]
+            cpu_vram: [0; 2048],
+            cheats: Cheats::new(),
+            nmi_pending: false,
+            irq_line: false,
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.cpu_vram
+    }
+
+    pub fn load_ram(&mut self, ram: &[u8]) {
+        self.cpu_vram.copy_from_slice(ram);
+    }
+
+    // `seed` is only used by RamPattern::Random
+    pub fn fill_ram(&mut self, pattern: RamPattern, seed: u64) {
+        let mut rng = XorShift::new(seed);
+        for (addr, byte) in self.cpu_vram.iter_mut().enumerate() {
+            *byte = match pattern {
+                RamPattern::Zero => 0x00,
+                RamPattern::Ff => 0xff,
+                RamPattern::Alternate if (addr >> 8) % 2 == 0 => 0x00,
+                RamPattern::Alternate => 0xff,
+                RamPattern::Random => rng.next_u64() as u8,
+            };
+        }
+    }
+
+    // Debugger access to the CPU address space. Unlike mem_read/mem_write these
+    // never trigger the side effects of an emulated access (a register read
+    // clearing a flag, a log line...): unmapped addresses and the PPU
+    // registers read as 0 and ignore writes.
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM ..= RAM_MIRRORS_END => self.cpu_vram[(addr & 0b00000111_11111111) as usize],
+            _ => 0,
+        }
+    }
+
+    pub fn poke(&mut self, addr: u16, data: u8) {
+        if let RAM ..= RAM_MIRRORS_END = addr {
+            self.cpu_vram[(addr & 0b00000111_11111111) as usize] = data;
         }
     }
+
+    // Writes the values of the enabled address:value cheats to RAM, once per
+    // frame
+    pub fn apply_freezes(&mut self) {
+        for freeze in self.cheats.freezes() {
+            self.cpu_vram[(freeze.address & 0b00000111_11111111) as usize] = freeze.value;
+        }
+    }
+
+    // Raises the NMI line, as the PPU does when vblank starts with NMIs
+    // enabled. The CPU takes the interrupt before its next instruction.
+    pub fn request_nmi(&mut self) {
+        self.nmi_pending = true;
+    }
+
+    // Whether an NMI was requested since the last poll. NMI is edge
+    // triggered, so polling acknowledges it.
+    pub fn poll_nmi_status(&mut self) -> bool {
+        std::mem::replace(&mut self.nmi_pending, false)
+    }
+
+    // The IRQ line that mappers and the APU frame counter assert. Unlike NMI
+    // it's level triggered: it stays asserted until its source acknowledges
+    // it, and the CPU ignores it while interrupts are disabled.
+    pub fn set_irq(&mut self, asserted: bool) {
+        self.irq_line = asserted;
+    }
+
+    pub fn irq_asserted(&self) -> bool {
+        self.irq_line
+    }
+
+    // The 256 bytes of page $XX00-$XXFF, for hex viewers
+    pub fn peek_page(&self, page: u8) -> [u8; 256] {
+        let mut bytes = [0; 256];
+        let start = (page as u16) << 8;
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = self.peek(start + i as u16);
+        }
+        bytes
+    }
 }
 
 impl Mem for Bus {
@@ -57,7 +188,8 @@ impl Mem for Bus {
             }
             _ => {
                 println!("Ignoring mem access at {}", addr);
-                0
+                // Game Genie codes patch the cartridge space
+                self.cheats.read(addr, 0)
             }
         }
     }
@@ -78,3 +210,42 @@ impl Mem for Bus {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_peek_poke_mirrors_ram() {
+        let mut bus = Bus::new();
+        bus.poke(0x0810, 0x42);
+        assert_eq!(bus.peek(0x0010), 0x42);
+        assert_eq!(bus.mem_read(0x1810), 0x42);
+        assert_eq!(bus.peek_page(0x18)[0x10], 0x42);
+    }
+
+    #[test]
+    fn test_peek_has_no_side_effects_outside_ram() {
+        let mut bus = Bus::new();
+        bus.poke(0x2000, 0x80);
+        assert_eq!(bus.peek(0x2002), 0);
+        assert_eq!(bus.peek(0x8000), 0);
+    }
+
+    #[test]
+    fn test_fill_ram_patterns() {
+        let mut bus = Bus::new();
+        bus.fill_ram("alternate".parse().unwrap(), 0);
+        assert_eq!((bus.peek(0x00ff), bus.peek(0x0100), bus.peek(0x0200)), (0x00, 0xff, 0x00));
+
+        bus.fill_ram(RamPattern::Random, 7);
+        let ram = bus.ram().to_vec();
+        assert!(ram.iter().any(|&byte| byte != ram[0]));
+        bus.fill_ram(RamPattern::Random, 7);
+        assert_eq!(bus.ram(), &ram[..]);
+
+        bus.fill_ram(RamPattern::Ff, 0);
+        assert!(bus.ram().iter().all(|&byte| byte == 0xff));
+        assert!("ones".parse::<RamPattern>().is_err());
+    }
+}
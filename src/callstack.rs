@@ -0,0 +1,114 @@
+use crate::cpu::CPU;
+
+const JSR: u8 = 0x20;
+const RTS: u8 = 0x60;
+
+// A subroutine called with JSR that hasn't returned yet
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Call {
+    // Address of the JSR
+    pub site: u16,
+    pub target: u16,
+    // What RTS pulls from the stack to go back after the JSR
+    pub return_address: u16,
+}
+
+// Where an RTS went, compared to the calls in progress
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Return {
+    // Back after the last JSR
+    Balanced,
+    // Somewhere else: further up the call stack, or where no JSR came from
+    // (e.g. a jump table pushing its target)
+    Unbalanced { expected: Option<u16>, actual: u16 },
+}
+
+// Follows JSR and RTS to know which subroutines are running. An RTS going
+// further up than the last call ends every call in between, one going
+// somewhere unknown ends none.
+pub struct CallStack {
+    calls: Vec<Call>,
+    ended: Vec<Call>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        CallStack { calls: Vec::new(), ended: Vec::new() }
+    }
+
+    // Called before each instruction is executed. Returns where the
+    // instruction returns to if it's an RTS.
+    pub fn update(&mut self, cpu: &CPU) -> Option<Return> {
+        self.ended.clear();
+        let pc = cpu.program_counter;
+        match cpu.bus.peek(pc) {
+            JSR => {
+                let target = cpu.bus.peek(pc.wrapping_add(1)) as u16 | (cpu.bus.peek(pc.wrapping_add(2)) as u16) << 8;
+                self.calls.push(Call { site: pc, target, return_address: pc.wrapping_add(2) });
+                None
+            }
+            RTS => {
+                let sp = cpu.stack_pointer;
+                let lo = cpu.bus.peek(0x0100 | sp.wrapping_add(1) as u16) as u16;
+                let hi = cpu.bus.peek(0x0100 | sp.wrapping_add(2) as u16) as u16;
+                let actual = hi << 8 | lo;
+                let expected = self.calls.last().map(|call| call.return_address);
+                match self.calls.iter().rposition(|call| call.return_address == actual) {
+                    Some(index) => {
+                        while self.calls.len() > index {
+                            self.ended.push(self.calls.pop().unwrap());
+                        }
+                        if self.ended.len() == 1 {
+                            Some(Return::Balanced)
+                        } else {
+                            Some(Return::Unbalanced { expected, actual })
+                        }
+                    }
+                    None => Some(Return::Unbalanced { expected, actual }),
+                }
+            }
+            _ => None,
+        }
+    }
+
+    // The calls in progress, the outermost first
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    // The calls ended by the last instruction, the innermost first
+    pub fn ended(&self) -> &[Call] {
+        &self.ended
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        CallStack::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_return_further_up_ends_the_calls_in_between() {
+        let mut cpu = CPU::new(Bus::new());
+        // JSR $0604; BRK; $0604: JSR $0608; BRK; $0608: PLA; PLA; RTS
+        cpu.load(vec![0x20, 0x04, 0x06, 0x00, 0x20, 0x08, 0x06, 0x00, 0x68, 0x68, 0x60]);
+        cpu.program_counter = 0x0600;
+        let mut calls = CallStack::new();
+        loop {
+            if let Some(returned) = calls.update(&cpu) {
+                assert_eq!(returned, Return::Unbalanced { expected: Some(0x0606), actual: 0x0602 });
+                break;
+            }
+            assert!(cpu.step());
+        }
+        assert_eq!(calls.calls(), &[]);
+        let ended: Vec<u16> = calls.ended().iter().map(|call| call.target).collect();
+        assert_eq!(ended, vec![0x0608, 0x0604]);
+    }
+}
@@ -0,0 +1,310 @@
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+// Encodes an RGB24 frame (3 bytes per pixel, row after row) as a PNG file.
+pub fn save_png(path: &Path, frame: &[u8], width: u32, height: u32) -> io::Result<()> {
+    assert_eq!(frame.len(), (width * height * 3) as usize, "frame size does not match {}x{}", width, height);
+
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), width, height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(frame)?;
+    Ok(())
+}
+
+// GIF delays are expressed in hundredths of a second and most viewers clamp
+// anything below 2 to a much slower default, so frames arriving faster than
+// this are merged into the pending one.
+const GIF_MIN_DELAY: Duration = Duration::from_millis(20);
+
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    width: u16,
+    height: u16,
+    palette: Vec<(u8, u8, u8)>,
+    pending: Option<(Vec<u8>, Instant)>,
+}
+
+impl GifRecorder {
+    pub fn new(path: &Path, width: u16, height: u16, palette: &[(u8, u8, u8)]) -> io::Result<Self> {
+        let file = File::create(path)?;
+        let flat_palette: Vec<u8> = palette.iter().flat_map(|&(r, g, b)| [r, g, b]).collect();
+        let mut encoder = gif::Encoder::new(BufWriter::new(file), width, height, &flat_palette).map_err(gif_error)?;
+        encoder.set_repeat(gif::Repeat::Infinite).map_err(gif_error)?;
+
+        Ok(GifRecorder {
+            encoder,
+            width,
+            height,
+            palette: palette.to_vec(),
+            pending: None,
+        })
+    }
+
+    // Queues an RGB24 frame. A frame is only written once the next one arrives,
+    // because its delay is the time it stayed on screen.
+    pub fn add_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.add_frame_at(frame, Instant::now())
+    }
+
+    fn add_frame_at(&mut self, frame: &[u8], now: Instant) -> io::Result<()> {
+        let indexed = self.quantize(frame);
+
+        match self.pending.take() {
+            Some((_, since)) if now.duration_since(since) < GIF_MIN_DELAY => {
+                self.pending = Some((indexed, since));
+            }
+            Some((previous, since)) => {
+                self.write_frame(&previous, now.duration_since(since))?;
+                self.pending = Some((indexed, now));
+            }
+            None => {
+                self.pending = Some((indexed, now));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some((last, since)) = self.pending.take() {
+            self.write_frame(&last, since.elapsed().max(GIF_MIN_DELAY))?;
+        }
+        self.encoder.into_inner()?;
+        Ok(())
+    }
+
+    fn write_frame(&mut self, indexed: &[u8], shown_for: Duration) -> io::Result<()> {
+        let frame = gif::Frame {
+            width: self.width,
+            height: self.height,
+            delay: (shown_for.as_millis() / 10).min(u16::MAX as u128) as u16,
+            buffer: Cow::Borrowed(indexed),
+            ..gif::Frame::default()
+        };
+        self.encoder.write_frame(&frame).map_err(gif_error)
+    }
+
+    // Maps every pixel to the closest palette entry.
+    fn quantize(&self, frame: &[u8]) -> Vec<u8> {
+        frame
+            .chunks(3)
+            .map(|pixel| {
+                let distance = |&(r, g, b): &(u8, u8, u8)| {
+                    let dr = pixel[0] as i32 - r as i32;
+                    let dg = pixel[1] as i32 - g as i32;
+                    let db = pixel[2] as i32 - b as i32;
+                    dr * dr + dg * dg + db * db
+                };
+                (0..self.palette.len())
+                    .min_by_key(|&i| distance(&self.palette[i]))
+                    .unwrap_or(0) as u8
+            })
+            .collect()
+    }
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    io::Error::other(e)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VideoFormat {
+    // Headerless rgb24 frames, e.g. for `ffmpeg -f rawvideo -pix_fmt rgb24 -s 32x32 -r 60 -i <path>`
+    RawRgb,
+    // YUV4MPEG2 with 4:4:4 chroma, readable by ffmpeg and most players without extra flags
+    Y4m,
+}
+
+impl VideoFormat {
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("y4m") => VideoFormat::Y4m,
+            _ => VideoFormat::RawRgb,
+        }
+    }
+}
+
+// Writes every emulated frame to a file or named pipe. The stream is declared
+// at the emulated frame rate, so it plays at the right speed no matter how
+// fast the emulator was running while dumping.
+pub struct VideoDumper {
+    writer: BufWriter<File>,
+    format: VideoFormat,
+    // The Y, U and V planes of the last y4m frame, reused for every frame
+    planes: Vec<u8>,
+}
+
+impl VideoDumper {
+    pub fn new(path: &Path, width: u32, height: u32, fps: f64) -> io::Result<Self> {
+        let format = VideoFormat::from_path(path);
+        let mut writer = BufWriter::new(File::create(path)?);
+        if format == VideoFormat::Y4m {
+            // Frame rate as a fraction, e.g. 60.0988 -> 60098800:1000000
+            let fps_numerator = (fps * 1_000_000.0).round() as u64;
+            writeln!(writer, "YUV4MPEG2 W{} H{} F{}:1000000 Ip A1:1 C444", width, height, fps_numerator)?;
+        }
+
+        Ok(VideoDumper {
+            writer,
+            format,
+            planes: Vec::new(),
+        })
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn add_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        match self.format {
+            VideoFormat::RawRgb => self.writer.write_all(frame),
+            VideoFormat::Y4m => {
+                let pixels = frame.len() / 3;
+                self.planes.resize(pixels * 3, 0);
+                for (i, p) in frame.chunks_exact(3).enumerate() {
+                    let (y, u, v) = rgb_to_yuv(p[0], p[1], p[2]);
+                    self.planes[i] = y;
+                    self.planes[pixels + i] = u;
+                    self.planes[2 * pixels + i] = v;
+                }
+                self.writer.write_all(b"FRAME\n")?;
+                self.writer.write_all(&self.planes)
+            }
+        }
+    }
+}
+
+// BT.601 limited range, which is what y4m consumers assume by default.
+fn rgb_to_yuv(r: u8, g: u8, b: u8) -> (u8, u8, u8) {
+    let (r, g, b) = (r as i32, g as i32, b as i32);
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let u = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let v = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, u as u8, v as u8)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    // A file in the temporary directory, removed when the test is done with it
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str) -> Self {
+            TempFile(std::env::temp_dir().join(format!("enes_{}_{}", std::process::id(), name)))
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    #[test]
+    fn test_save_png_round_trip() {
+        let file = TempFile::new("screenshot.png");
+        let frame = [255, 0, 0, 0, 255, 0, 0, 0, 255, 10, 20, 30, 40, 50, 60, 70, 80, 90];
+        save_png(&file.0, &frame, 3, 2).unwrap();
+
+        let decoder = png::Decoder::new(File::open(&file.0).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut decoded = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut decoded).unwrap();
+        assert_eq!((info.width, info.height), (3, 2));
+        assert_eq!((info.color_type, info.bit_depth), (png::ColorType::Rgb, png::BitDepth::Eight));
+        assert_eq!(&decoded[..info.buffer_size()], &frame[..]);
+    }
+
+    // Frames of the GIF at `path` as their delay and palette indices
+    fn read_gif(path: &Path) -> Vec<(u16, Vec<u8>)> {
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(File::open(path).unwrap()).unwrap();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            frames.push((frame.delay, frame.buffer.to_vec()));
+        }
+        frames
+    }
+
+    #[test]
+    fn test_gif_quantizes_to_the_closest_colour() {
+        let file = TempFile::new("quantize.gif");
+        let palette = [(0, 0, 0), (255, 255, 255), (200, 0, 0), (0, 0, 200)];
+        let recorder = GifRecorder::new(&file.0, 2, 2, &palette).unwrap();
+        let frame = [10, 10, 10, 250, 240, 255, 180, 30, 20, 0, 40, 160];
+        assert_eq!(recorder.quantize(&frame), [0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_gif_merges_frames_under_the_minimum_delay() {
+        let file = TempFile::new("delay.gif");
+        let palette = [(0, 0, 0), (255, 255, 255)];
+        let black = [0; 2 * 2 * 3];
+        let white = [255; 2 * 2 * 3];
+        let mut recorder = GifRecorder::new(&file.0, 2, 2, &palette).unwrap();
+
+        let start = Instant::now();
+        let at = |millis| start + Duration::from_millis(millis);
+        recorder.add_frame_at(&black, at(0)).unwrap();
+        // Too soon after the black one, it takes its place
+        recorder.add_frame_at(&white, at(5)).unwrap();
+        recorder.add_frame_at(&black, at(50)).unwrap();
+        recorder.add_frame_at(&white, at(80)).unwrap();
+        recorder.finish().unwrap();
+
+        let frames = read_gif(&file.0);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], (5, vec![1; 4]));
+        assert_eq!(frames[1], (3, vec![0; 4]));
+        // The last one lasts at least the minimum delay
+        assert!(frames[2].0 >= 2);
+        assert_eq!(frames[2].1, vec![1; 4]);
+    }
+
+    #[test]
+    fn test_y4m_header_and_repeated_frames() {
+        let file = TempFile::new("dump.y4m");
+        let mut video = VideoDumper::new(&file.0, 2, 1, 60.0988).unwrap();
+        // The frontend writes the last screen again when the game hasn't
+        // drawn, so the stream keeps up with the emulated frame rate
+        let screen = [255, 255, 255, 0, 0, 0];
+        for _ in 0..3 {
+            video.add_frame(&screen).unwrap();
+        }
+        video.finish().unwrap();
+
+        let data = std::fs::read(&file.0).unwrap();
+        let header = b"YUV4MPEG2 W2 H1 F60098800:1000000 Ip A1:1 C444\n";
+        assert_eq!(&data[..header.len()], header);
+        let frame = [&b"FRAME\n"[..], &[235, 16, 128, 128, 128, 128]].concat();
+        assert_eq!(&data[header.len()..], &frame.repeat(3)[..]);
+    }
+
+    #[test]
+    fn test_raw_video_has_no_header() {
+        let file = TempFile::new("dump.rgb");
+        let mut video = VideoDumper::new(&file.0, 2, 1, 60.0).unwrap();
+        let screen = [1, 2, 3, 4, 5, 6];
+        video.add_frame(&screen).unwrap();
+        video.add_frame(&screen).unwrap();
+        video.finish().unwrap();
+        assert_eq!(std::fs::read(&file.0).unwrap(), screen.repeat(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "frame size does not match 3x3")]
+    fn test_save_png_checks_the_frame_size() {
+        let file = TempFile::new("wrong_size.png");
+        let _ = save_png(&file.0, &[0; 18], 3, 3);
+    }
+}
@@ -0,0 +1,312 @@
+use std::str::FromStr;
+
+// The letters of Game Genie codes, by the 4 bit value they stand for
+const GAME_GENIE_LETTERS: &str = "APZLGITYEOXUKSVN";
+
+// A Game Genie code replaces the value read from one ROM address, optionally
+// only when the ROM holds the `compare` value there (8 letter codes), so that
+// the right bank is patched on mappers that switch them.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GameGenie {
+    pub address: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenie {
+    pub fn parse(code: &str) -> Result<GameGenie, String> {
+        let n = code
+            .chars()
+            .map(|letter| GAME_GENIE_LETTERS.find(letter.to_ascii_uppercase()).map(|value| value as u16))
+            .collect::<Option<Vec<u16>>>()
+            .ok_or(format!("'{}' is not a Game Genie code", code))?;
+        if n.len() != 6 && n.len() != 8 {
+            return Err(format!("Game Genie codes have 6 or 8 letters, '{}' has {}", code, n.len()));
+        }
+
+        // The bits of the address and the values are shuffled across the letters
+        let address = 0x8000
+            | ((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8);
+        let (last, compare) = if n.len() == 6 {
+            (n[5], None)
+        } else {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            (n[7], Some(compare as u8))
+        };
+        let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7) | (last & 8);
+
+        Ok(GameGenie {
+            address,
+            value: value as u8,
+            compare,
+        })
+    }
+}
+
+// A raw "address:value" code, e.g. 075a:09, writes the value to RAM every
+// frame so that it stays frozen whatever the game does with it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Freeze {
+    pub address: u16,
+    pub value: u8,
+}
+
+impl Freeze {
+    pub fn parse(code: &str) -> Result<Freeze, String> {
+        let (address, value) = code.split_once(':').ok_or(format!("'{}' is not an address:value code", code))?;
+        let address = u16::from_str_radix(address, 16).map_err(|_| format!("Invalid address in '{}'", code))?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| format!("Invalid value in '{}'", code))?;
+        if address > 0x1fff {
+            return Err(format!("{:04X} is not a RAM address", address));
+        }
+        Ok(Freeze { address, value })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Patch {
+    GameGenie(GameGenie),
+    Freeze(Freeze),
+}
+
+#[derive(Debug, Clone)]
+pub struct Cheat {
+    // As the user typed it
+    pub code: String,
+    pub enabled: bool,
+    patch: Patch,
+}
+
+// The cheats of the running game. The bus applies the Game Genie codes to
+// every read and writes the frozen values when apply_freezes() is called.
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    list: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn new() -> Self {
+        Cheats { list: Vec::new() }
+    }
+
+    // Adds an enabled cheat, either a Game Genie or an address:value code,
+    // and returns its index
+    pub fn add(&mut self, code: &str) -> Result<usize, String> {
+        let patch = if code.contains(':') {
+            Patch::Freeze(Freeze::parse(code)?)
+        } else {
+            Patch::GameGenie(GameGenie::parse(code)?)
+        };
+        self.list.push(Cheat {
+            code: code.to_uppercase(),
+            enabled: true,
+            patch,
+        });
+        Ok(self.list.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    // Returns false if there is no cheat at `index`
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.list.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.list
+    }
+
+    // The value a read of `addr` returns once the cheats are applied to the
+    // `value` found in memory
+    pub fn read(&self, addr: u16, value: u8) -> u8 {
+        for cheat in self.list.iter().filter(|cheat| cheat.enabled) {
+            if let Patch::GameGenie(patch) = &cheat.patch {
+                if patch.address == addr && patch.compare.is_none_or(|compare| compare == value) {
+                    return patch.value;
+                }
+            }
+        }
+        value
+    }
+
+    // The RAM values to write again every frame
+    pub fn freezes(&self) -> impl Iterator<Item = Freeze> + '_ {
+        self.list.iter().filter(|cheat| cheat.enabled).filter_map(|cheat| match cheat.patch {
+            Patch::Freeze(freeze) => Some(freeze),
+            Patch::GameGenie(_) => None,
+        })
+    }
+}
+
+// How the value of an address must have changed since the last search step
+// to stay a candidate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Comparison {
+    Equal(u8),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    IncreasedBy(u8),
+    DecreasedBy(u8),
+}
+
+impl Comparison {
+    fn matches(self, previous: u8, value: u8) -> bool {
+        match self {
+            Comparison::Equal(expected) => value == expected,
+            Comparison::Changed => value != previous,
+            Comparison::Unchanged => value == previous,
+            Comparison::Increased => value > previous,
+            Comparison::Decreased => value < previous,
+            Comparison::IncreasedBy(n) => value == previous.wrapping_add(n),
+            Comparison::DecreasedBy(n) => value == previous.wrapping_sub(n),
+        }
+    }
+}
+
+impl FromStr for Comparison {
+    type Err = String;
+
+    // "=05", "changed", "unchanged", "inc", "dec", "+1" or "-1", numbers in hex
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let number = |digits: &str| u8::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex byte", digits));
+        match text {
+            "changed" => return Ok(Comparison::Changed),
+            "unchanged" => return Ok(Comparison::Unchanged),
+            "inc" => return Ok(Comparison::Increased),
+            "dec" => return Ok(Comparison::Decreased),
+            _ => {}
+        }
+        if let Some(digits) = text.strip_prefix('=') {
+            Ok(Comparison::Equal(number(digits)?))
+        } else if let Some(digits) = text.strip_prefix('+') {
+            Ok(Comparison::IncreasedBy(number(digits)?))
+        } else if let Some(digits) = text.strip_prefix('-') {
+            Ok(Comparison::DecreasedBy(number(digits)?))
+        } else {
+            Err(format!("Unknown comparison '{}'", text))
+        }
+    }
+}
+
+// Finds where a game keeps a value (lives, health...) by taking snapshots of
+// the RAM while playing and keeping the addresses that changed as expected
+// between them
+pub struct CheatSearch {
+    previous: Vec<u8>,
+    candidates: Vec<u16>,
+}
+
+impl CheatSearch {
+    // Every address of `ram` is a candidate at first
+    pub fn new(ram: &[u8]) -> Self {
+        CheatSearch {
+            previous: ram.to_vec(),
+            candidates: (0..ram.len() as u16).collect(),
+        }
+    }
+
+    // Keeps the candidates whose value compares with the last snapshot, and
+    // takes a new one. Returns the number of candidates left.
+    pub fn filter(&mut self, ram: &[u8], comparison: Comparison) -> usize {
+        let previous = &self.previous;
+        self.candidates
+            .retain(|&address| comparison.matches(previous[address as usize], ram[address as usize]));
+        self.previous = ram.to_vec();
+        self.candidates.len()
+    }
+
+    // The remaining addresses with their value at the last snapshot
+    pub fn candidates(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.candidates.iter().map(move |&address| (address, self.previous[address as usize]))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_game_genie_codes() {
+        // Infinite lives in Super Mario Bros.
+        assert_eq!(GameGenie::parse("SXIOPO"), Ok(GameGenie { address: 0x91d9, value: 0xad, compare: None }));
+        assert_eq!(GameGenie::parse("sxiopo"), GameGenie::parse("SXIOPO"));
+        assert_eq!(GameGenie::parse("PAAAAAZA"), Ok(GameGenie { address: 0x8000, value: 0x01, compare: Some(0x02) }));
+        assert!(GameGenie::parse("SXIOP").is_err());
+        assert!(GameGenie::parse("SXIOPB").is_err());
+    }
+
+    #[test]
+    fn test_read_applies_enabled_cheats() {
+        let mut cheats = Cheats::new();
+        assert_eq!(cheats.add("SXIOPO"), Ok(0));
+        assert_eq!(cheats.add("PAAAAAZA"), Ok(1));
+
+        assert_eq!(cheats.read(0x91d9, 0x00), 0xad);
+        assert_eq!(cheats.read(0x91da, 0x00), 0x00);
+        // 8 letter codes only patch when the compare value matches
+        assert_eq!(cheats.read(0x8000, 0x02), 0x01);
+        assert_eq!(cheats.read(0x8000, 0x03), 0x03);
+
+        assert!(cheats.set_enabled(0, false));
+        assert_eq!(cheats.read(0x91d9, 0x00), 0x00);
+        assert!(!cheats.set_enabled(2, false));
+        assert_eq!(cheats.remove(0).map(|cheat| cheat.code), Some("SXIOPO".to_string()));
+        assert_eq!(cheats.list().len(), 1);
+    }
+
+    #[test]
+    fn test_raw_codes_freeze_ram() {
+        let mut cheats = Cheats::new();
+        assert_eq!(cheats.add("075a:09"), Ok(0));
+        assert_eq!(cheats.add("SXIOPO"), Ok(1));
+        assert!(cheats.add("8000:01").is_err());
+        assert!(cheats.add("075a:100").is_err());
+
+        assert_eq!(cheats.freezes().collect::<Vec<_>>(), vec![Freeze { address: 0x075a, value: 0x09 }]);
+        assert_eq!(cheats.read(0x075a, 0x03), 0x03);
+        cheats.set_enabled(0, false);
+        assert_eq!(cheats.freezes().count(), 0);
+    }
+
+    #[test]
+    fn test_search_narrows_down_candidates() {
+        let mut ram = vec![0u8; 2048];
+        ram[0x10] = 3;
+        ram[0x20] = 3;
+        let mut search = CheatSearch::new(&ram);
+
+        // A life is lost
+        ram[0x10] = 2;
+        ram[0x20] = 2;
+        ram[0x30] = 9;
+        assert_eq!(search.filter(&ram, "-1".parse().unwrap()), 2);
+
+        ram[0x20] = 7;
+        assert_eq!(search.filter(&ram, Comparison::Unchanged), 1);
+        assert_eq!(search.candidates().collect::<Vec<_>>(), vec![(0x10, 2)]);
+
+        assert_eq!("=0a".parse(), Ok(Comparison::Equal(0x0a)));
+        assert_eq!("inc".parse(), Ok(Comparison::Increased));
+        assert!("~1".parse::<Comparison>().is_err());
+    }
+}
@@ -0,0 +1,566 @@
+use crate::display::DisplaySettings;
+use crate::pacing::{PacingMode, SpeedControl};
+use clap::{Parser, Subcommand, ValueEnum};
+use enes::bus::RamPattern;
+use enes::filter::Filter;
+use enes::region::Region;
+use enes::trace::{self, TraceField};
+use sdl2::keyboard::Keycode;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Settings are read from ~/.config/enes/config.toml (or $XDG_CONFIG_HOME/enes/config.toml)
+// and any option given on the command line wins over the file:
+//
+//   scale = 10
+//   integer_scaling = true
+//   aspect_correction = false
+//   fullscreen = false
+//   input_display = false
+//   show_metrics = false
+//   filters = ["scanlines", "glow", "curvature"]
+//   pacing = "limiter"
+//   region = "ntsc"
+//   ram_pattern = "zero"
+//   fps = 60.0
+//   fast_forward_speed = 4.0
+//   slow_motion_speed = 0.5
+//   state_dir = "/home/me/.local/share/enes/states"
+//   state_compression = 3
+//   crash_dir = "/home/me/.local/share/enes/crashes"
+//   resume = "ask"
+//   input_profile = "arrows"
+//
+//   [input_profiles.arrows]
+//   up = "Up"
+//   down = "Down"
+//   left = "Left"
+//   right = "Right"
+//
+// Key names are the SDL ones (https://wiki.libsdl.org/SDL2/SDL_Keycode).
+//
+// While the game runs, changes to the file are picked up within a second for
+// the display settings, filters, input profile, frame rate and speeds.
+
+#[derive(Parser, Debug)]
+#[command(name = "enes", about = "Educational NES emulator", args_conflicts_with_subcommands = true)]
+struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Raw 6502 program loaded at $0600 (defaults to the built-in snake game)
+    rom: Option<PathBuf>,
+
+    /// Window scale factor
+    #[arg(long)]
+    scale: Option<u32>,
+
+    /// Allow non-integer scaling when the window size is not a multiple of the screen
+    #[arg(long)]
+    smooth_scaling: bool,
+
+    /// Stretch pixels to the 8:7 aspect ratio of a TV
+    #[arg(long)]
+    aspect_correction: bool,
+
+    /// Start in borderless fullscreen
+    #[arg(long)]
+    fullscreen: bool,
+
+    /// Show the pressed direction on screen, e.g. for streaming
+    #[arg(long)]
+    input_display: bool,
+
+    /// Show the frame rate, speed and frame timings in the window title
+    #[arg(long)]
+    show_metrics: bool,
+
+    /// Post-processing filter, can be given several times
+    #[arg(long = "filter", value_enum)]
+    filters: Vec<Filter>,
+
+    /// How emulation speed is kept in check
+    #[arg(long, value_enum)]
+    pacing: Option<PacingMode>,
+
+    /// TV system to emulate: ntsc, pal or dendy
+    #[arg(long)]
+    region: Option<Region>,
+
+    /// RAM contents at power on: zero, ff, alternate (00/ff pages) or random (from the seed)
+    #[arg(long)]
+    ram_pattern: Option<RamPattern>,
+
+    /// Emulated frames per second (defaults to the frame rate of the region)
+    #[arg(long)]
+    fps: Option<f64>,
+
+    /// Whether to continue the last session of the game
+    #[arg(long, value_enum)]
+    resume: Option<ResumeMode>,
+
+    /// Name of the input profile to use
+    #[arg(long)]
+    input_profile: Option<String>,
+
+    /// Config file to read instead of the default one
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// Start the command line debugger instead of the game window
+    #[arg(long)]
+    debug: bool,
+
+    /// Start the terminal debugger, with live disassembly, registers, stack and watches
+    #[arg(long)]
+    tui: bool,
+
+    /// Label file shown in the debuggers and the trace (Mesen .mlb or cc65 .dbg)
+    #[arg(long)]
+    labels: Option<PathBuf>,
+
+    /// Log every executed instruction to a file, or to stdout with '-'
+    #[arg(long)]
+    trace: Option<PathBuf>,
+
+    /// Fields of the trace: disassembly, registers, cycles (all by default)
+    #[arg(long, value_delimiter = ',')]
+    trace_fields: Vec<TraceField>,
+
+    /// Only trace instructions in this hex address range, e.g. 0600-06ff, can be given several times
+    #[arg(long = "trace-range", value_parser = trace::parse_range)]
+    trace_ranges: Vec<RangeInclusive<u16>>,
+
+    /// Write the cycles spent in each subroutine to a file when the emulator exits
+    #[arg(long)]
+    profile: Option<PathBuf>,
+
+    /// Write how often each address was executed to a file when the emulator exits (.png for an image, CSV otherwise)
+    #[arg(long)]
+    heatmap: Option<PathBuf>,
+
+    /// Record the keys pressed to a file, to replay the session later with --replay
+    #[arg(long)]
+    record_input: Option<PathBuf>,
+
+    /// Replay a session recorded with --record-input, keys are ignored
+    #[arg(long, conflicts_with = "record_input")]
+    replay: Option<PathBuf>,
+
+    /// Seed of the random numbers given to the game (random by default)
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Write how often each opcode and addressing mode ran, as CSV, when the emulator exits
+    #[arg(long)]
+    opcode_stats: Option<PathBuf>,
+
+    /// Count reads, writes and executions of every address and write a report of the unused memory when the emulator exits (.csv for the counts)
+    #[arg(long)]
+    coverage: Option<PathBuf>,
+
+    /// Report stack pointer wraps and RTS that don't return after their JSR, with the calls that led there
+    #[arg(long)]
+    stack_check: bool,
+
+    /// Print each place the program runs an unofficial opcode, once, with its disassembly
+    #[arg(long)]
+    log_unofficial: bool,
+
+    /// Run without a window, comparing every instruction with a reference trace (nestest or Mesen format)
+    #[arg(long)]
+    diff_trace: Option<PathBuf>,
+
+    /// Game Genie code to apply, can be given several times
+    #[arg(long = "game-genie")]
+    game_genie: Vec<String>,
+
+    /// Keep a RAM address at a value, as address:value in hex (e.g. 0010:05), can be given several times
+    #[arg(long = "freeze")]
+    freezes: Vec<String>,
+
+    /// Dump video to a file or pipe (.y4m for YUV4MPEG2, raw rgb24 otherwise)
+    #[arg(long)]
+    dump_video: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Print the header, checksums and problems of a ROM file, to tell why it doesn't load
+    RomInfo { rom: PathBuf },
+    /// Disassemble a program statically into a ca65 source, following the code from $0600
+    Disasm {
+        rom: PathBuf,
+        /// Code/Data Logger file (FCEUX or Mesen) telling code reached by computed jumps from data
+        #[arg(long)]
+        cdl: Option<PathBuf>,
+        /// Mesen .mlb or cc65 .dbg file naming the labels
+        #[arg(long)]
+        labels: Option<PathBuf>,
+        /// Where to write the listing (defaults to the standard output)
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    scale: Option<u32>,
+    integer_scaling: Option<bool>,
+    aspect_correction: Option<bool>,
+    fullscreen: Option<bool>,
+    input_display: Option<bool>,
+    show_metrics: Option<bool>,
+    filters: Option<Vec<Filter>>,
+    pacing: Option<PacingMode>,
+    region: Option<Region>,
+    ram_pattern: Option<RamPattern>,
+    fps: Option<f64>,
+    fast_forward_speed: Option<f64>,
+    slow_motion_speed: Option<f64>,
+    state_dir: Option<PathBuf>,
+    state_compression: Option<i32>,
+    crash_dir: Option<PathBuf>,
+    resume: Option<ResumeMode>,
+    input_profile: Option<String>,
+    input_profiles: HashMap<String, KeyNames>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct KeyNames {
+    up: String,
+    down: String,
+    left: String,
+    right: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ResumeMode {
+    Ask,
+    Always,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputProfile {
+    pub up: Keycode,
+    pub down: Keycode,
+    pub left: Keycode,
+    pub right: Keycode,
+}
+
+impl InputProfile {
+    fn wasd() -> Self {
+        InputProfile {
+            up: Keycode::W,
+            down: Keycode::S,
+            left: Keycode::A,
+            right: Keycode::D,
+        }
+    }
+
+    fn from_names(names: &KeyNames) -> Result<Self, String> {
+        let key = |name: &str| Keycode::from_name(name).ok_or(format!("Unknown key name '{}'", name));
+        Ok(InputProfile {
+            up: key(&names.up)?,
+            down: key(&names.down)?,
+            left: key(&names.left)?,
+            right: key(&names.right)?,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Config {
+    // The config file read, or the default one that would be
+    pub path: Option<PathBuf>,
+    pub command: Option<Command>,
+    pub rom: Option<PathBuf>,
+    pub scale: u32,
+    pub display: DisplaySettings,
+    pub filters: Vec<Filter>,
+    pub pacing: PacingMode,
+    pub fps: f64,
+    pub ram_pattern: RamPattern,
+    pub speed: SpeedControl,
+    pub state_dir: PathBuf,
+    pub state_compression: i32,
+    // Where crash reports are written when emulation fails
+    pub crash_dir: PathBuf,
+    pub resume: ResumeMode,
+    pub input: InputProfile,
+    pub dump_video: Option<PathBuf>,
+    pub show_metrics: bool,
+    pub debug: bool,
+    pub tui: bool,
+    pub labels: Option<PathBuf>,
+    pub trace: Option<TraceSettings>,
+    pub profile: Option<PathBuf>,
+    pub heatmap: Option<PathBuf>,
+    pub record_input: Option<PathBuf>,
+    pub replay: Option<PathBuf>,
+    pub seed: Option<u64>,
+    pub diff_trace: Option<PathBuf>,
+    pub opcode_stats: Option<PathBuf>,
+    pub stack_check: bool,
+    pub log_unofficial: bool,
+    pub coverage: Option<PathBuf>,
+    pub game_genie: Vec<String>,
+    pub freezes: Vec<String>,
+}
+
+#[derive(Debug)]
+pub struct TraceSettings {
+    pub path: PathBuf,
+    pub fields: Vec<TraceField>,
+    pub ranges: Vec<RangeInclusive<u16>>,
+}
+
+impl Config {
+    pub fn load() -> Result<Config, String> {
+        let args = Args::parse();
+
+        let path = args.config.clone().or_else(default_config_path);
+        let file = match &path {
+            Some(path) if path.exists() || args.config.is_some() => read_config_file(path)?,
+            _ => ConfigFile::default(),
+        };
+        Ok(Config {
+            path,
+            ..Config::from_parts(args, file)?
+        })
+    }
+
+    // The settings of the command line, falling back to the ones of the
+    // config file and then to the defaults
+    fn from_parts(args: Args, file: ConfigFile) -> Result<Config, String> {
+        let scale = args.scale.or(file.scale).unwrap_or(10);
+        if scale == 0 {
+            return Err("Scale must be at least 1".to_string());
+        }
+
+        let display = DisplaySettings {
+            integer_scaling: !args.smooth_scaling && file.integer_scaling.unwrap_or(true),
+            aspect_correction: args.aspect_correction || file.aspect_correction.unwrap_or(false),
+            fullscreen: args.fullscreen || file.fullscreen.unwrap_or(false),
+            input_display: args.input_display || file.input_display.unwrap_or(false),
+        };
+
+        let filters = if args.filters.is_empty() {
+            file.filters.unwrap_or_default()
+        } else {
+            args.filters
+        };
+
+        let pacing = args.pacing.or(file.pacing).unwrap_or(PacingMode::Limiter);
+        let region = args.region.or(file.region).unwrap_or(Region::Ntsc);
+        let fps = args.fps.or(file.fps).unwrap_or_else(|| region.frame_rate());
+        if fps.is_nan() || fps <= 0.0 {
+            return Err("Frame rate must be positive".to_string());
+        }
+
+        let speed = SpeedControl {
+            fast_forward: false,
+            slow_motion: false,
+            fast_forward_speed: file.fast_forward_speed.unwrap_or(4.0),
+            slow_motion_speed: file.slow_motion_speed.unwrap_or(0.5),
+        };
+        if !(speed.fast_forward_speed > 0.0 && speed.slow_motion_speed > 0.0) {
+            return Err("Fast-forward and slow motion speeds must be positive".to_string());
+        }
+
+        let state_dir = match file.state_dir {
+            Some(dir) => dir,
+            None => default_data_dir().ok_or("Set state_dir in the config file, $HOME is not defined")?.join("states"),
+        };
+
+        // zstd level of the saved states, 0 to store them uncompressed
+        let state_compression = file.state_compression.unwrap_or(3);
+        if !(0..=22).contains(&state_compression) {
+            return Err("State compression must be between 0 and 22".to_string());
+        }
+
+        let crash_dir = match file.crash_dir {
+            Some(dir) => dir,
+            None => default_data_dir().ok_or("Set crash_dir in the config file, $HOME is not defined")?.join("crashes"),
+        };
+
+        let resume = args.resume.or(file.resume).unwrap_or(ResumeMode::Ask);
+
+        let input = match args.input_profile.as_ref().or(file.input_profile.as_ref()) {
+            None => InputProfile::wasd(),
+            Some(name) => match file.input_profiles.get(name) {
+                Some(names) => InputProfile::from_names(names)?,
+                None if name == "wasd" => InputProfile::wasd(),
+                None => return Err(format!("Input profile '{}' is not defined", name)),
+            },
+        };
+
+        let fields = if args.trace_fields.is_empty() {
+            vec![TraceField::Disassembly, TraceField::Registers, TraceField::Cycles]
+        } else {
+            args.trace_fields
+        };
+        let trace = match args.trace {
+            Some(path) => Some(TraceSettings {
+                path,
+                fields,
+                ranges: args.trace_ranges,
+            }),
+            None => None,
+        };
+
+        Ok(Config {
+            path: None,
+            command: args.command,
+            rom: args.rom,
+            scale,
+            display,
+            filters,
+            pacing,
+            fps,
+            ram_pattern: args.ram_pattern.or(file.ram_pattern).unwrap_or(RamPattern::Zero),
+            speed,
+            state_dir,
+            state_compression,
+            crash_dir,
+            resume,
+            input,
+            dump_video: args.dump_video,
+            show_metrics: args.show_metrics || file.show_metrics.unwrap_or(false),
+            debug: args.debug,
+            tui: args.tui,
+            labels: args.labels,
+            trace,
+            profile: args.profile,
+            heatmap: args.heatmap,
+            record_input: args.record_input,
+            replay: args.replay,
+            seed: args.seed,
+            diff_trace: args.diff_trace,
+            opcode_stats: args.opcode_stats,
+            stack_check: args.stack_check,
+            log_unofficial: args.log_unofficial,
+            coverage: args.coverage,
+            game_genie: args.game_genie,
+            freezes: args.freezes,
+        })
+    }
+}
+
+// Tells when the config file was created, changed or removed since the last
+// check
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: PathBuf) -> Self {
+        let modified = modified(&path);
+        ConfigWatcher { path, modified }
+    }
+
+    pub fn changed(&mut self) -> bool {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return false;
+        }
+        self.modified = modified;
+        true
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+fn default_config_path() -> Option<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_home.join("enes").join("config.toml"))
+}
+
+fn default_data_dir() -> Option<PathBuf> {
+    let data_home = match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(std::env::var_os("HOME")?).join(".local").join("share"),
+    };
+    Some(data_home.join("enes"))
+}
+
+fn read_config_file(path: &Path) -> Result<ConfigFile, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("Invalid config {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Directories set so that the tests don't depend on $HOME
+    fn parse(args: &[&str], file: &str) -> Result<Config, String> {
+        let args = Args::try_parse_from(std::iter::once("enes").chain(args.iter().copied())).unwrap();
+        let file = format!("state_dir = \"states\"\ncrash_dir = \"crashes\"\n{}", file);
+        Config::from_parts(args, toml::from_str(&file).unwrap())
+    }
+
+    #[test]
+    fn test_command_line_wins_over_the_file() {
+        let file = "scale = 2\nfps = 30.0\npacing = \"vsync\"\nfilters = [\"glow\"]\nregion = \"pal\"\n";
+        let config = parse(&["--scale", "3", "--fps", "50", "--filter", "scanlines"], file).unwrap();
+        assert_eq!((config.scale, config.fps), (3, 50.0));
+        assert_eq!(config.filters, [Filter::Scanlines]);
+        // Settings missing from the command line come from the file
+        assert_eq!(config.pacing, PacingMode::Vsync);
+
+        let config = parse(&[], "region = \"pal\"\n").unwrap();
+        assert_eq!(config.fps, Region::Pal.frame_rate());
+        assert_eq!(config.scale, 10);
+    }
+
+    #[test]
+    fn test_scale_must_be_positive() {
+        assert_eq!(parse(&["--scale", "0"], "").unwrap_err(), "Scale must be at least 1");
+        assert_eq!(parse(&[], "scale = 0\n").unwrap_err(), "Scale must be at least 1");
+    }
+
+    #[test]
+    fn test_fps_must_be_positive() {
+        for fps in ["--fps=0", "--fps=-60", "--fps=NaN"] {
+            assert_eq!(parse(&[fps], "").unwrap_err(), "Frame rate must be positive", "{}", fps);
+        }
+        assert_eq!(parse(&[], "fps = nan\n").unwrap_err(), "Frame rate must be positive");
+    }
+
+    #[test]
+    fn test_state_compression_range() {
+        for level in [-1, 23] {
+            let file = format!("state_compression = {}\n", level);
+            assert_eq!(parse(&[], &file).unwrap_err(), "State compression must be between 0 and 22");
+        }
+        assert_eq!(parse(&[], "state_compression = 0\n").unwrap().state_compression, 0);
+        assert_eq!(parse(&[], "state_compression = 22\n").unwrap().state_compression, 22);
+    }
+
+    #[test]
+    fn test_input_profiles() {
+        assert_eq!(
+            parse(&["--input-profile", "arrows"], "").unwrap_err(),
+            "Input profile 'arrows' is not defined"
+        );
+        assert_eq!(parse(&[], "input_profile = \"arrows\"\n").unwrap_err(), "Input profile 'arrows' is not defined");
+        // wasd is always there, even without a definition
+        assert_eq!(parse(&["--input-profile", "wasd"], "").unwrap().input, InputProfile::wasd());
+        assert_eq!(parse(&[], "").unwrap().input, InputProfile::wasd());
+    }
+}
@@ -0,0 +1,210 @@
+use crate::cpu::{AddressingMode, CPU};
+use crate::opcodes;
+use std::ops::Range;
+
+const STACK: u16 = 0x0100;
+const RAM_SIZE: u16 = 0x0800;
+
+// How an instruction uses the memory at its operand address
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+fn access(mnemonic: &str) -> Option<Access> {
+    match mnemonic {
+        "ADC" | "AND" | "BIT" | "CMP" | "CPX" | "CPY" | "EOR" | "LDA" | "LDX" | "LDY" | "ORA" | "SBC" | "LAX"
+        | "LAS" => Some(Access::Read),
+        "STA" | "STX" | "STY" | "SAX" | "AHX" | "TAS" | "SHX" | "SHY" => Some(Access::Write),
+        "ASL" | "DEC" | "INC" | "LSR" | "ROL" | "ROR" | "SLO" | "RLA" | "SRE" | "RRA" | "DCP" | "ISC" => {
+            Some(Access::ReadWrite)
+        }
+        _ => None,
+    }
+}
+
+// The address an instruction at the program counter works on, read without
+// side effects. None for implied, accumulator and immediate operands.
+pub fn operand_address(cpu: &CPU, mode: &AddressingMode) -> Option<u16> {
+    let bus = &cpu.bus;
+    let operand = cpu.program_counter.wrapping_add(1);
+    let byte = bus.peek(operand);
+    let word = || byte as u16 | (bus.peek(operand.wrapping_add(1)) as u16) << 8;
+    let pointer = |at: u8| bus.peek(at as u16) as u16 | (bus.peek(at.wrapping_add(1) as u16) as u16) << 8;
+    match mode {
+        AddressingMode::ZeroPage => Some(byte as u16),
+        AddressingMode::ZeroPage_X => Some(byte.wrapping_add(cpu.register_x) as u16),
+        AddressingMode::ZeroPage_Y => Some(byte.wrapping_add(cpu.register_y) as u16),
+        AddressingMode::Absolute => Some(word()),
+        AddressingMode::Absolute_X => Some(word().wrapping_add(cpu.register_x as u16)),
+        AddressingMode::Absolute_Y => Some(word().wrapping_add(cpu.register_y as u16)),
+        AddressingMode::Indirect_X => Some(pointer(byte.wrapping_add(cpu.register_x))),
+        AddressingMode::Indirect_Y => Some(pointer(byte).wrapping_add(cpu.register_y as u16)),
+        AddressingMode::Immediate | AddressingMode::NoneAddressing => None,
+    }
+}
+
+// Reads, writes and executions of every address of the CPU address space.
+// Data accesses are those of the instruction's operand and of the stack;
+// executing counts every byte of the instruction.
+pub struct Coverage {
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+    executes: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Coverage {
+            reads: vec![0; 0x10000],
+            writes: vec![0; 0x10000],
+            executes: vec![0; 0x10000],
+        }
+    }
+
+    // Called before each instruction is executed
+    pub fn record(&mut self, cpu: &CPU) {
+        let pc = cpu.program_counter;
+        let opcode = match opcodes::OPCODES_MAP.get(&cpu.bus.peek(pc)) {
+            Some(opcode) => opcode,
+            None => return,
+        };
+        for i in 0..opcode.len as u16 {
+            self.executes[pc.wrapping_add(i) as usize] += 1;
+        }
+
+        if let (Some(access), Some(address)) = (access(opcode.mnemonic), operand_address(cpu, &opcode.mode)) {
+            if access != Access::Write {
+                self.reads[address as usize] += 1;
+            }
+            if access != Access::Read {
+                self.writes[address as usize] += 1;
+            }
+        }
+
+        let sp = cpu.stack_pointer;
+        let (pushes, pulls) = opcode.stack_effect();
+        for i in 0..pushes {
+            self.writes[(STACK | sp.wrapping_sub(i) as u16) as usize] += 1;
+        }
+        for i in 1..=pulls {
+            self.reads[(STACK | sp.wrapping_add(i) as u16) as usize] += 1;
+        }
+    }
+
+    pub fn reads(&self, address: u16) -> u64 {
+        self.reads[address as usize]
+    }
+
+    pub fn writes(&self, address: u16) -> u64 {
+        self.writes[address as usize]
+    }
+
+    pub fn executes(&self, address: u16) -> u64 {
+        self.executes[address as usize]
+    }
+
+    // "address,reads,writes,executes" lines for the addresses used at all
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,reads,writes,executes\n");
+        for address in 0..=0xffff_u16 {
+            let (reads, writes, executes) = (self.reads(address), self.writes(address), self.executes(address));
+            if reads + writes + executes > 0 {
+                csv += &format!("{:04X},{},{},{}\n", address, reads, writes, executes);
+            }
+        }
+        csv
+    }
+
+    // Summary of the RAM that was never touched and of the bytes of the
+    // program, loaded at `program`, that never ran
+    pub fn report(&self, program: Range<u16>) -> String {
+        let untouched = |address: u16| {
+            self.reads(address) + self.writes(address) + self.executes(address) == 0 && !program.contains(&address)
+        };
+        let never_run = |address: u16| self.executes(address) == 0;
+
+        let ram = ranges(0..RAM_SIZE, untouched);
+        let size: usize = ram.iter().map(|range| range.len()).sum();
+        let mut report = format!("RAM never read, written or executed: {} of {} bytes\n", size, RAM_SIZE);
+        for range in ram {
+            report += &format!("  {}\n", range_text(&range));
+        }
+
+        let code = ranges(program.clone(), never_run);
+        let size: usize = code.iter().map(|range| range.len()).sum();
+        report += &format!("Program bytes never executed: {} of {} bytes\n", size, program.len());
+        for range in code {
+            report += &format!("  {}\n", range_text(&range));
+        }
+        report
+    }
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+// The runs of addresses of `within` matching `keep`
+fn ranges(within: Range<u16>, keep: impl Fn(u16) -> bool) -> Vec<Range<u16>> {
+    let mut ranges: Vec<Range<u16>> = Vec::new();
+    for address in within {
+        if !keep(address) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if range.end == address => range.end += 1,
+            _ => ranges.push(address..address + 1),
+        }
+    }
+    ranges
+}
+
+fn range_text(range: &Range<u16>) -> String {
+    if range.len() == 1 {
+        format!("${:04X}", range.start)
+    } else {
+        format!("${:04X}-${:04X}", range.start, range.end - 1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_records_accesses_and_reports_unused_memory() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDX #$01; LDA $10,X; STA $0200; INC $20; JSR $060f; BRK; BEQ $0600; RTS
+        let program = vec![0xa2, 0x01, 0xb5, 0x10, 0x8d, 0x00, 0x02, 0xe6, 0x20, 0x20, 0x0f, 0x06, 0x00, 0xf0, 0xf1, 0x60];
+        cpu.load(program.clone());
+        cpu.program_counter = 0x0600;
+        let mut coverage = Coverage::new();
+        loop {
+            coverage.record(&cpu);
+            if !cpu.step() {
+                break;
+            }
+        }
+
+        assert_eq!((coverage.reads(0x0011), coverage.writes(0x0011)), (1, 0));
+        assert_eq!((coverage.reads(0x0200), coverage.writes(0x0200)), (0, 1));
+        assert_eq!((coverage.reads(0x0020), coverage.writes(0x0020)), (1, 1));
+        // JSR, then BRK after RTS
+        assert_eq!((coverage.writes(0x01fd), coverage.writes(0x01fc), coverage.writes(0x01fb)), (2, 2, 1));
+        assert_eq!((coverage.reads(0x01fd), coverage.reads(0x01fc)), (1, 1));
+        assert_eq!(coverage.executes(0x0601), 1);
+        assert_eq!(coverage.executes(0x060d), 0);
+
+        let report = coverage.report(0x0600..0x0600 + program.len() as u16);
+        assert!(report.contains("RAM never read, written or executed: 2026 of 2048 bytes\n"));
+        assert!(report.contains("Program bytes never executed: 2 of 16 bytes\n  $060D-$060E\n"));
+        assert!(coverage.to_csv().starts_with("address,reads,writes,executes\n0011,1,0,0\n"));
+    }
+}
@@ -1,9 +1,15 @@
 use std::collections::HashMap;
 use crate::opcodes;
 use crate::bus::Bus;
+use crate::error::EmulationError;
 
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xFD;
+// The reset sequence takes as long as an interrupt
+const RESET_CYCLES: u64 = 7;
+const INTERRUPT_CYCLES: u64 = 7;
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
 
 #[non_exhaustive]
 struct CpuFlags;
@@ -72,6 +78,15 @@ pub struct CPU {
     pub status: u8,
     pub program_counter: u16,
     pub stack_pointer: u8,
+    // Cycles run since power on, page crossings and taken branches included
+    pub cycles: u64,
+    // Stop the run loops after a BRK instead of going on through $FFFE, for
+    // programs without a BRK handler that end on BRK
+    pub stop_on_brk: bool,
+    // Run the unofficial opcodes like the real 6502 does. When false they fail
+    // with EmulationError::UnknownOpcode, for programs that should only use
+    // official ones.
+    pub lenient_opcodes: bool,
     pub bus: Bus,
 }
 
@@ -101,6 +116,9 @@ impl CPU {
             stack_pointer: STACK_RESET,
             program_counter: 0,
             status: 0,
+            cycles: 0,
+            stop_on_brk: false,
+            lenient_opcodes: true,
             bus: bus,
         }
     }
@@ -160,6 +178,22 @@ impl CPU {
        }
    }
 
+   // Whether indexing the operand address leaves the page of its base
+   fn page_crossed(&self, mode: &AddressingMode) -> bool {
+       let (base, index) = match mode {
+           AddressingMode::Absolute_X => (self.mem_read_u16(self.program_counter), self.register_x),
+           AddressingMode::Absolute_Y => (self.mem_read_u16(self.program_counter), self.register_y),
+           AddressingMode::Indirect_Y => {
+               let pointer = self.mem_read(self.program_counter);
+               let lo = self.mem_read(pointer as u16);
+               let hi = self.mem_read(pointer.wrapping_add(1) as u16);
+               ((hi as u16) << 8 | (lo as u16), self.register_y)
+           }
+           _ => return false,
+       };
+       base & 0xff00 != base.wrapping_add(index as u16) & 0xff00
+   }
+
    /* Stack logic */
    fn stack_pop(&mut self) -> u8 {
        self.stack_pointer = self.stack_pointer.wrapping_add(1);
@@ -193,6 +227,18 @@ impl CPU {
         //self.memory = [0; 0xFFFF];
         self.stack_pointer = STACK_RESET;
         self.program_counter = self.mem_read_u16(0xFFFC);
+        self.cycles = RESET_CYCLES;
+    }
+
+    // The reset button of a running console, unlike reset() which is the
+    // state at power on: registers and RAM are kept, interrupts are disabled
+    // and the stack pointer goes down by 3 as if an interrupt pushed the
+    // return address and status, without the writes
+    pub fn soft_reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status |= CpuFlags::INTERRUPT;
+        self.program_counter = self.mem_read_u16(0xFFFC);
+        self.cycles += RESET_CYCLES;
     }
 
     pub fn load(&mut self, program: Vec<u8>) {
@@ -216,194 +262,483 @@ impl CPU {
     where
         F: FnMut(&mut CPU),
     {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
+        loop {
+            self.poll_interrupts();
+            if !self.step() {
+                return;
+            }
+            callback(self);
+        }
+    }
 
+    // Same as run_with_callback, stopping with an error on opcodes the CPU
+    // can't execute instead of panicking
+    pub fn try_run_with_callback<F>(&mut self, mut callback: F) -> Result<(), EmulationError>
+    where
+        F: FnMut(&mut CPU),
+    {
         loop {
-            let code = self.mem_read(self.program_counter);
-            println!("> PC: {:#04x}  |  Opcode: {:#04x}  |  SP: {:#04x}  |  A: {:#04x}  |  X: {:#04x}  |  Y: {:#04x}",
-                self.program_counter, code, self.stack_pointer, self.register_a, self.register_x, self.register_y);
-            self.program_counter += 1;
-            let program_counter_state = self.program_counter;
-
-            let opcode = opcodes.get(&code).expect(&format!("OpCode {:x} is not recognized", code));
-
-            match code {
-                /* LDA */
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&opcode.mode);
-                }
+            self.poll_interrupts();
+            if !self.try_step()? {
+                return Ok(());
+            }
+            callback(self);
+        }
+    }
 
-                /* LDX */
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    self.ldx(&opcode.mode);
-                }
+    // Takes the NMI if the bus raised it, or else the IRQ if it's asserted
+    // and interrupts are enabled. Run loops call this before each
+    // instruction.
+    pub fn poll_interrupts(&mut self) {
+        if self.bus.poll_nmi_status() {
+            self.interrupt_nmi();
+        } else if self.bus.irq_asserted() && self.status & CpuFlags::INTERRUPT == 0 {
+            self.interrupt_irq();
+        }
+    }
 
-                /* LDY */
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
-                    self.ldy(&opcode.mode);
-                }
+    // Pushes the return address and the status (B clear, bit 5 set), disables
+    // interrupts and jumps through the NMI vector at $FFFA. RTI returns.
+    pub fn interrupt_nmi(&mut self) {
+        self.interrupt(NMI_VECTOR);
+    }
 
-                /* STA */
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
+    // Same as interrupt_nmi, through the IRQ/BRK vector at $FFFE
+    pub fn interrupt_irq(&mut self) {
+        self.interrupt(IRQ_VECTOR);
+    }
 
-                /* STX */
-                0x86 | 0x96 | 0x8E => {
-                    let addr = self.get_operand_address(&opcode.mode);
-                    self.mem_write(addr, self.register_x);
-                }
+    fn interrupt(&mut self, vector: u16) {
+        self.stack_push_u16(self.program_counter);
+        self.stack_push((self.status & !CpuFlags::BREAK) | CpuFlags::BREAK2);
+        self.status |= CpuFlags::INTERRUPT;
+        self.cycles += INTERRUPT_CYCLES;
+        self.program_counter = self.mem_read_u16(vector);
+    }
 
-                /* CPX */
-                0xE0 | 0xE4 | 0xEC => self.compare(&opcode.mode, self.register_x),
+    // Executes a single instruction. Returns false when it was a BRK and
+    // stop_on_brk is set. Panics on opcodes the CPU can't execute (unknown
+    // or jamming ones), so it's only meant for programs known to be good:
+    // use try_step for anything else.
+    pub fn step(&mut self) -> bool {
+        self.try_step().unwrap_or_else(|e| panic!("{}", e))
+    }
 
-                /* JSR */
-                0x20 => {
-                    self.stack_push_u16(self.program_counter + 2 - 1);
-                    let target_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = target_address;
-                }
-                /* RTS */
-                0x60 => {
-                    self.program_counter = self.stack_pop_u16() + 1;
-                }
+    // Same as step, with an error instead of a panic for opcodes that jam
+    // the CPU, and for unofficial ones unless lenient_opcodes is set
+    pub fn try_step(&mut self) -> Result<bool, EmulationError> {
+        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::OPCODES_MAP;
 
-                /* ADC */
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
-                    self.adc(&opcode.mode);
-                }
+        let address = self.program_counter;
+        let code = self.mem_read(self.program_counter);
+        self.program_counter += 1;
+        let program_counter_state = self.program_counter;
 
-                /* SBC */
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
-                    self.sbc(&opcode.mode);
-                }
+        let opcode = match opcodes.get(&code) {
+            Some(_) if !self.lenient_opcodes && opcodes::UNOFFICIAL_OPCODES_MAP.contains_key(&code) => {
+                self.program_counter = address;
+                return Err(EmulationError::UnknownOpcode { opcode: code, address });
+            }
+            Some(opcode) => opcode,
+            None => {
+                self.program_counter = address;
+                return Err(EmulationError::UnknownOpcode { opcode: code, address });
+            }
+        };
 
-                /* AND */
-                0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
+        self.cycles += opcode.cycles as u64;
+        if opcode.page_cross_penalty() && self.page_crossed(&opcode.mode) {
+            self.cycles += 1;
+        }
 
-                /* BNE */
-                0xD0 => {
-                    self.branch(self.status & CpuFlags::ZERO == 0);
-                }
+        match code {
+            /* LDA */
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                self.lda(&opcode.mode);
+            }
 
-                /* BEQ */
-                0xf0 => {
-                    self.branch(self.status & CpuFlags::ZERO != 0);
-                }
+            /* LDX */
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                self.ldx(&opcode.mode);
+            }
 
-                /* BVS */
-                0x70 => {
-                    self.branch(self.status & CpuFlags::OVERFLOW != 0);
-                }
+            /* LDY */
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
+                self.ldy(&opcode.mode);
+            }
 
-                /* BVC */
-                0x50 => {
-                    self.branch(self.status & CpuFlags::OVERFLOW == 0);
-                }
+            /* STA */
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                self.sta(&opcode.mode);
+            }
 
-                /* BPL */
-                0x10 => {
-                    self.branch(self.status & CpuFlags::NEGATIVE == 0);
-                }
+            /* STX */
+            0x86 | 0x96 | 0x8E => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.register_x);
+            }
 
-                /* BMI */
-                0x30 => {
-                    self.branch(self.status & CpuFlags::NEGATIVE != 0);
-                }
+            /* CPX */
+            0xE0 | 0xE4 | 0xEC => self.compare(&opcode.mode, self.register_x),
 
-                /* BCS */
-                0xb0 => {
-                    self.branch(self.status & CpuFlags::CARRY != 0);
-                }
+            /* CPY */
+            0xC0 | 0xC4 | 0xCC => self.compare(&opcode.mode, self.register_y),
 
-                /* BCC */
-                0x90 => {
-                    self.branch(self.status & CpuFlags::CARRY == 0);
-                }
+            /* JSR */
+            0x20 => {
+                self.stack_push_u16(self.program_counter + 2 - 1);
+                let target_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = target_address;
+            }
+            /* RTS */
+            0x60 => {
+                self.program_counter = self.stack_pop_u16() + 1;
+            }
 
-                0xCA => self.dex(),
-                0xAA => self.tax(),
-                0x8A => self.txa(),
-                0xE8 => self.inx(),
-                0x00 => {
-                    self.brk();
-                    return
-                }
+            /* RTI */
+            0x40 => {
+                self.plp();
+                self.program_counter = self.stack_pop_u16();
+            }
 
+            /* STY */
+            0x84 | 0x94 | 0x8C => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.register_y);
+            }
 
-                /* DEC */
-                0xC6 | 0xD6 | 0xCE | 0xDE => {
-                    self.dec(&opcode.mode);
-                }
+            /* ORA */
+            0x09 | 0x05 | 0x15 | 0x0d | 0x1d | 0x19 | 0x01 | 0x11 => {
+                self.ora(&opcode.mode);
+            }
 
-                /* Flags */
-                0xd8 => {
-                    self.status &= !CpuFlags::DECIMAL;
-                }
-                0x58 => {
-                    self.status &= !CpuFlags::INTERRUPT;
-                }
-                0xb8 => {
-                    self.status &= !CpuFlags::OVERFLOW;
-                }
-                0x18 => {
-                    self.status &= !CpuFlags::CARRY;
-                }
-                0x38 => {
-                    self.status |= CpuFlags::CARRY;
-                }
-                0x78 => {
-                    self.status |= CpuFlags::INTERRUPT;
-                }
-                0xf8 => {
-                    self.status |= CpuFlags::DECIMAL;
-                }
+            /* EOR */
+            0x49 | 0x45 | 0x55 | 0x4d | 0x5d | 0x59 | 0x41 | 0x51 => {
+                self.eor(&opcode.mode);
+            }
 
-                /* BIT */
-                0x24 | 0x2c => {
-                    self.bit(&opcode.mode);
-                }
+            /* ADC */
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
+                self.adc(&opcode.mode);
+            }
 
-                /* CMP */
-                0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
-                    self.compare(&opcode.mode, self.register_a);
-                }
+            /* SBC */
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
+                self.sbc(&opcode.mode);
+            }
 
-                /* LSR */
-                0x4A => {
-                    self.lsr_accumulator();
-                }
-                0x46 | 0x56 | 0x4e | 0x5e => {
-                    self.lsr(&opcode.mode);
-                }
+            /* AND */
+            0x29 | 0x25 | 0x35 | 0x2d | 0x3d | 0x39 | 0x21 | 0x31 => {
+                self.and(&opcode.mode);
+            }
 
-                /* INC */
-                0xe6 | 0xf6 | 0xee | 0xfe => {
-                    self.inc(&opcode.mode);
-                }
+            /* BNE */
+            0xD0 => {
+                self.branch(self.status & CpuFlags::ZERO == 0);
+            }
+
+            /* BEQ */
+            0xf0 => {
+                self.branch(self.status & CpuFlags::ZERO != 0);
+            }
+
+            /* BVS */
+            0x70 => {
+                self.branch(self.status & CpuFlags::OVERFLOW != 0);
+            }
+
+            /* BVC */
+            0x50 => {
+                self.branch(self.status & CpuFlags::OVERFLOW == 0);
+            }
 
-                /* JMP */
-                0x4c => {
-                    let mem_address = self.mem_read_u16(self.program_counter);
-                    self.program_counter = mem_address;
+            /* BPL */
+            0x10 => {
+                self.branch(self.status & CpuFlags::NEGATIVE == 0);
+            }
+
+            /* BMI */
+            0x30 => {
+                self.branch(self.status & CpuFlags::NEGATIVE != 0);
+            }
+
+            /* BCS */
+            0xb0 => {
+                self.branch(self.status & CpuFlags::CARRY != 0);
+            }
+
+            /* BCC */
+            0x90 => {
+                self.branch(self.status & CpuFlags::CARRY == 0);
+            }
+
+            0xCA => self.dex(),
+            0xAA => self.tax(),
+            0x8A => self.txa(),
+            0xE8 => self.inx(),
+            0x88 => self.dey(),
+            0xC8 => self.iny(),
+            0xA8 => self.tay(),
+            0x98 => self.tya(),
+            0xBA => self.tsx(),
+            0x9A => self.stack_pointer = self.register_x,
+
+            /* Stack */
+            0x48 => self.stack_push(self.register_a),
+            0x68 => {
+                let data = self.stack_pop();
+                self.set_register_a(data);
+            }
+            // B and bit 5 only exist on the stack, see plp()
+            0x08 => self.stack_push(self.status | CpuFlags::BREAK | CpuFlags::BREAK2),
+            0x28 => self.plp(),
+            0x00 => {
+                self.brk();
+                if self.stop_on_brk {
+                    return Ok(false);
                 }
+            }
+
+
+            /* DEC */
+            0xC6 | 0xD6 | 0xCE | 0xDE => {
+                self.dec(&opcode.mode);
+            }
+
+            /* Flags */
+            0xd8 => {
+                self.status &= !CpuFlags::DECIMAL;
+            }
+            0x58 => {
+                self.status &= !CpuFlags::INTERRUPT;
+            }
+            0xb8 => {
+                self.status &= !CpuFlags::OVERFLOW;
+            }
+            0x18 => {
+                self.status &= !CpuFlags::CARRY;
+            }
+            0x38 => {
+                self.status |= CpuFlags::CARRY;
+            }
+            0x78 => {
+                self.status |= CpuFlags::INTERRUPT;
+            }
+            0xf8 => {
+                self.status |= CpuFlags::DECIMAL;
+            }
+
+            /* BIT */
+            0x24 | 0x2c => {
+                self.bit(&opcode.mode);
+            }
+
+            /* CMP */
+            0xc9 | 0xc5 | 0xd5 | 0xcd | 0xdd | 0xd9 | 0xc1 | 0xd1 => {
+                self.compare(&opcode.mode, self.register_a);
+            }
+
+            /* LSR */
+            0x4A => {
+                self.lsr_accumulator();
+            }
+            0x46 | 0x56 | 0x4e | 0x5e => {
+                self.lsr(&opcode.mode);
+            }
+
+            /* ASL */
+            0x0A => {
+                let data = self.asl_value(self.register_a);
+                self.set_register_a(data);
+            }
+            0x06 | 0x16 | 0x0e | 0x1e => {
+                self.modify(&opcode.mode, CPU::asl_value);
+            }
+
+            /* ROL */
+            0x2A => {
+                let data = self.rol_value(self.register_a);
+                self.set_register_a(data);
+            }
+            0x26 | 0x36 | 0x2e | 0x3e => {
+                self.modify(&opcode.mode, CPU::rol_value);
+            }
+
+            /* ROR */
+            0x6A => {
+                let data = self.ror_value(self.register_a);
+                self.set_register_a(data);
+            }
+            0x66 | 0x76 | 0x6e | 0x7e => {
+                self.modify(&opcode.mode, CPU::ror_value);
+            }
 
-                /* NOP */
-                0xEA => {
-                    // no operation
+            /* INC */
+            0xe6 | 0xf6 | 0xee | 0xfe => {
+                self.inc(&opcode.mode);
+            }
+
+            /* JMP */
+            0x4c => {
+                let mem_address = self.mem_read_u16(self.program_counter);
+                self.program_counter = mem_address;
+            }
+            0x6c => {
+                let pointer = self.mem_read_u16(self.program_counter);
+                // The 6502 doesn't carry into the high byte of the pointer:
+                // JMP ($10FF) reads $10FF and $1000
+                let hi_address = (pointer & 0xff00) | (pointer as u8).wrapping_add(1) as u16;
+                let lo = self.mem_read(pointer) as u16;
+                let hi = self.mem_read(hi_address) as u16;
+                self.program_counter = hi << 8 | lo;
+            }
+
+            /* NOP */
+            0xEA => {
+                // no operation
+            }
+
+            /* Unofficial opcodes */
+
+            /* SLO */
+            0x03 | 0x07 | 0x0f | 0x13 | 0x17 | 0x1b | 0x1f => {
+                let data = self.modify(&opcode.mode, CPU::asl_value);
+                self.set_register_a(self.register_a | data);
+            }
+
+            /* RLA */
+            0x23 | 0x27 | 0x2f | 0x33 | 0x37 | 0x3b | 0x3f => {
+                let data = self.modify(&opcode.mode, CPU::rol_value);
+                self.set_register_a(self.register_a & data);
+            }
+
+            /* SRE */
+            0x43 | 0x47 | 0x4f | 0x53 | 0x57 | 0x5b | 0x5f => {
+                let data = self.lsr(&opcode.mode);
+                self.set_register_a(self.register_a ^ data);
+            }
+
+            /* RRA */
+            0x63 | 0x67 | 0x6f | 0x73 | 0x77 | 0x7b | 0x7f => {
+                let data = self.modify(&opcode.mode, CPU::ror_value);
+                self.add_to_register_a(data);
+            }
+
+            /* SAX */
+            0x83 | 0x87 | 0x8f | 0x97 => {
+                let addr = self.get_operand_address(&opcode.mode);
+                self.mem_write(addr, self.register_a & self.register_x);
+            }
+
+            /* LAX */
+            0xa3 | 0xa7 | 0xaf | 0xb3 | 0xb7 | 0xbf => {
+                self.lda(&opcode.mode);
+                self.register_x = self.register_a;
+            }
+
+            /* DCP */
+            0xc3 | 0xc7 | 0xcf | 0xd3 | 0xd7 | 0xdb | 0xdf => {
+                let data = self.dec(&opcode.mode);
+                self.compare_value(self.register_a, data);
+            }
+
+            /* ISC */
+            0xe3 | 0xe7 | 0xef | 0xf3 | 0xf7 | 0xfb | 0xff => {
+                let data = self.inc(&opcode.mode);
+                self.add_to_register_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+            }
+
+            /* ANC */
+            0x0b | 0x2b => {
+                self.and(&opcode.mode);
+                self.set_carry(self.register_a & 0x80 != 0);
+            }
+
+            /* ALR */
+            0x4b => {
+                self.and(&opcode.mode);
+                self.lsr_accumulator();
+            }
+
+            /* ARR */
+            0x6b => {
+                self.and(&opcode.mode);
+                let data = self.ror_value(self.register_a);
+                self.set_register_a(data);
+                // Carry and overflow come from bits 6 and 5 of the result
+                let bit6 = data & 0x40 != 0;
+                let bit5 = data & 0x20 != 0;
+                self.set_carry(bit6);
+                if bit6 != bit5 {
+                    self.status |= CpuFlags::OVERFLOW;
+                } else {
+                    self.status &= !CpuFlags::OVERFLOW;
                 }
+            }
 
-                _ => todo!(),
+            /* AXS */
+            0xcb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                let (result, borrow) = (self.register_a & self.register_x).overflowing_sub(data);
+                self.set_carry(!borrow);
+                self.register_x = result;
+                self.update_zero_and_negative_flags(result);
             }
 
-            if program_counter_state == self.program_counter {
-                self.program_counter += (opcode.len - 1) as u16;
+            /* SBC, same as 0xE9 */
+            0xeb => {
+                self.sbc(&opcode.mode);
             }
 
-            callback(self);
+            /* XAA, LXA. $EE is the value most consoles OR A with. */
+            0x8b => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.set_register_a((self.register_a | 0xee) & self.register_x & data);
+            }
+            0xab => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr);
+                self.set_register_a((self.register_a | 0xee) & data);
+                self.register_x = self.register_a;
+            }
+
+            /* LAS */
+            0xbb => {
+                let addr = self.get_operand_address(&opcode.mode);
+                let data = self.mem_read(addr) & self.stack_pointer;
+                self.stack_pointer = data;
+                self.register_x = data;
+                self.set_register_a(data);
+            }
+
+            /* AHX, TAS, SHY, SHX */
+            0x93 => self.store_and_high(&opcode.mode, self.register_y, self.register_a & self.register_x),
+            0x9f => self.store_and_high(&opcode.mode, self.register_y, self.register_a & self.register_x),
+            0x9b => {
+                self.stack_pointer = self.register_a & self.register_x;
+                self.store_and_high(&opcode.mode, self.register_y, self.stack_pointer);
+            }
+            0x9c => self.store_and_high(&opcode.mode, self.register_x, self.register_y),
+            0x9e => self.store_and_high(&opcode.mode, self.register_y, self.register_x),
+
+            /* NOP, with the operand skipped */
+            0x1a | 0x3a | 0x5a | 0x7a | 0xda | 0xfa | 0x80 | 0x82 | 0x89 | 0xc2 | 0xe2 | 0x04 | 0x44 | 0x64
+            | 0x14 | 0x34 | 0x54 | 0x74 | 0xd4 | 0xf4 | 0x0c | 0x1c | 0x3c | 0x5c | 0x7c | 0xdc | 0xfc => {}
+
+            /* JAM */
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xb2 | 0xd2 | 0xf2 => {
+                self.program_counter = address;
+                return Err(EmulationError::Jam { opcode: code, address });
+            }
+        }
+
+        if program_counter_state == self.program_counter {
+            self.program_counter += (opcode.len - 1) as u16;
         }
+
+        Ok(true)
     }
 
     fn update_zero_and_negative_flags(&mut self, result: u8) {
@@ -453,16 +788,23 @@ impl CPU {
          self.set_register_a(result);
     }
 
-    fn compare(&mut self, mode: &AddressingMode, compare_with: u8) {
+    // CMP, CPX and CPY: register - operand, keeping only the flags. Carry is
+    // set when there is no borrow, that is when register >= operand.
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
         let addr = self.get_operand_address(mode);
         let data = self.mem_read(addr);
-        if data <= compare_with {
-            self.status |= CpuFlags::CARRY;
-        } else {
+        self.compare_value(register, data);
+    }
+
+    fn compare_value(&mut self, register: u8, data: u8) {
+        let (result, borrow) = register.overflowing_sub(data);
+        if borrow {
             self.status &= !CpuFlags::CARRY;
+        } else {
+            self.status |= CpuFlags::CARRY;
         }
 
-        self.update_zero_and_negative_flags(compare_with.wrapping_sub(data));
+        self.update_zero_and_negative_flags(result);
     }
 
     fn branch(&mut self, condition: bool) {
@@ -473,6 +815,11 @@ impl CPU {
                 .wrapping_add(1)
                 .wrapping_add(jump as u16);
 
+            // One more cycle to branch, and another one to a different page
+            self.cycles += 1;
+            if self.program_counter.wrapping_add(1) & 0xff00 != jump_addr & 0xff00 {
+                self.cycles += 1;
+            }
             self.program_counter = jump_addr;
         }
     }
@@ -552,8 +899,109 @@ impl CPU {
         data
     }
 
+    // Like an IRQ, with B set in the pushed status. The return address skips
+    // the padding byte that follows the opcode.
     fn brk(&mut self) {
-        self.status = self.status | CpuFlags::BREAK | CpuFlags::BREAK2;
+        self.stack_push_u16(self.program_counter.wrapping_add(1));
+        self.stack_push(self.status | CpuFlags::BREAK | CpuFlags::BREAK2);
+        self.status |= CpuFlags::INTERRUPT;
+        self.program_counter = self.mem_read_u16(IRQ_VECTOR);
+    }
+
+    fn dey(&mut self) {
+        self.register_y = self.register_y.wrapping_sub(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn iny(&mut self) {
+        self.register_y = self.register_y.wrapping_add(1);
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn tay(&mut self) {
+        self.register_y = self.register_a;
+        self.update_zero_and_negative_flags(self.register_y);
+    }
+
+    fn tya(&mut self) {
+        self.register_a = self.register_y;
+        self.update_zero_and_negative_flags(self.register_a);
+    }
+
+    fn tsx(&mut self) {
+        self.register_x = self.stack_pointer;
+        self.update_zero_and_negative_flags(self.register_x);
+    }
+
+    // Pulls the status for PLP and RTI. The B flag is not a real flag, it
+    // only tells pushes by BRK and PHP from interrupts, so it's dropped, and
+    // bit 5 always reads as set.
+    fn plp(&mut self) {
+        self.status = self.stack_pop();
+        self.status &= !CpuFlags::BREAK;
+        self.status |= CpuFlags::BREAK2;
+    }
+
+    fn ora(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.set_register_a(data | self.register_a);
+    }
+
+    fn eor(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        self.set_register_a(data ^ self.register_a);
+    }
+
+    fn set_carry(&mut self, carry: bool) {
+        if carry {
+            self.status |= CpuFlags::CARRY;
+        } else {
+            self.status &= !CpuFlags::CARRY;
+        }
+    }
+
+    fn asl_value(&mut self, data: u8) -> u8 {
+        self.set_carry(data & 0x80 != 0);
+        data << 1
+    }
+
+    fn rol_value(&mut self, data: u8) -> u8 {
+        let carry_in = self.status & CpuFlags::CARRY;
+        self.set_carry(data & 0x80 != 0);
+        data << 1 | carry_in
+    }
+
+    fn ror_value(&mut self, data: u8) -> u8 {
+        let carry_in = self.status & CpuFlags::CARRY;
+        self.set_carry(data & 1 != 0);
+        data >> 1 | carry_in << 7
+    }
+
+    // Read-modify-write of the operand in memory, for the shifts and rotates
+    fn modify(&mut self, mode: &AddressingMode, operation: fn(&mut CPU, u8) -> u8) -> u8 {
+        let addr = self.get_operand_address(mode);
+        let data = self.mem_read(addr);
+        let data = operation(self, data);
+        self.mem_write(addr, data);
+        self.update_zero_and_negative_flags(data);
+        data
+    }
+
+    // The unstable stores of AHX, TAS, SHY and SHX: `value` ANDed with the high
+    // byte of the base address plus one. When indexing crosses a page, that
+    // also replaces the high byte of the address written to.
+    fn store_and_high(&mut self, mode: &AddressingMode, index: u8, value: u8) {
+        let addr = self.get_operand_address(mode);
+        let base = addr.wrapping_sub(index as u16);
+        let data = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if base & 0xff00 != addr & 0xff00 {
+            (data as u16) << 8 | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.mem_write(addr, data);
     }
 
     fn and(&mut self, mode: &AddressingMode) {
@@ -623,6 +1071,7 @@ mod test {
     fn test_0xa9_lda_immediate_load_data() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
 
         cpu.load(vec![0xa9, 0x05, 0x00]);
         cpu.reset();
@@ -638,6 +1087,7 @@ mod test {
     fn test_0xa9_lda_zero_flag() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
 
         cpu.load(vec![0xa9, 0x00, 0x00]);
         cpu.reset();
@@ -651,6 +1101,7 @@ mod test {
     fn test_0xaa_tax_move_a_to_x() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
         cpu.register_a = 10;
 
         cpu.load(vec![0xa9, 0x0A, 0xaa, 0x00]);
@@ -665,6 +1116,7 @@ mod test {
     fn test_5_ops_working_together() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
         cpu.load(vec![0xa9, 0xc0, 0xaa, 0xe8, 0x00]);
         cpu.reset();
         cpu.program_counter = 0x0600;
@@ -677,6 +1129,7 @@ mod test {
     fn test_inx_overflow() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
         cpu.register_x = 0xff;
 
         cpu.load(vec![0xa9, 0xff, 0xaa, 0xe8, 0xe8, 0x00]);
@@ -691,6 +1144,7 @@ mod test {
     fn test_lda_from_memory() {
         let bus = Bus::new();
         let mut cpu = CPU::new(bus);
+        cpu.stop_on_brk = true;
         cpu.mem_write(0x10, 0x55);
 
         cpu.load(vec![0xa5, 0x10, 0x00]);
@@ -700,4 +1154,314 @@ mod test {
 
         assert_eq!(cpu.register_a, 0x55);
     }
+
+    #[test]
+    fn test_stack_instructions() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDA #$42; PHA; PHP; LDA #$00; PLP; PLA; TSX; LDX #$80; TXS; BRK
+        cpu.load(vec![0xa9, 0x42, 0x48, 0x08, 0xa9, 0x00, 0x28, 0x68, 0xba, 0xa2, 0x80, 0x9a, 0x00]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.run();
+
+        assert_eq!(cpu.register_a, 0x42);
+        assert_eq!(cpu.register_x, 0x80);
+        // PLP restored the flags of LDA #$42, with bit 5 set and B dropped,
+        // then LDX #$80 set N and BRK disabled interrupts
+        assert_eq!(cpu.status, CpuFlags::BREAK2 | CpuFlags::NEGATIVE | CpuFlags::INTERRUPT);
+        // PHP pushed B and bit 5
+        assert_eq!(cpu.mem_read(0x01fc), CpuFlags::BREAK | CpuFlags::BREAK2);
+        // TXS, then the 3 bytes pushed by BRK
+        assert_eq!(cpu.stack_pointer, 0x80 - 3);
+    }
+
+    #[test]
+    fn test_shifts_and_rotates() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDA #$81; ASL A; ROL A; STA $10; ROR $10; SEC; ROR $10; BRK
+        cpu.load(vec![0xa9, 0x81, 0x0a, 0x2a, 0x85, 0x10, 0x66, 0x10, 0x38, 0x66, 0x10, 0x00]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.run();
+
+        // $81 << 1 = $02 carry 1, rotated left = $05 carry 0
+        assert_eq!(cpu.register_a, 0x05);
+        // $05 >> 1 = $02 carry 1, then the carry set by SEC comes in on top
+        assert_eq!(cpu.mem_read(0x10), 0x81);
+        assert_eq!(cpu.status & (CpuFlags::CARRY | CpuFlags::NEGATIVE), CpuFlags::NEGATIVE);
+    }
+
+    #[test]
+    fn test_jmp_indirect_page_wrap() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.mem_write(0x02ff, 0x00);
+        cpu.mem_write(0x0200, 0x07);
+        cpu.mem_write(0x0300, 0x08);
+        // JMP ($02FF); BRK
+        cpu.load(vec![0x6c, 0xff, 0x02, 0x00]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.step();
+
+        // The high byte comes from $0200, not $0300
+        assert_eq!(cpu.program_counter, 0x0700);
+        assert_eq!(cpu.cycles, 7 + 5);
+
+        // Pointers elsewhere in the page read both bytes in order
+        cpu.mem_write(0x0210, 0x34);
+        cpu.mem_write(0x0211, 0x06);
+        cpu.mem_write(0x0601, 0x10);
+        cpu.program_counter = 0x0600;
+        cpu.step();
+        assert_eq!(cpu.program_counter, 0x0634);
+    }
+
+    #[test]
+    fn test_unofficial_opcodes() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        cpu.mem_write(0x10, 0x81);
+        cpu.mem_write(0x11, 0x05);
+        cpu.mem_write(0x12, 0x40);
+        // LAX $10; NOP $1234,X; SAX $20; DCP $11; ISC $12; SLO $11; AXS #$01; BRK
+        cpu.load(vec![
+            0xa7, 0x10, 0x1c, 0x34, 0x12, 0x87, 0x20, 0xc7, 0x11, 0xe7, 0x12, 0x07, 0x11, 0xcb, 0x01, 0x00,
+        ]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.run();
+
+        assert_eq!(cpu.mem_read(0x20), 0x81);
+        // $05 - 1 = $04, compared to A = $81
+        // $40 + 1 = $41, subtracted from $81 with the carry of the compare
+        assert_eq!(cpu.mem_read(0x12), 0x41);
+        // $04 << 1 = $08, ORed into A = $81 - $41 = $40
+        assert_eq!(cpu.mem_read(0x11), 0x08);
+        assert_eq!(cpu.register_a, 0x48);
+        // ($48 & $81) - 1
+        assert_eq!(cpu.register_x, 0xff);
+        assert_eq!(cpu.status & CpuFlags::CARRY, 0);
+        // The return address pushed by BRK at $060F
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0611);
+    }
+
+    #[test]
+    fn test_cycles_with_page_crossing_and_branches() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDX #$01; LDA $00FF,X; LDA $0010,X; STA $00FF,X; BNE +0; BEQ +0; BRK
+        cpu.load(vec![0xa2, 0x01, 0xbd, 0xff, 0x00, 0xbd, 0x10, 0x00, 0x9d, 0xff, 0x00, 0xd0, 0x00, 0xf0, 0x00, 0x00]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        assert_eq!(cpu.cycles, 7);
+        let mut cycles = Vec::new();
+        loop {
+            let before = cpu.cycles;
+            let running = cpu.step();
+            cycles.push(cpu.cycles - before);
+            if !running {
+                break;
+            }
+        }
+        // A is 0, so BNE falls through and BEQ branches
+        assert_eq!(cycles, vec![2, 5, 4, 5, 2, 3, 7]);
+
+        // A taken branch to another page
+        cpu.mem_write(0x06fd, 0xf0);
+        cpu.mem_write(0x06fe, 0x10);
+        cpu.program_counter = 0x06fd;
+        let before = cpu.cycles;
+        cpu.step();
+        assert_eq!((cpu.program_counter, cpu.cycles - before), (0x070f, 4));
+    }
+
+    #[test]
+    fn test_nmi_runs_the_handler_and_returns() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // Nothing is mapped at $FFFA yet, so the vector reads as $0000:
+        // INY; RTI
+        cpu.mem_write(0x0000, 0xc8);
+        cpu.mem_write(0x0001, 0x40);
+        // INX; INX; BRK
+        cpu.load(vec![0xe8, 0xe8, 0x00]);
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        cpu.status = CpuFlags::CARRY;
+        cpu.bus.request_nmi();
+
+        let mut states = Vec::new();
+        cpu.run_with_callback(|cpu| states.push((cpu.program_counter, cpu.status)));
+
+        let pcs: Vec<u16> = states.iter().map(|&(pc, _)| pc).collect();
+        assert_eq!(pcs, vec![0x0001, 0x0600, 0x0601, 0x0602]);
+        assert_eq!((cpu.register_x, cpu.register_y), (2, 1));
+        assert_eq!(states[0].1, CpuFlags::CARRY | CpuFlags::INTERRUPT);
+        // The pushed status had B clear and bit 5 set, RTI restored it
+        assert_eq!(states[1].1, CpuFlags::CARRY | CpuFlags::BREAK2);
+        assert!(!cpu.bus.poll_nmi_status());
+    }
+
+    #[test]
+    fn test_brk_goes_on_through_the_vector() {
+        let mut cpu = CPU::new(Bus::new());
+        // The vector at $FFFE reads as $0000 too: INY; RTI
+        cpu.mem_write(0x0000, 0xc8);
+        cpu.mem_write(0x0001, 0x40);
+        // BRK; padding byte; INX
+        cpu.load(vec![0x00, 0xff, 0xe8]);
+        cpu.program_counter = 0x0600;
+
+        for _ in 0..4 {
+            assert!(cpu.step());
+        }
+        assert_eq!((cpu.register_x, cpu.register_y), (1, 1));
+        assert_eq!(cpu.program_counter, 0x0603);
+
+        cpu.stop_on_brk = true;
+        cpu.program_counter = 0x0600;
+        assert!(!cpu.step());
+        assert_eq!(cpu.program_counter, 0x0000);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_the_interrupt_flag() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.program_counter = 0x0600;
+        cpu.status = CpuFlags::INTERRUPT;
+        cpu.bus.set_irq(true);
+        cpu.poll_interrupts();
+        assert_eq!(cpu.program_counter, 0x0600);
+
+        cpu.status = 0;
+        cpu.poll_interrupts();
+        // $FFFE reads as $0000 with nothing mapped there
+        assert_eq!(cpu.program_counter, 0x0000);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0600);
+        assert_eq!(cpu.mem_read(0x01fb), CpuFlags::BREAK2);
+        assert_ne!(cpu.status & CpuFlags::INTERRUPT, 0);
+        // Still asserted, but now masked
+        cpu.poll_interrupts();
+        assert_eq!(cpu.stack_pointer, 0xfa);
+    }
+
+    // Flag properties of the arithmetic and compare instructions, checked on
+    // random operands instead of a few hand-picked ones
+    mod arithmetic {
+        use super::*;
+        use proptest::prelude::*;
+
+        const CARRY: u8 = CpuFlags::CARRY;
+        const ZERO: u8 = CpuFlags::ZERO;
+        const OVERFLOW: u8 = CpuFlags::OVERFLOW;
+        const NEGATIVE: u8 = CpuFlags::NEGATIVE;
+
+        // CPU with the operand in memory at the program counter, where the
+        // immediate addressing mode reads it
+        fn cpu_with(a: u8, operand: u8, carry: bool) -> CPU {
+            let mut cpu = CPU::new(Bus::new());
+            cpu.register_a = a;
+            cpu.status = if carry { CARRY } else { 0 };
+            cpu.program_counter = 0x0010;
+            cpu.mem_write(0x0010, operand);
+            cpu
+        }
+
+        fn flag(cpu: &CPU, flag: u8) -> bool {
+            cpu.status & flag != 0
+        }
+
+        proptest! {
+            #[test]
+            fn adc_matches_integer_addition(a: u8, m: u8, carry: bool) {
+                let mut cpu = cpu_with(a, m, carry);
+                cpu.add_to_register_a(m);
+
+                let unsigned = a as u16 + m as u16 + carry as u16;
+                let signed = a as i8 as i16 + m as i8 as i16 + carry as i16;
+                prop_assert_eq!(cpu.register_a, unsigned as u8);
+                prop_assert_eq!(flag(&cpu, CARRY), unsigned > 0xff);
+                prop_assert_eq!(flag(&cpu, OVERFLOW), !(-128..=127).contains(&signed));
+                prop_assert_eq!(flag(&cpu, ZERO), cpu.register_a == 0);
+                prop_assert_eq!(flag(&cpu, NEGATIVE), cpu.register_a & 0x80 != 0);
+            }
+
+            #[test]
+            fn sbc_matches_integer_subtraction(a: u8, m: u8, carry: bool) {
+                let mut cpu = cpu_with(a, m, carry);
+                cpu.sbc(&AddressingMode::Immediate);
+
+                // Carry set means no borrow
+                let borrow = !carry as i16;
+                let unsigned = a as i16 - m as i16 - borrow;
+                let signed = a as i8 as i16 - m as i8 as i16 - borrow;
+                prop_assert_eq!(cpu.register_a, unsigned as u8);
+                prop_assert_eq!(flag(&cpu, CARRY), unsigned >= 0);
+                prop_assert_eq!(flag(&cpu, OVERFLOW), !(-128..=127).contains(&signed));
+                prop_assert_eq!(flag(&cpu, ZERO), cpu.register_a == 0);
+                prop_assert_eq!(flag(&cpu, NEGATIVE), cpu.register_a & 0x80 != 0);
+            }
+
+            #[test]
+            fn sbc_undoes_adc(a: u8, m: u8) {
+                let mut cpu = cpu_with(a, m, false);
+                cpu.add_to_register_a(m);
+                cpu.status |= CARRY;
+                cpu.sbc(&AddressingMode::Immediate);
+                prop_assert_eq!(cpu.register_a, a);
+            }
+
+            #[test]
+            fn compare_is_sbc_without_store(register: u8, m: u8, carry: bool, overflow: bool) {
+                let mut compared = cpu_with(register, m, carry);
+                if overflow {
+                    compared.status |= OVERFLOW;
+                }
+                compared.compare(&AddressingMode::Immediate, register);
+
+                prop_assert_eq!(flag(&compared, CARRY), register >= m);
+                prop_assert_eq!(flag(&compared, ZERO), register == m);
+                prop_assert_eq!(flag(&compared, NEGATIVE), register.wrapping_sub(m) & 0x80 != 0);
+                // Nothing is stored and the overflow flag is left alone
+                prop_assert_eq!(compared.register_a, register);
+                prop_assert_eq!(flag(&compared, OVERFLOW), overflow);
+
+                // SBC with the carry set computes the same difference
+                let mut subtracted = cpu_with(register, m, true);
+                subtracted.sbc(&AddressingMode::Immediate);
+                let nzc = CARRY | ZERO | NEGATIVE;
+                prop_assert_eq!(compared.status & nzc, subtracted.status & nzc);
+            }
+        }
+
+        // Every register and operand pair, through the instructions themselves
+        #[test]
+        fn test_compare_instructions_exhaustively() {
+            let mut cpu = CPU::new(Bus::new());
+            for &opcode in &[0xc9, 0xe0, 0xc0] {
+                for register in 0..=255u8 {
+                    for m in 0..=255u8 {
+                        cpu.register_a = if opcode == 0xc9 { register } else { 0 };
+                        cpu.register_x = if opcode == 0xe0 { register } else { 0 };
+                        cpu.register_y = if opcode == 0xc0 { register } else { 0 };
+                        cpu.status = OVERFLOW;
+                        cpu.mem_write(0x0600, opcode);
+                        cpu.mem_write(0x0601, m);
+                        cpu.program_counter = 0x0600;
+                        cpu.step();
+
+                        let context = format!("opcode {:02X}, register {:02X}, operand {:02X}", opcode, register, m);
+                        assert_eq!(flag(&cpu, CARRY), register >= m, "carry, {}", context);
+                        assert_eq!(flag(&cpu, ZERO), register == m, "zero, {}", context);
+                        assert_eq!(flag(&cpu, NEGATIVE), register.wrapping_sub(m) >= 0x80, "negative, {}", context);
+                        assert!(flag(&cpu, OVERFLOW), "overflow, {}", context);
+                        assert_eq!(cpu.register_a | cpu.register_x | cpu.register_y, register);
+                        assert_eq!(cpu.program_counter, 0x0602);
+                    }
+                }
+            }
+        }
+    }
 }
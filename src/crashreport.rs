@@ -0,0 +1,184 @@
+use crate::cpu::{Mem, CPU};
+use crate::disasm;
+use crate::jsonstate;
+use crate::rominfo::RomInfo;
+use crate::savestate;
+use std::collections::VecDeque;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// An instruction as it was about to run
+#[derive(Debug, Clone, Copy)]
+struct Entry {
+    pc: u16,
+    bytes: [u8; 3],
+    a: u8,
+    x: u8,
+    y: u8,
+    p: u8,
+    sp: u8,
+}
+
+// The bytes of one instruction at its address, to disassemble it as it was
+// even if the code changed since
+impl Mem for Entry {
+    fn mem_read(&self, addr: u16) -> u8 {
+        match addr.wrapping_sub(self.pc) {
+            offset @ 0..=2 => self.bytes[offset as usize],
+            _ => 0,
+        }
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {}
+}
+
+// The last instructions run, kept cheaply so that a crash report can show
+// how the program got there
+pub struct RecentInstructions {
+    entries: VecDeque<Entry>,
+    capacity: usize,
+}
+
+impl RecentInstructions {
+    pub fn new(capacity: usize) -> Self {
+        RecentInstructions {
+            entries: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    // Called before each instruction is executed
+    pub fn record(&mut self, cpu: &CPU) {
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        let pc = cpu.program_counter;
+        self.entries.push_back(Entry {
+            pc,
+            bytes: [cpu.bus.peek(pc), cpu.bus.peek(pc.wrapping_add(1)), cpu.bus.peek(pc.wrapping_add(2))],
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            p: cpu.status,
+            sp: cpu.stack_pointer,
+        });
+    }
+
+    // Oldest first, in the format of the trace:
+    //
+    //   0600  20 06 06  JSR $0606        A:00 X:00 Y:00 P:00 SP:FD
+    pub fn lines(&self) -> Vec<String> {
+        self.entries
+            .iter()
+            .map(|entry| {
+                let instruction = disasm::disassemble(entry, entry.pc);
+                let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+                format!(
+                    "{:04X}  {:<8}  {:<16} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                    entry.pc,
+                    bytes.join(" "),
+                    instruction.text(),
+                    entry.a,
+                    entry.x,
+                    entry.y,
+                    entry.p,
+                    entry.sp
+                )
+            })
+            .collect()
+    }
+}
+
+// Writes what's needed to look into an emulation error into a new directory
+// of `dir`, named after the time, and returns it:
+//
+//   report.txt   the error, the eNES version, the ROM checksums and the config
+//   trace.txt    the last instructions run, the failing one last
+//   state.enes   a save state, loadable with the debugger
+//   state.json   the same state, readable
+pub fn write(
+    dir: &Path,
+    error: &str,
+    cpu: &CPU,
+    recent: &RecentInstructions,
+    rom: &[u8],
+    config: &str,
+) -> Result<PathBuf, String> {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0);
+    let mut path = dir.join(format!("crash-{}", seconds));
+    // Crashes in the same second, e.g. from a script running several games
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("crash-{}-{}", seconds, n));
+    }
+    fs::create_dir_all(&path).map_err(|e| format!("Could not create {}: {}", path.display(), e))?;
+
+    let info = RomInfo::inspect(rom);
+    let report = format!(
+        "Error: {}\neNES version: {}\nROM size: {} bytes\nROM CRC32: {:08X}\nROM SHA-1: {}\n\nConfig:\n{}\n",
+        error,
+        env!("CARGO_PKG_VERSION"),
+        info.size,
+        info.crc32,
+        info.sha1,
+        config
+    );
+    let mut trace = recent.lines().join("\n");
+    trace.push('\n');
+
+    let files = [
+        ("report.txt", report.into_bytes()),
+        ("trace.txt", trace.into_bytes()),
+        ("state.enes", savestate::save(cpu, info.crc32, None)),
+        ("state.json", jsonstate::export(cpu, &[]).into_bytes()),
+    ];
+    for (name, data) in files.iter() {
+        let file = path.join(name);
+        fs::write(&file, data).map_err(|e| format!("Could not write {}: {}", file.display(), e))?;
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_recent_instructions_and_bundle() {
+        let mut cpu = CPU::new(Bus::new());
+        // LDA #$01; LDX #$02; JSR $0609; BRK; $0609: .byte $02
+        let program = vec![0xa9, 0x01, 0xa2, 0x02, 0x20, 0x09, 0x06, 0x00, 0x00, 0x02];
+        cpu.load(program.clone());
+        cpu.program_counter = 0x0600;
+        let mut recent = RecentInstructions::new(3);
+        let error = loop {
+            recent.record(&cpu);
+            if let Err(e) = cpu.try_step() {
+                break e;
+            }
+        };
+
+        assert_eq!(
+            recent.lines(),
+            vec![
+                "0602  A2 02     LDX #$02         A:01 X:00 Y:00 P:00 SP:FD",
+                "0604  20 09 06  JSR $0609        A:01 X:02 Y:00 P:00 SP:FD",
+                "0609  02        JAM              A:01 X:02 Y:00 P:00 SP:FB",
+            ]
+        );
+
+        let dir = std::env::temp_dir().join(format!("enes-crash-test-{}", std::process::id()));
+        let bundle = write(&dir, &error.to_string(), &cpu, &recent, &program, "scale = 3").unwrap();
+        let report = fs::read_to_string(bundle.join("report.txt")).unwrap();
+        assert!(report.starts_with("Error: Opcode 02 at $0609 jammed the CPU\n"));
+        assert!(report.ends_with("Config:\nscale = 3\n"));
+        let mut loaded = CPU::new(Bus::new());
+        savestate::load(&mut loaded, &fs::read(bundle.join("state.enes")).unwrap(), Some(crc32fast::hash(&program))).unwrap();
+        assert_eq!(loaded.program_counter, 0x0609);
+        assert!(bundle.join("state.json").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,130 @@
+use crate::cpu::CPU;
+use crate::error::EmulationError;
+use crate::instructions;
+use crate::replay::Session;
+use crate::rewind::History;
+use std::collections::BTreeSet;
+
+// Snapshots for stepping back are taken every SNAPSHOT_INTERVAL instructions,
+// SNAPSHOT_COUNT of them cover the last million instructions
+const SNAPSHOT_INTERVAL: u64 = 1000;
+const SNAPSHOT_COUNT: usize = 1000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    // The program counter reached a breakpoint, before executing it
+    Breakpoint(u16),
+    // A BRK instruction was executed
+    Break,
+    // The requested number of instructions ran
+    StepsDone,
+    // The caller asked to stop, e.g. on Ctrl-C
+    Interrupted,
+    // The instruction at the program counter can't be executed
+    Error(EmulationError),
+}
+
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    history: History,
+    session: Option<Session>,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            history: History::new(SNAPSHOT_INTERVAL, SNAPSHOT_COUNT),
+            session: None,
+        }
+    }
+
+    // The session feeds the random numbers and keys of the program before
+    // each instruction, like the game window does
+    pub fn with_session(session: Session) -> Self {
+        Debugger {
+            session: Some(session),
+            ..Debugger::new()
+        }
+    }
+
+    // Returns false if there already was a breakpoint at that address
+    pub fn add_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.insert(address)
+    }
+
+    // Returns false if there was no breakpoint at that address
+    pub fn remove_breakpoint(&mut self, address: u16) -> bool {
+        self.breakpoints.remove(&address)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = &u16> {
+        self.breakpoints.iter()
+    }
+
+    // Undoes the last `count` instructions run by the debugger, or as many as
+    // the history allows, and returns how many were undone
+    pub fn step_back(&mut self, cpu: &mut CPU, count: u64) -> Result<u64, String> {
+        self.history.step_back(cpu, self.session.as_mut(), count)
+    }
+
+    // To be called when the machine was changed from outside, e.g. by a poke,
+    // since going back can not undo that
+    pub fn clear_history(&mut self) {
+        self.history.clear();
+    }
+
+    // Runs until the program counter reaches `address`, as with a breakpoint
+    // that goes away once the run stops, whatever stopped it
+    pub fn run_to<F>(&mut self, cpu: &mut CPU, address: u16, should_stop: F) -> StopReason
+    where
+        F: FnMut(&CPU) -> bool,
+    {
+        let added = self.add_breakpoint(address);
+        let reason = self.run(cpu, None, should_stop);
+        if added {
+            self.remove_breakpoint(address);
+        }
+        reason
+    }
+
+    // Runs at most `max_steps` instructions (or without limit when None). The
+    // instruction at the current program counter is always executed, so
+    // continuing from a breakpoint does not stop on it again right away.
+    pub fn run<F>(&mut self, cpu: &mut CPU, max_steps: Option<u64>, mut should_stop: F) -> StopReason
+    where
+        F: FnMut(&CPU) -> bool,
+    {
+        let mut steps = 0;
+        loop {
+            let step = instructions::step_with(cpu, |cpu| {
+                self.history.record(cpu, self.session.as_ref());
+                if let Some(session) = self.session.as_mut() {
+                    session.before_instruction(cpu);
+                }
+            });
+            match step {
+                Ok((_, true)) => {}
+                Ok((_, false)) => return StopReason::Break,
+                Err(e) => return StopReason::Error(e),
+            }
+            steps += 1;
+
+            if self.breakpoints.contains(&cpu.program_counter) {
+                return StopReason::Breakpoint(cpu.program_counter);
+            }
+            if max_steps == Some(steps) {
+                return StopReason::StepsDone;
+            }
+            if should_stop(cpu) {
+                return StopReason::Interrupted;
+            }
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Debugger::new()
+    }
+}
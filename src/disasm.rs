@@ -0,0 +1,108 @@
+use crate::cpu::{AddressingMode, Mem};
+use crate::labels::Labels;
+use crate::opcodes;
+
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+    // Address the operand refers to, None for implied and immediate operands
+    pub target: Option<u16>,
+}
+
+impl Instruction {
+    // e.g. "LDA $10,X" or "BNE $0602"
+    pub fn text(&self) -> String {
+        if self.operand.is_empty() {
+            self.mnemonic.to_string()
+        } else {
+            format!("{} {}", self.mnemonic, self.operand)
+        }
+    }
+
+    // Same as text() with the operand address replaced by its label, e.g. "JSR init"
+    pub fn labelled_text(&self, labels: &Labels) -> String {
+        let name = match self.target.and_then(|target| labels.name(target)) {
+            Some(name) => name,
+            None => return self.text(),
+        };
+        let start = self.operand.find('$').unwrap_or(0);
+        let end = self.operand[start + 1..]
+            .find(|c: char| !c.is_ascii_hexdigit())
+            .map_or(self.operand.len(), |i| start + 1 + i);
+        format!("{} {}{}{}", self.mnemonic, &self.operand[..start], name, &self.operand[end..])
+    }
+}
+
+// Decodes the instruction at `address`, unofficial opcodes included. Unknown
+// opcodes are shown as a single data byte so that disassembling can carry on
+// after them.
+pub fn disassemble<M: Mem>(mem: &M, address: u16) -> Instruction {
+    let code = mem.mem_read(address);
+    let opcode = match opcodes::OPCODES_MAP.get(&code) {
+        Some(opcode) => opcode,
+        None => {
+            return Instruction {
+                address,
+                bytes: vec![code],
+                mnemonic: ".byte",
+                operand: format!("${:02X}", code),
+                target: None,
+            }
+        }
+    };
+
+    let bytes: Vec<u8> = (0..opcode.len as u16)
+        .map(|i| mem.mem_read(address.wrapping_add(i)))
+        .collect();
+    let byte = || bytes[1];
+    let word = || u16::from_le_bytes([bytes[1], bytes[2]]);
+
+    let target = match opcode.mode {
+        AddressingMode::Immediate => None,
+        AddressingMode::ZeroPage
+        | AddressingMode::ZeroPage_X
+        | AddressingMode::ZeroPage_Y
+        | AddressingMode::Indirect_X
+        | AddressingMode::Indirect_Y => Some(byte() as u16),
+        AddressingMode::Absolute | AddressingMode::Absolute_X | AddressingMode::Absolute_Y => Some(word()),
+        AddressingMode::NoneAddressing => match opcode.len {
+            1 => None,
+            2 => Some(address.wrapping_add(2).wrapping_add(byte() as i8 as u16)),
+            _ => Some(word()),
+        },
+    };
+
+    let operand = match opcode.mode {
+        AddressingMode::Immediate => format!("#${:02X}", byte()),
+        AddressingMode::ZeroPage => format!("${:02X}", byte()),
+        AddressingMode::ZeroPage_X => format!("${:02X},X", byte()),
+        AddressingMode::ZeroPage_Y => format!("${:02X},Y", byte()),
+        AddressingMode::Absolute => format!("${:04X}", word()),
+        AddressingMode::Absolute_X => format!("${:04X},X", word()),
+        AddressingMode::Absolute_Y => format!("${:04X},Y", word()),
+        AddressingMode::Indirect_X => format!("(${:02X},X)", byte()),
+        AddressingMode::Indirect_Y => format!("(${:02X}),Y", byte()),
+        AddressingMode::NoneAddressing => match opcode.len {
+            // Shifts and rotates working on the accumulator
+            1 if matches!(code, 0x0a | 0x2a | 0x4a | 0x6a) => "A".to_string(),
+            1 => String::new(),
+            // Branches, relative to the next instruction
+            2 => {
+                let target = address.wrapping_add(2).wrapping_add(byte() as i8 as u16);
+                format!("${:04X}", target)
+            }
+            _ if code == 0x6c => format!("(${:04X})", word()),
+            _ => format!("${:04X}", word()),
+        },
+    };
+
+    Instruction {
+        address,
+        bytes,
+        mnemonic: opcode.mnemonic,
+        operand,
+        target,
+    }
+}
@@ -0,0 +1,120 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+use sdl2::render::{Texture, WindowCanvas};
+use sdl2::video::FullscreenType;
+
+// On a TV the NES pixel is not square, it is 8/7 times wider than it is tall.
+const PIXEL_ASPECT_RATIO: f64 = 8.0 / 7.0;
+
+// The keys the game reads at $ff, by their column and row on the pad of the
+// input display
+const PAD: [(u8, i32, i32); 4] = [(0x77, 1, 0), (0x61, 0, 1), (0x64, 2, 1), (0x73, 1, 2)];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplaySettings {
+    pub integer_scaling: bool,
+    pub aspect_correction: bool,
+    pub fullscreen: bool,
+    // Shows the pressed direction in a corner of the screen
+    pub input_display: bool,
+}
+
+impl DisplaySettings {
+    pub fn pixel_width(&self) -> f64 {
+        if self.aspect_correction { PIXEL_ASPECT_RATIO } else { 1.0 }
+    }
+
+    // Where a width x height frame is drawn inside the window, centered and
+    // letterboxed. With integer scaling only whole multiples of the frame
+    // height are used, so every row of pixels has the same thickness.
+    pub fn target_rect(&self, output: (u32, u32), width: u32, height: u32) -> Rect {
+        let (output_width, output_height) = output;
+        let frame_width = width as f64 * self.pixel_width();
+
+        let mut scale = (output_width as f64 / frame_width).min(output_height as f64 / height as f64);
+        if self.integer_scaling {
+            scale = scale.floor().max(1.0);
+        }
+
+        let w = (frame_width * scale).round() as u32;
+        let h = (height as f64 * scale).round() as u32;
+        let x = (output_width as i32 - w as i32) / 2;
+        let y = (output_height as i32 - h as i32) / 2;
+        Rect::new(x, y, w, h)
+    }
+}
+
+// `input` is the key the game currently reads, for the input display
+pub fn present(canvas: &mut WindowCanvas, texture: &Texture, settings: &DisplaySettings, width: u32, height: u32,
+               input: u8) {
+    let output = canvas.output_size().unwrap();
+    canvas.set_draw_color(Color::BLACK);
+    canvas.clear();
+    let target = settings.target_rect(output, width, height);
+    canvas.copy(texture, None, target).unwrap();
+    if settings.input_display {
+        draw_input(canvas, target, input);
+    }
+    canvas.present();
+}
+
+// A small direction pad over the bottom right corner of the frame, with the
+// pressed direction lit
+fn draw_input(canvas: &mut WindowCanvas, frame: Rect, input: u8) {
+    let cell = (frame.height() / 16).max(2);
+    let left = frame.right() - 4 * cell as i32;
+    let top = frame.bottom() - 4 * cell as i32;
+    for &(key, column, row) in PAD.iter() {
+        let color = if key == input { Color::WHITE } else { Color::RGB(64, 64, 64) };
+        canvas.set_draw_color(color);
+        let button = Rect::new(left + column * cell as i32, top + row * cell as i32, cell, cell);
+        canvas.fill_rect(button).unwrap();
+    }
+}
+
+pub fn apply_fullscreen(canvas: &mut WindowCanvas, settings: &DisplaySettings) {
+    let mode = if settings.fullscreen { FullscreenType::Desktop } else { FullscreenType::Off };
+    if let Err(e) = canvas.window_mut().set_fullscreen(mode) {
+        println!("Could not change fullscreen mode: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn settings(integer_scaling: bool, aspect_correction: bool) -> DisplaySettings {
+        DisplaySettings {
+            integer_scaling,
+            aspect_correction,
+            fullscreen: false,
+            input_display: false,
+        }
+    }
+
+    #[test]
+    fn test_target_rect_exact_multiple() {
+        for integer_scaling in [false, true] {
+            let rect = settings(integer_scaling, false).target_rect((320, 320), 32, 32);
+            assert_eq!(rect, Rect::new(0, 0, 320, 320));
+        }
+    }
+
+    #[test]
+    fn test_target_rect_not_a_multiple() {
+        // 9.375 times the frame fits, letterboxed on the sides
+        assert_eq!(settings(false, false).target_rect((400, 300), 32, 32), Rect::new(50, 0, 300, 300));
+        // Only 9 times with integer scaling, with borders all around
+        assert_eq!(settings(true, false).target_rect((400, 300), 32, 32), Rect::new(56, 6, 288, 288));
+        // Never less than once, even if it doesn't fit
+        assert_eq!(settings(true, false).target_rect((20, 20), 32, 32), Rect::new(-6, -6, 32, 32));
+    }
+
+    #[test]
+    fn test_target_rect_aspect_correction() {
+        // 32 pixels 8/7 wide are 36.57 square ones, 15 times is 548.57
+        assert_eq!(settings(false, true).target_rect((640, 480), 32, 32), Rect::new(45, 0, 549, 480));
+        assert_eq!(settings(false, true).target_rect((600, 500), 32, 32), Rect::new(14, 0, 571, 500));
+        assert_eq!(settings(true, true).target_rect((600, 500), 32, 32), Rect::new(25, 10, 549, 480));
+    }
+}
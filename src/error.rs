@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+// Why the CPU could not execute an instruction. The program counter is left
+// on the opcode, so the state can be looked at in a debugger.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum EmulationError {
+    #[error("Opcode {opcode:02X} at ${address:04X} is not recognized")]
+    UnknownOpcode { opcode: u8, address: u16 },
+    // A real 6502 stops fetching instructions until it's reset
+    #[error("Opcode {opcode:02X} at ${address:04X} jammed the CPU")]
+    Jam { opcode: u8, address: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum RomError {
+    #[error("Could not read {}: {message}", .path.display())]
+    Unreadable { path: PathBuf, message: String },
+    #[error("The program is empty")]
+    Empty,
+    // Programs are loaded at $0600 and the RAM ends at $07FF
+    #[error("The program is {size} bytes, only {max} fit in RAM from $0600")]
+    TooLarge { size: usize, max: usize },
+}
+
+// Cartridge boards. Only raw programs loaded at $0600 run for now, there's
+// no mapper at all.
+#[derive(Debug, Clone, Copy, PartialEq, Error)]
+pub enum MapperError {
+    #[error("Mapper {0} is not supported yet, eNES runs raw 6502 programs")]
+    Unsupported(u16),
+}
+
+// Input logs that can't be read or replayed
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ReplayError {
+    #[error("Could not read {}: {message}", .path.display())]
+    Unreadable { path: PathBuf, message: String },
+    #[error("Could not write {}: {message}", .path.display())]
+    Unwritable { path: PathBuf, message: String },
+    // A log file that can't be parsed, with why
+    #[error("{}: {source}", .path.display())]
+    InFile { path: PathBuf, source: Box<ReplayError> },
+    #[error("Not an eNES input log")]
+    NotALog,
+    #[error("Missing '{0}' line")]
+    MissingLine(&'static str),
+    #[error("Invalid {0}")]
+    InvalidField(&'static str),
+    #[error("Invalid checkpoint '{0}'")]
+    InvalidCheckpoint(String),
+    #[error("Invalid input event '{0}'")]
+    InvalidEvent(String),
+    #[error("Input event '{0}' is out of order")]
+    OutOfOrder(String),
+    #[error("The input log was recorded with ROM {expected:08x}, not {found:08x}")]
+    WrongRom { expected: u32, found: u32 },
+    #[error("The replay diverged from the recording before instruction {0}")]
+    Desync(u64),
+    #[error(transparent)]
+    Emulation(#[from] EmulationError),
+}
+
+// Settings of a Nes that can't work together, see NesBuilder::build
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum ConfigError {
+    #[error("A frame must run at least one instruction")]
+    NoInstructionsPerFrame,
+    #[error("Invalid cheat: {message}")]
+    InvalidCheat { code: String, message: String },
+    #[error("Two cheats freeze ${address:04x} to different values")]
+    ConflictingCheats { address: u16 },
+    #[error("Trace ranges were given without a trace")]
+    TraceRangesWithoutTrace,
+}
+
+#[derive(Debug, Clone, PartialEq, Error)]
+pub enum StateError {
+    #[error("Not an eNES save state")]
+    NotAState,
+    #[error("Unsupported save state version {0}")]
+    UnsupportedVersion(u8),
+    #[error("Save state is for ROM {found:08x}, not {expected:08x}")]
+    WrongRom { expected: u32, found: u32 },
+    #[error("Save state is truncated")]
+    Truncated,
+    #[error("Save state has no {0} chunk")]
+    MissingChunk(&'static str),
+    #[error("Version 1 save states can't be compressed")]
+    NotCompressible,
+    #[error("Could not compress save state: {0}")]
+    Compression(String),
+    #[error("Could not decompress save state: {0}")]
+    Decompression(String),
+    #[error("Compressed save state would take {0} bytes")]
+    TooLarge(usize),
+    #[error("Save state is compressed twice")]
+    NestedCompression,
+    #[error("Invalid JSON state: {0}")]
+    Json(String),
+}
@@ -0,0 +1,59 @@
+use crate::instructions::Executed;
+
+// What a running Nes tells its subscribers about
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event<'a> {
+    InstructionRetired(&'a Executed),
+    // Sent once the frame is drawn, `pixels` as returned by Nes::frame()
+    FrameComplete { frame: u64, pixels: &'a [u8] },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SubscriptionId(u64);
+
+// Send, like the rest of the machine, so a Nes can run on a worker thread
+type Callback = Box<dyn FnMut(&Event) + Send>;
+
+// The callbacks subscribed to the events, called in the order they were
+// subscribed
+#[derive(Default)]
+pub struct Observers {
+    next_id: u64,
+    list: Vec<(SubscriptionId, Callback)>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Observers {
+            next_id: 0,
+            list: Vec::new(),
+        }
+    }
+
+    pub fn subscribe<F>(&mut self, callback: F) -> SubscriptionId
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        let id = SubscriptionId(self.next_id);
+        self.next_id += 1;
+        self.list.push((id, Box::new(callback)));
+        id
+    }
+
+    // Returns false if the subscription was already gone
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        let len = self.list.len();
+        self.list.retain(|(subscription, _)| *subscription != id);
+        self.list.len() != len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn emit(&mut self, event: &Event) {
+        for (_, callback) in self.list.iter_mut() {
+            callback(event);
+        }
+    }
+}
@@ -0,0 +1,292 @@
+use crate::cpu::CPU;
+use crate::labels::Labels;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    P,
+    SP,
+    PC,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+    Or,
+    And,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Subtract,
+    Multiply,
+}
+
+// Operators by increasing precedence
+const OPERATORS: &[&[(&str, Operator)]] = &[
+    &[("||", Operator::Or)],
+    &[("&&", Operator::And)],
+    &[("==", Operator::Equal), ("!=", Operator::NotEqual)],
+    &[
+        ("<=", Operator::LessEqual),
+        (">=", Operator::GreaterEqual),
+        ("<", Operator::Less),
+        (">", Operator::Greater),
+    ],
+    &[("|", Operator::BitOr)],
+    &[("^", Operator::BitXor)],
+    &[("&", Operator::BitAnd)],
+    &[("+", Operator::Add), ("-", Operator::Subtract)],
+    &[("*", Operator::Multiply)],
+];
+
+// An expression on the registers and memory, e.g. "ram[$0086] + 1" or
+// "a == 3 && ram[lives] < 2". Numbers are decimal unless written $86 or 0x86,
+// label names stand for their address and comparisons give 1 or 0.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Number(i64),
+    Register(Register),
+    // The byte at an address, read without side effects
+    Ram(Box<Expr>),
+    Negate(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(Operator, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    pub fn parse(text: &str, labels: &Labels) -> Result<Expr, String> {
+        let mut parser = Parser { text, position: 0, labels };
+        let expr = parser.binary(0)?;
+        parser.skip_spaces();
+        if parser.position < text.len() {
+            return Err(format!("Unexpected '{}' in '{}'", parser.rest(), text));
+        }
+        Ok(expr)
+    }
+
+    pub fn eval(&self, cpu: &CPU) -> i64 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => match register {
+                Register::A => cpu.register_a as i64,
+                Register::X => cpu.register_x as i64,
+                Register::Y => cpu.register_y as i64,
+                Register::P => cpu.status as i64,
+                Register::SP => cpu.stack_pointer as i64,
+                Register::PC => cpu.program_counter as i64,
+            },
+            Expr::Ram(address) => cpu.bus.peek(address.eval(cpu) as u16) as i64,
+            Expr::Negate(expr) => expr.eval(cpu).wrapping_neg(),
+            Expr::Not(expr) => (expr.eval(cpu) == 0) as i64,
+            Expr::Binary(operator, left, right) => {
+                let left = left.eval(cpu);
+                // The right side of || and && is only evaluated when needed
+                match operator {
+                    Operator::Or => return (left != 0 || right.eval(cpu) != 0) as i64,
+                    Operator::And => return (left != 0 && right.eval(cpu) != 0) as i64,
+                    _ => {}
+                }
+                let right = right.eval(cpu);
+                match operator {
+                    Operator::Or | Operator::And => unreachable!(),
+                    Operator::Equal => (left == right) as i64,
+                    Operator::NotEqual => (left != right) as i64,
+                    Operator::Less => (left < right) as i64,
+                    Operator::LessEqual => (left <= right) as i64,
+                    Operator::Greater => (left > right) as i64,
+                    Operator::GreaterEqual => (left >= right) as i64,
+                    Operator::BitOr => left | right,
+                    Operator::BitXor => left ^ right,
+                    Operator::BitAnd => left & right,
+                    Operator::Add => left.wrapping_add(right),
+                    Operator::Subtract => left.wrapping_sub(right),
+                    Operator::Multiply => left.wrapping_mul(right),
+                }
+            }
+        }
+    }
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+    labels: &'a Labels,
+}
+
+impl<'a> Parser<'a> {
+    fn rest(&self) -> &'a str {
+        &self.text[self.position..]
+    }
+
+    fn skip_spaces(&mut self) {
+        let rest = self.rest();
+        self.position += rest.len() - rest.trim_start().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_spaces();
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), String> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' in '{}'", token, self.text))
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Expr, String> {
+        if level == OPERATORS.len() {
+            return self.unary();
+        }
+        let mut left = self.binary(level + 1)?;
+        'operators: loop {
+            for &(token, operator) in OPERATORS[level] {
+                // "|" and "&" are not the start of "||" and "&&"
+                let doubled = matches!(token, "|" | "&") && self.rest().trim_start().starts_with(&token.repeat(2));
+                if !doubled && self.eat(token) {
+                    let right = self.binary(level + 1)?;
+                    left = Expr::Binary(operator, Box::new(left), Box::new(right));
+                    continue 'operators;
+                }
+            }
+            return Ok(left);
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.eat("-") {
+            return Ok(Expr::Negate(Box::new(self.unary()?)));
+        }
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.binary(0)?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        self.skip_spaces();
+        let rest = self.rest();
+        let length = rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(rest.len());
+        if length == 0 {
+            return Err(format!("Expected a value in '{}'", self.text));
+        }
+        let word = &rest[..length];
+        self.position += length;
+
+        let hex = word.strip_prefix('$').or_else(|| word.strip_prefix("0x"));
+        if let Some(digits) = hex {
+            return i64::from_str_radix(digits, 16)
+                .map(Expr::Number)
+                .map_err(|_| format!("'{}' is not a hex number", word));
+        }
+        if word.starts_with(|c: char| c.is_ascii_digit()) {
+            return word.parse().map(Expr::Number).map_err(|_| format!("'{}' is not a number", word));
+        }
+
+        let register = match word.to_ascii_lowercase().as_str() {
+            "a" => Some(Register::A),
+            "x" => Some(Register::X),
+            "y" => Some(Register::Y),
+            "p" => Some(Register::P),
+            "sp" => Some(Register::SP),
+            "pc" => Some(Register::PC),
+            "ram" => {
+                self.expect("[")?;
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                return Ok(Expr::Ram(Box::new(address)));
+            }
+            _ => None,
+        };
+        match (register, self.labels.address(word)) {
+            (Some(register), _) => Ok(Expr::Register(register)),
+            (None, Some(address)) => Ok(Expr::Number(address as i64)),
+            (None, None) => Err(format!("Unknown name '{}'", word)),
+        }
+    }
+}
+
+// A named expression shown while debugging, e.g. "player_x = ram[0x0086]"
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watch {
+    pub name: String,
+    pub text: String,
+    pub expr: Expr,
+}
+
+impl Watch {
+    pub fn parse(text: &str, labels: &Labels) -> Result<Watch, String> {
+        let (name, expr) = match text.split_once('=') {
+            // Not the first half of "=="
+            Some((name, expr)) if !expr.starts_with('=') => (name.trim(), expr.trim()),
+            _ => return Err(format!("Expected 'name = expression', got '{}'", text)),
+        };
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(format!("'{}' is not a valid watch name", name));
+        }
+        Ok(Watch {
+            name: name.to_string(),
+            text: expr.to_string(),
+            expr: Expr::parse(expr, labels)?,
+        })
+    }
+}
+
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} = {}", self.name, self.text)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_parse_and_eval() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_a = 3;
+        cpu.mem_write(0x0086, 0x40);
+        cpu.mem_write(0x075a, 2);
+        let mut labels = Labels::new();
+        labels.insert(0x075a, "lives");
+
+        let eval = |text: &str| Expr::parse(text, &labels).map(|expr| expr.eval(&cpu));
+        assert_eq!(eval("ram[0x0086]"), Ok(0x40));
+        assert_eq!(eval("ram[$80 + 6] + 2 * 3"), Ok(0x46));
+        assert_eq!(eval("A == 3 && ram[lives] < 2"), Ok(0));
+        assert_eq!(eval("a == 3 || ram[lives] < 2"), Ok(1));
+        assert_eq!(eval("(ram[lives] | 1) & -1"), Ok(3));
+        assert_eq!(eval("!x"), Ok(1));
+        assert!(eval("ram[1").is_err());
+        assert!(eval("a +").is_err());
+        assert!(eval("score").is_err());
+
+        let watch = Watch::parse("player_x = ram[0x0086]", &labels).unwrap();
+        assert_eq!((watch.name.as_str(), watch.expr.eval(&cpu)), ("player_x", 0x40));
+        assert_eq!(watch.to_string(), "player_x = ram[0x0086]");
+        assert!(Watch::parse("a == 3", &labels).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+// Filters run on a copy of the frame upscaled by this factor, so that each
+// emulated pixel has a few rows and columns to darken, blur or bend.
+const UPSCALE: usize = 4;
+
+// How much the screen bulges with the curvature filter, 0 means flat.
+const CURVATURE: f64 = 0.08;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Filter {
+    // Darkens the last row of every emulated line
+    Scanlines,
+    // Adds a blurred copy on top, like phosphor light bleeding into its neighbours
+    Glow,
+    // Barrel distortion of a curved CRT tube
+    Curvature,
+}
+
+// Applies the configured filters in a fixed order (scanlines, glow, curvature)
+// regardless of the order they were listed in.
+pub struct FilterChain {
+    filters: Vec<Filter>,
+    width: usize,
+    height: usize,
+    output: Vec<u8>,
+    scratch: Vec<u8>,
+    blurred: Vec<u8>,
+}
+
+impl FilterChain {
+    pub fn new(filters: &[Filter], width: usize, height: usize) -> Self {
+        let size = width * UPSCALE * height * UPSCALE * 3;
+        FilterChain {
+            filters: filters.to_vec(),
+            width,
+            height,
+            output: vec![0; size],
+            scratch: vec![0; size],
+            blurred: vec![0; size],
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.filters.is_empty()
+    }
+
+    // Size of the frames returned by apply()
+    pub fn output_size(&self) -> (usize, usize) {
+        if self.is_empty() {
+            (self.width, self.height)
+        } else {
+            (self.width * UPSCALE, self.height * UPSCALE)
+        }
+    }
+
+    pub fn apply<'a>(&'a mut self, frame: &'a [u8]) -> &'a [u8] {
+        if self.is_empty() {
+            return frame;
+        }
+
+        let (width, height) = self.output_size();
+        upscale(frame, self.width, self.height, &mut self.output);
+
+        if self.filters.contains(&Filter::Scanlines) {
+            scanlines(&mut self.output, width, height);
+        }
+        if self.filters.contains(&Filter::Glow) {
+            glow(&mut self.output, &mut self.scratch, &mut self.blurred, width, height);
+        }
+        if self.filters.contains(&Filter::Curvature) {
+            curvature(&self.output, &mut self.scratch, width, height);
+            std::mem::swap(&mut self.output, &mut self.scratch);
+        }
+        &self.output
+    }
+}
+
+fn upscale(frame: &[u8], width: usize, height: usize, output: &mut [u8]) {
+    let out_width = width * UPSCALE;
+    for y in 0..height * UPSCALE {
+        for x in 0..out_width {
+            let src = ((y / UPSCALE) * width + x / UPSCALE) * 3;
+            let dst = (y * out_width + x) * 3;
+            output[dst..dst + 3].copy_from_slice(&frame[src..src + 3]);
+        }
+    }
+}
+
+fn scanlines(buffer: &mut [u8], width: usize, height: usize) {
+    for y in (UPSCALE - 1..height).step_by(UPSCALE) {
+        for byte in &mut buffer[y * width * 3..(y + 1) * width * 3] {
+            *byte /= 2;
+        }
+    }
+}
+
+fn glow(buffer: &mut [u8], scratch: &mut [u8], blurred: &mut [u8], width: usize, height: usize) {
+    // Box blur with a radius of one emulated pixel, done in two passes
+    let radius = UPSCALE as isize;
+    let blur = |src: &[u8], dst: &mut [u8], horizontal: bool| {
+        for y in 0..height as isize {
+            for x in 0..width as isize {
+                for channel in 0..3 {
+                    let mut sum = 0u32;
+                    let mut count = 0u32;
+                    for offset in -radius..=radius {
+                        let (sx, sy) = if horizontal { (x + offset, y) } else { (x, y + offset) };
+                        if sx >= 0 && sy >= 0 && sx < width as isize && sy < height as isize {
+                            sum += src[(sy as usize * width + sx as usize) * 3 + channel] as u32;
+                            count += 1;
+                        }
+                    }
+                    dst[(y as usize * width + x as usize) * 3 + channel] = (sum / count) as u8;
+                }
+            }
+        }
+    };
+
+    blur(buffer, scratch, true);
+    blur(scratch, blurred, false);
+
+    for (pixel, &light) in buffer.iter_mut().zip(blurred.iter()) {
+        *pixel = pixel.saturating_add(light / 4);
+    }
+}
+
+fn curvature(src: &[u8], dst: &mut [u8], width: usize, height: usize) {
+    for y in 0..height {
+        for x in 0..width {
+            // From the centre of the pixel, so that the screen bends the same
+            // on every side
+            let nx = 2.0 * (x as f64 + 0.5) / width as f64 - 1.0;
+            let ny = 2.0 * (y as f64 + 0.5) / height as f64 - 1.0;
+            let sx = nx * (1.0 + CURVATURE * ny * ny);
+            let sy = ny * (1.0 + CURVATURE * nx * nx);
+
+            let out = (y * width + x) * 3;
+            if sx.abs() >= 1.0 || sy.abs() >= 1.0 {
+                dst[out..out + 3].copy_from_slice(&[0, 0, 0]);
+            } else {
+                let px = ((sx + 1.0) / 2.0 * width as f64) as usize;
+                let py = ((sy + 1.0) / 2.0 * height as f64) as usize;
+                let src_idx = (py * width + px) * 3;
+                dst[out..out + 3].copy_from_slice(&src[src_idx..src_idx + 3]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A width x height frame with a different colour for every pixel
+    fn gradient(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height).flat_map(|i| [(i * 16) as u8, 255 - (i * 16) as u8, 128]).collect()
+    }
+
+    fn pixel(frame: &[u8], width: usize, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * width + x) * 3;
+        [frame[i], frame[i + 1], frame[i + 2]]
+    }
+
+    #[test]
+    fn test_scanlines_darken_the_last_row_of_every_line() {
+        let frame = [200u8; 2 * 2 * 3];
+        let mut chain = FilterChain::new(&[Filter::Scanlines], 2, 2);
+        assert_eq!(chain.output_size(), (8, 8));
+        let output = chain.apply(&frame);
+        for y in 0..8 {
+            let expected = if y % UPSCALE == UPSCALE - 1 { 100 } else { 200 };
+            assert!(output[y * 8 * 3..(y + 1) * 8 * 3].iter().all(|&byte| byte == expected), "row {}", y);
+        }
+    }
+
+    #[test]
+    fn test_glow_blur_keeps_a_flat_colour() {
+        let (width, height) = (2 * UPSCALE, 2 * UPSCALE);
+        let flat: Vec<u8> = [40u8, 100, 200].repeat(width * height);
+        let mut buffer = flat.clone();
+        let mut scratch = vec![0; flat.len()];
+        let mut blurred = vec![0; flat.len()];
+        glow(&mut buffer, &mut scratch, &mut blurred, width, height);
+
+        // Nothing to bleed into a flat colour, so the glow only brightens it
+        assert_eq!(blurred, flat);
+        assert_eq!(buffer, [50u8, 125, 250].repeat(width * height));
+    }
+
+    #[test]
+    fn test_curvature_keeps_the_centre_and_blacks_out_the_corners() {
+        let frame = gradient(4, 4);
+        let mut chain = FilterChain::new(&[Filter::Curvature], 4, 4);
+        let (width, height) = chain.output_size();
+        let output = chain.apply(&frame).to_vec();
+
+        assert_eq!(pixel(&output, width, width / 2, height / 2), pixel(&frame, 4, 2, 2));
+        for (x, y) in [(0, 0), (width - 1, 0), (0, height - 1), (width - 1, height - 1)] {
+            assert_eq!(pixel(&output, width, x, y), [0, 0, 0], "corner {},{}", x, y);
+        }
+    }
+}
@@ -0,0 +1,89 @@
+// Number of times an instruction was executed at each address of the CPU
+// address space. Only the address of the opcode byte is counted.
+pub struct Heatmap {
+    counts: Vec<u64>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Heatmap {
+            counts: vec![0; 0x10000],
+        }
+    }
+
+    // Called with the program counter before each instruction is executed
+    pub fn record(&mut self, address: u16) {
+        self.counts[address as usize] += 1;
+    }
+
+    pub fn count(&self, address: u16) -> u64 {
+        self.counts[address as usize]
+    }
+
+    pub fn max_count(&self) -> u64 {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    // Executed addresses with their counts, in address order
+    pub fn visited(&self) -> impl Iterator<Item = (u16, u64)> + '_ {
+        self.counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(address, &count)| (address as u16, count))
+    }
+
+    // "address,count" lines for the executed addresses
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("address,count\n");
+        for (address, count) in self.visited() {
+            csv += &format!("{:04X},{}\n", address, count);
+        }
+        csv
+    }
+
+    // The address space as a 256x256 RGB image, one row per page. Never
+    // executed addresses are black, the others go from blue to red on a
+    // logarithmic scale.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        let max = (self.max_count() as f64).ln_1p();
+        let mut rgb = Vec::with_capacity(0x10000 * 3);
+        for &count in &self.counts {
+            if count == 0 {
+                rgb.extend_from_slice(&[0, 0, 0]);
+            } else {
+                let heat = (count as f64).ln_1p() / max;
+                rgb.extend_from_slice(&[(heat * 255.0) as u8, 0, ((1.0 - heat) * 255.0) as u8]);
+            }
+        }
+        rgb
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Heatmap::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_counts_and_csv() {
+        let mut heatmap = Heatmap::new();
+        heatmap.record(0x0600);
+        heatmap.record(0x0602);
+        heatmap.record(0x0600);
+
+        assert_eq!(heatmap.count(0x0600), 2);
+        assert_eq!(heatmap.count(0x0601), 0);
+        assert_eq!(heatmap.max_count(), 2);
+        assert_eq!(heatmap.to_csv(), "address,count\n0600,2\n0602,1\n");
+
+        let rgb = heatmap.to_rgb();
+        assert_eq!(&rgb[0x0600 * 3..0x0600 * 3 + 3], &[255, 0, 0]);
+        assert_eq!(&rgb[0x0601 * 3..0x0601 * 3 + 3], &[0, 0, 0]);
+    }
+}
@@ -0,0 +1,190 @@
+use crate::cpu::CPU;
+use crate::error::EmulationError;
+use crate::opcodes;
+
+// CPU registers at one point of the execution
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+}
+
+impl Registers {
+    pub fn of(cpu: &CPU) -> Self {
+        Registers {
+            a: cpu.register_a,
+            x: cpu.register_x,
+            y: cpu.register_y,
+            status: cpu.status,
+            stack_pointer: cpu.stack_pointer,
+        }
+    }
+}
+
+// One instruction as it was executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Executed {
+    pub pc: u16,
+    pub opcode: u8,
+    pub mnemonic: &'static str,
+    // Operand bytes, `len - 1` of them are used
+    pub operands: [u8; 2],
+    pub len: u8,
+    // Cycles taken, page crossing and branch penalties included
+    pub cycles: u8,
+    // Before the instruction ran
+    pub before: Registers,
+    pub after: Registers,
+}
+
+impl Executed {
+    pub fn operands(&self) -> &[u8] {
+        &self.operands[..self.len.saturating_sub(1) as usize]
+    }
+}
+
+// Runs the CPU one instruction per call to next(). Stops after BRK if
+// stop_on_brk is set, or before an instruction that can't be executed, see
+// error().
+pub struct Instructions<'a> {
+    cpu: &'a mut CPU,
+    done: bool,
+    error: Option<EmulationError>,
+}
+
+impl Instructions<'_> {
+    // Why the iteration stopped early, if it did
+    pub fn error(&self) -> Option<EmulationError> {
+        self.error
+    }
+}
+
+impl Iterator for Instructions<'_> {
+    type Item = Executed;
+
+    fn next(&mut self) -> Option<Executed> {
+        if self.done {
+            return None;
+        }
+        match step_with(self.cpu, |_| {}) {
+            Ok((executed, running)) => {
+                self.done = !running;
+                Some(executed)
+            }
+            Err(e) => {
+                self.done = true;
+                self.error = Some(e);
+                None
+            }
+        }
+    }
+}
+
+// Executes the instruction at the program counter and describes it. The
+// flag is false when it was a BRK with stop_on_brk set, like for CPU::step.
+pub fn execute(cpu: &mut CPU) -> Result<(Executed, bool), EmulationError> {
+    let pc = cpu.program_counter;
+    let opcode = cpu.bus.peek(pc);
+    let operands = [cpu.bus.peek(pc.wrapping_add(1)), cpu.bus.peek(pc.wrapping_add(2))];
+    let before = Registers::of(cpu);
+    let cycles = cpu.cycles;
+
+    let running = cpu.try_step()?;
+
+    // try_step only succeeds for opcodes of the table
+    let info = opcodes::OPCODES_MAP[&opcode];
+    let executed = Executed {
+        pc,
+        opcode,
+        mnemonic: info.mnemonic,
+        operands,
+        len: info.len,
+        cycles: (cpu.cycles - cycles) as u8,
+        before,
+        after: Registers::of(cpu),
+    };
+    Ok((executed, running))
+}
+
+// One step of every run loop: takes a pending NMI or IRQ, lets `before`
+// look at (or feed) the machine about to run the next instruction, then
+// executes it. Loops that step the CPU another way would drift apart from
+// each other, and from the hashes of recorded sessions.
+pub fn step_with<F>(cpu: &mut CPU, before: F) -> Result<(Executed, bool), EmulationError>
+where
+    F: FnOnce(&mut CPU),
+{
+    cpu.poll_interrupts();
+    before(cpu);
+    execute(cpu)
+}
+
+impl CPU {
+    // The instructions executed from the current program counter on, run
+    // lazily as the iterator is consumed:
+    //
+    //   for executed in cpu.instructions().take(100) { ... }
+    pub fn instructions(&mut self) -> Instructions<'_> {
+        Instructions {
+            cpu: self,
+            done: false,
+            error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_iterates_until_brk() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDA #$05; STA $10; TAX; BRK
+        cpu.load(vec![0xa9, 0x05, 0x85, 0x10, 0xaa, 0x00]);
+        cpu.program_counter = 0x0600;
+
+        let executed: Vec<Executed> = cpu.instructions().collect();
+        let summary: Vec<(u16, &str, &[u8])> = executed.iter().map(|e| (e.pc, e.mnemonic, e.operands())).collect();
+        assert_eq!(
+            summary,
+            vec![(0x0600, "LDA", &[0x05][..]), (0x0602, "STA", &[0x10][..]), (0x0604, "TAX", &[][..]), (0x0605, "BRK", &[][..])]
+        );
+        assert_eq!(executed[0].before.a, 0x00);
+        assert_eq!(executed[0].after.a, 0x05);
+        assert_eq!(executed.iter().map(|e| e.cycles as u32).sum::<u32>(), 2 + 3 + 2 + 7);
+    }
+
+    #[test]
+    fn test_takes_pending_interrupts() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // Nothing is mapped at $FFFA, the NMI handler is at $0000: INY; RTI
+        cpu.mem_write(0x0000, 0xc8);
+        cpu.mem_write(0x0001, 0x40);
+        // INX; BRK
+        cpu.load(vec![0xe8, 0x00]);
+        cpu.program_counter = 0x0600;
+        cpu.bus.request_nmi();
+
+        let mnemonics: Vec<&str> = cpu.instructions().map(|e| e.mnemonic).collect();
+        assert_eq!(mnemonics, vec!["INY", "RTI", "INX", "BRK"]);
+    }
+
+    #[test]
+    fn test_stops_before_jam() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(vec![0xe8, 0x02]);
+        cpu.program_counter = 0x0600;
+
+        let mut instructions = cpu.instructions();
+        assert_eq!(instructions.by_ref().count(), 1);
+        assert_eq!(instructions.error(), Some(EmulationError::Jam { opcode: 0x02, address: 0x0601 }));
+    }
+}
@@ -0,0 +1,144 @@
+use crate::cpu::{Mem, CPU};
+use crate::error::StateError;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+const RAM_SIZE: u16 = 0x0800;
+const BYTES_PER_ROW: u16 = 16;
+
+// Registers as hex numbers, e.g. "pc": "0600"
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Registers {
+    pub a: String,
+    pub x: String,
+    pub y: String,
+    pub p: String,
+    pub sp: String,
+    pub pc: String,
+}
+
+// A readable dump of the machine for bug reports and test fixtures, next to
+// the binary save states:
+//
+//   {
+//     "registers": { "a": "01", "x": "00", "y": "00", "p": "24", "sp": "FD", "pc": "0602" },
+//     "flags": "nv-bdIzc",
+//     "memory": { "0200": "00 01 02 03 04 05 06 07 08 09 0A 0B 0C 0D 0E 0F", ... }
+//   }
+//
+// Memory is in rows of up to 16 bytes keyed by their address, so that a
+// change shows up as one line in a diff. Only the rows present are loaded.
+// The flags spell out P and are ignored when loading.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct JsonState {
+    pub registers: Registers,
+    #[serde(default)]
+    pub flags: String,
+    #[serde(default)]
+    pub memory: BTreeMap<String, String>,
+}
+
+// The whole RAM when `ranges` is empty
+pub fn export(cpu: &CPU, ranges: &[Range<u16>]) -> String {
+    let ram = 0..RAM_SIZE;
+    let ranges = if ranges.is_empty() { std::slice::from_ref(&ram) } else { ranges };
+
+    let mut memory = BTreeMap::new();
+    for range in ranges {
+        let mut start = range.start;
+        while start < range.end {
+            let end = range.end.min(start.saturating_add(BYTES_PER_ROW));
+            let bytes: Vec<String> = (start..end).map(|addr| format!("{:02X}", cpu.bus.peek(addr))).collect();
+            memory.insert(format!("{:04X}", start), bytes.join(" "));
+            start = end;
+        }
+    }
+
+    let state = JsonState {
+        registers: Registers {
+            a: format!("{:02X}", cpu.register_a),
+            x: format!("{:02X}", cpu.register_x),
+            y: format!("{:02X}", cpu.register_y),
+            p: format!("{:02X}", cpu.status),
+            sp: format!("{:02X}", cpu.stack_pointer),
+            pc: format!("{:04X}", cpu.program_counter),
+        },
+        flags: "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, flag)| if cpu.status & (0b1000_0000 >> i) != 0 { flag } else { flag.to_ascii_lowercase() })
+            .collect(),
+        memory,
+    };
+    serde_json::to_string_pretty(&state).unwrap() + "\n"
+}
+
+// Nothing is changed when the JSON is invalid
+pub fn import(cpu: &mut CPU, json: &str) -> Result<(), StateError> {
+    let state: JsonState = serde_json::from_str(json).map_err(|e| StateError::Json(e.to_string()))?;
+    let byte = |name: &str, text: &str| {
+        u8::from_str_radix(text, 16).map_err(|_| StateError::Json(format!("{} '{}' is not a hex byte", name, text)))
+    };
+    let registers = &state.registers;
+    let a = byte("a", &registers.a)?;
+    let x = byte("x", &registers.x)?;
+    let y = byte("y", &registers.y)?;
+    let p = byte("p", &registers.p)?;
+    let sp = byte("sp", &registers.sp)?;
+    let pc = u16::from_str_radix(&registers.pc, 16)
+        .map_err(|_| StateError::Json(format!("pc '{}' is not a hex address", registers.pc)))?;
+
+    let mut writes = Vec::new();
+    for (start, bytes) in &state.memory {
+        let start =
+            u16::from_str_radix(start, 16).map_err(|_| StateError::Json(format!("'{}' is not a hex address", start)))?;
+        for (i, text) in bytes.split_whitespace().enumerate() {
+            writes.push((start.wrapping_add(i as u16), byte("memory byte", text)?));
+        }
+    }
+
+    cpu.register_a = a;
+    cpu.register_x = x;
+    cpu.register_y = y;
+    cpu.status = p;
+    cpu.stack_pointer = sp;
+    cpu.program_counter = pc;
+    for (addr, data) in writes {
+        cpu.mem_write(addr, data);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_export_and_import() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_a = 0x42;
+        cpu.status = 0b1000_0011;
+        cpu.program_counter = 0x0612;
+        cpu.mem_write(0x0201, 0xab);
+        let json = export(&cpu, &[0x0200..0x0210, 0x0210..0x0213]);
+        assert!(json.contains("\"pc\": \"0612\""));
+        assert!(json.contains("\"flags\": \"Nv-bdiZC\""));
+        assert!(json.contains("\"0200\": \"00 AB 00 00 00 00 00 00 00 00 00 00 00 00 00 00\""));
+        assert!(json.contains("\"0210\": \"00 00 00\""));
+
+        let mut copy = CPU::new(Bus::new());
+        import(&mut copy, &json).unwrap();
+        assert_eq!(export(&copy, &[]), export(&cpu, &[]));
+
+        let mut cpu = CPU::new(Bus::new());
+        let fixture = r#"{ "registers": { "a": "01", "x": "02", "y": "03", "p": "00", "sp": "FD", "pc": "0600" },
+                           "memory": { "0010": "FF 7F" } }"#;
+        import(&mut cpu, fixture).unwrap();
+        assert_eq!((cpu.register_y, cpu.bus.peek(0x0011)), (3, 0x7f));
+        assert!(import(&mut cpu, &fixture.replace("FF 7F", "FF 7G")).is_err());
+        assert_eq!(cpu.bus.peek(0x0010), 0xff);
+    }
+}
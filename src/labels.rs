@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::path::Path;
+
+// Programs are loaded at $0600 (see CPU::load), so the PRG offsets of Mesen
+// label files are relative to it.
+const PRG_START: u16 = 0x0600;
+
+// Names for addresses, read from Mesen .mlb or cc65 .dbg files
+#[derive(Debug, Clone, Default)]
+pub struct Labels {
+    names: HashMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl Labels {
+    pub fn new() -> Self {
+        Labels::default()
+    }
+
+    // Picks the format from the extension: .dbg for cc65, Mesen .mlb otherwise
+    pub fn load(path: &Path) -> Result<Labels, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let result = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => Labels::parse_dbg(&text),
+            _ => Labels::parse_mlb(&text),
+        };
+        result.map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn insert(&mut self, address: u16, name: &str) {
+        // The first name given to an address is the one displayed
+        self.names.entry(address).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), address);
+    }
+
+    pub fn name(&self, address: u16) -> Option<&str> {
+        self.names.get(&address).map(|name| name.as_str())
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    // Mesen labels, one per line: <type>:<hex address>[-<hex end>]:<label>[:<comment>]
+    // with P for PRG ROM offsets, R for internal RAM and G for registers.
+    // Other memory types don't exist here and are skipped.
+    pub fn parse_mlb(text: &str) -> Result<Labels, String> {
+        let mut labels = Labels::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fields: Vec<&str> = line.splitn(4, ':').collect();
+            if fields.len() < 3 {
+                return Err(format!("line {}: expected <type>:<address>:<label>", number + 1));
+            }
+            let start = fields[1].split('-').next().unwrap_or_default();
+            let address = u16::from_str_radix(start, 16)
+                .map_err(|_| format!("line {}: '{}' is not a hex address", number + 1, fields[1]))?;
+            let name = fields[2];
+            // Comment-only entries have no label
+            if name.is_empty() {
+                continue;
+            }
+            match fields[0] {
+                "P" => labels.insert(PRG_START.wrapping_add(address), name),
+                "R" | "G" => labels.insert(address, name),
+                _ => {}
+            }
+        }
+        Ok(labels)
+    }
+
+    // The sym lines of a cc65 debug file (ld65 --dbgfile), e.g.
+    // sym	id=3,name="reset",addrsize=absolute,scope=0,def=4,val=0x600,seg=1,type=lab
+    pub fn parse_dbg(text: &str) -> Result<Labels, String> {
+        let mut labels = Labels::new();
+        for (number, line) in text.lines().enumerate() {
+            let fields = match line.strip_prefix("sym\t") {
+                Some(fields) => fields,
+                None => continue,
+            };
+
+            let mut name = None;
+            let mut value = None;
+            for field in fields.split(',') {
+                match field.split_once('=') {
+                    Some(("name", quoted)) => name = Some(quoted.trim_matches('"')),
+                    Some(("val", number)) => value = Some(number),
+                    _ => {}
+                }
+            }
+
+            // Imports and scopes have no value
+            let (name, value) = match (name, value) {
+                (Some(name), Some(value)) => (name, value),
+                _ => continue,
+            };
+            let value = match value.strip_prefix("0x") {
+                Some(hex) => u32::from_str_radix(hex, 16),
+                None => value.parse::<u32>(),
+            }
+            .map_err(|_| format!("line {}: '{}' is not a number", number + 1, value))?;
+            if let Ok(address) = u16::try_from(value) {
+                labels.insert(address, name);
+            }
+        }
+        Ok(labels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mlb() {
+        let labels = Labels::parse_mlb("P:0000:reset\nR:0010-0011:apple_position\nP:0030::just a comment\nS:0000:save\n").unwrap();
+        assert_eq!(labels.name(0x0600), Some("reset"));
+        assert_eq!(labels.name(0x0010), Some("apple_position"));
+        assert_eq!(labels.name(0x0630), None);
+        assert_eq!(labels.address("reset"), Some(0x0600));
+        assert_eq!(labels.address("save"), None);
+    }
+
+    #[test]
+    fn test_parse_dbg() {
+        let text = "version\tmajor=2,minor=0\n\
+                    sym\tid=0,name=\"reset\",addrsize=absolute,scope=0,def=1,val=0x600,seg=0,type=lab\n\
+                    sym\tid=1,name=\"snake_length\",addrsize=zeropage,scope=0,def=2,val=3,type=equ\n\
+                    sym\tid=2,name=\"imported\",addrsize=absolute,scope=0,def=3,type=imp\n";
+        let labels = Labels::parse_dbg(text).unwrap();
+        assert_eq!(labels.name(0x0600), Some("reset"));
+        assert_eq!(labels.name(0x0003), Some("snake_length"));
+        assert_eq!(labels.address("imported"), None);
+    }
+}
@@ -0,0 +1,36 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod cpu;
+pub mod bus;
+pub mod opcodes;
+pub mod savestate;
+pub mod disasm;
+pub mod debugger;
+pub mod labels;
+pub mod trace;
+pub mod profiler;
+pub mod heatmap;
+pub mod replay;
+pub mod tracediff;
+pub mod stats;
+pub mod rewind;
+pub mod cheats;
+pub mod region;
+pub mod nes;
+pub mod error;
+pub mod instructions;
+pub mod events;
+pub mod statediff;
+pub mod metrics;
+pub mod callstack;
+pub mod stackcheck;
+pub mod coverage;
+pub mod rominfo;
+pub mod listing;
+pub mod unofficial;
+pub mod expr;
+pub mod jsonstate;
+pub mod crashreport;
+pub mod filter;
+pub mod snake;
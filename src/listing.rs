@@ -0,0 +1,247 @@
+use crate::cpu::{AddressingMode, Mem};
+use crate::disasm::{self, Instruction};
+use crate::labels::Labels;
+use crate::opcodes;
+use std::collections::BTreeSet;
+
+// Programs are loaded at $0600 (see CPU::load)
+pub const PROGRAM_START: u16 = 0x0600;
+
+// Bits of a Code/Data Logger file, one byte per program byte, as written by
+// FCEUX and Mesen
+const CDL_CODE: u8 = 0x01;
+const CDL_DATA: u8 = 0x02;
+
+const BYTES_PER_ROW: usize = 8;
+
+// The program alone, reading 0 everywhere else
+struct Program<'a> {
+    bytes: &'a [u8],
+}
+
+impl Mem for Program<'_> {
+    fn mem_read(&self, addr: u16) -> u8 {
+        let offset = addr.wrapping_sub(PROGRAM_START) as usize;
+        self.bytes.get(offset).copied().unwrap_or(0)
+    }
+
+    fn mem_write(&mut self, _addr: u16, _data: u8) {}
+}
+
+// What recursive traversal found out about each byte of a program
+pub struct Listing<'a> {
+    program: &'a [u8],
+    cdl: Option<&'a [u8]>,
+    // Instructions found, by offset in the program
+    instructions: Vec<Option<Instruction>>,
+    // Offsets of the bytes that belong to an instruction
+    code: Vec<bool>,
+    // Addresses inside the program jumped to, branched to or used as data
+    targets: BTreeSet<u16>,
+}
+
+impl<'a> Listing<'a> {
+    // Follows the code from `entries`, through branches, jumps and calls,
+    // until RTS, RTI, BRK, JAM, an indirect jump or an unknown opcode. Bytes
+    // never reached are data. A CDL file, as logged while the program ran,
+    // adds the code only reached through computed jumps (jump tables, RTS
+    // tricks) and keeps bytes logged as data from being taken for code.
+    pub fn build(program: &'a [u8], entries: &[u16], cdl: Option<&'a [u8]>) -> Listing<'a> {
+        let mut listing = Listing {
+            program,
+            cdl,
+            instructions: (0..program.len()).map(|_| None).collect(),
+            code: vec![false; program.len()],
+            targets: entries.iter().copied().collect(),
+        };
+        listing.trace(entries.to_vec());
+
+        // Code logged but not reached starts right after the code found so
+        // far, or after data
+        let logged = |offset: usize| cdl.and_then(|cdl| cdl.get(offset)).is_some_and(|flags| flags & CDL_CODE != 0);
+        for offset in 0..program.len() {
+            if logged(offset) && !listing.code[offset] && (offset == 0 || listing.code[offset - 1] || !logged(offset - 1)) {
+                listing.trace(vec![PROGRAM_START + offset as u16]);
+            }
+        }
+        listing
+    }
+
+    fn offset(&self, address: u16) -> Option<usize> {
+        let offset = address.wrapping_sub(PROGRAM_START) as usize;
+        if offset < self.program.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn is_data(&self, offset: usize) -> bool {
+        self.cdl.and_then(|cdl| cdl.get(offset)).is_some_and(|flags| flags & CDL_DATA != 0)
+    }
+
+    fn trace(&mut self, mut pending: Vec<u16>) {
+        let mem = Program { bytes: self.program };
+        while let Some(mut address) = pending.pop() {
+            while let Some(offset) = self.offset(address) {
+                let code = self.program[offset];
+                let opcode = match opcodes::OPCODES_MAP.get(&code) {
+                    Some(opcode) => opcode,
+                    None => break,
+                };
+                let end = offset + opcode.len as usize;
+                if end > self.program.len() || (offset..end).any(|i| self.code[i] || self.is_data(i)) {
+                    break;
+                }
+
+                let instruction = disasm::disassemble(&mem, address);
+                for flag in &mut self.code[offset..end] {
+                    *flag = true;
+                }
+                let target = instruction.target.filter(|&target| self.offset(target).is_some());
+                self.targets.extend(target);
+                self.instructions[offset] = Some(instruction);
+
+                let branch = matches!(opcode.mode, AddressingMode::NoneAddressing) && opcode.len == 2;
+                match opcode.mnemonic {
+                    "RTS" | "RTI" | "BRK" | "JAM" => break,
+                    // Indirect jumps go somewhere only known while running
+                    "JMP" if code == 0x6c => break,
+                    "JMP" => {
+                        pending.extend(target);
+                        break;
+                    }
+                    "JSR" => pending.extend(target),
+                    _ if branch => pending.extend(target),
+                    _ => {}
+                }
+                address = address.wrapping_add(opcode.len as u16);
+            }
+        }
+    }
+
+    pub fn is_code(&self, address: u16) -> bool {
+        self.offset(address).is_some_and(|offset| self.code[offset])
+    }
+
+    // Only addresses that start a line can be labelled
+    fn is_labelled(&self, address: u16) -> bool {
+        match self.offset(address) {
+            Some(offset) => {
+                self.targets.contains(&address) && (self.instructions[offset].is_some() || !self.code[offset])
+            }
+            None => false,
+        }
+    }
+
+    // A ca65 source that assembles back to the same bytes. Targets get the
+    // name they have in `labels`, or Lxxxx. Unofficial opcodes are written as
+    // bytes, ca65 doesn't know them for the 6502 and some of them would come
+    // back as their official twin (SBC #$xx for $EB).
+    pub fn to_ca65(&self, labels: &Labels) -> String {
+        let label = |address: u16| match labels.name(address) {
+            Some(name) => name.to_string(),
+            None => format!("L{:04X}", address),
+        };
+
+        let mut text = String::from("; Recursive-traversal disassembly\n");
+        text += &format!(".setcpu \"6502\"\n.org ${:04X}\n\n", PROGRAM_START);
+        let mut offset = 0;
+        while offset < self.program.len() {
+            let address = PROGRAM_START + offset as u16;
+            if self.is_labelled(address) {
+                text += &format!("{}:\n", label(address));
+            }
+
+            if let Some(instruction) = &self.instructions[offset] {
+                let mut names = Labels::new();
+                if let Some(target) = instruction.target.filter(|&target| self.is_labelled(target)) {
+                    names.insert(target, &label(target));
+                }
+                if opcodes::UNOFFICIAL_OPCODES_MAP.contains_key(&instruction.bytes[0]) {
+                    let bytes: Vec<String> = instruction.bytes.iter().map(|byte| format!("${:02X}", byte)).collect();
+                    text += &format!("    .byte {} ; {}\n", bytes.join(", "), instruction.labelled_text(&names));
+                } else {
+                    text += &format!("    {}\n", ca65_text(instruction, &names));
+                }
+                offset += instruction.bytes.len();
+                continue;
+            }
+
+            // Data up to the next label or instruction
+            let mut end = offset + 1;
+            while end < self.program.len()
+                && end - offset < BYTES_PER_ROW
+                && !self.code[end]
+                && !self.is_labelled(PROGRAM_START + end as u16)
+            {
+                end += 1;
+            }
+            let bytes: Vec<String> = self.program[offset..end].iter().map(|byte| format!("${:02X}", byte)).collect();
+            text += &format!("    .byte {}\n", bytes.join(", "));
+            offset = end;
+        }
+        text
+    }
+}
+
+// ca65 assembles absolute operands below $0100 as zero page ones unless
+// told otherwise with "a:"
+fn ca65_text(instruction: &Instruction, labels: &Labels) -> String {
+    let text = instruction.labelled_text(labels);
+    let jump = matches!(instruction.mnemonic, "JMP" | "JSR");
+    let absolute = instruction.bytes.len() == 3 && instruction.bytes[2] == 0 && !jump;
+    match (absolute, text.find('$')) {
+        (true, Some(start)) => format!("{}a:{}", &text[..start], &text[start..]),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_follows_code_and_leaves_data() {
+        // JSR $0609; BEQ $0608; JMP $0600; RTS; $0609: LDA $0010; RTS; .byte $ff, $42
+        let program = [
+            0x20, 0x09, 0x06, 0xf0, 0x03, 0x4c, 0x00, 0x06, 0x60, 0xad, 0x10, 0x00, 0x60, 0xff, 0x42,
+        ];
+        let listing = Listing::build(&program, &[PROGRAM_START], None);
+        assert!(listing.is_code(0x060c));
+        assert!(!listing.is_code(0x060d));
+
+        let mut labels = Labels::new();
+        labels.insert(0x0609, "load");
+        assert_eq!(
+            listing.to_ca65(&labels),
+            "; Recursive-traversal disassembly\n.setcpu \"6502\"\n.org $0600\n\n\
+             L0600:\n    JSR load\n    BEQ L0608\n    JMP L0600\n\
+             L0608:\n    RTS\n\
+             load:\n    LDA a:$0010\n    RTS\n    .byte $FF, $42\n"
+        );
+    }
+
+    #[test]
+    fn test_cdl_adds_code_and_marks_data() {
+        // JMP ($0010); LDA #$01; RTS
+        let program = [0x6c, 0x10, 0x00, 0xa9, 0x01, 0x60];
+        assert!(!Listing::build(&program, &[PROGRAM_START], None).is_code(0x0603));
+
+        let cdl = [CDL_CODE, CDL_CODE, CDL_CODE, CDL_CODE, CDL_CODE, CDL_DATA];
+        let listing = Listing::build(&program, &[PROGRAM_START], Some(&cdl));
+        assert!(listing.is_code(0x0603));
+        assert!(!listing.is_code(0x0605));
+    }
+
+    #[test]
+    fn test_unofficial_opcodes_are_bytes() {
+        // LAX $10; SBC #$01 (the unofficial $EB); NOP (the unofficial $1A); RTS
+        let program = [0xa7, 0x10, 0xeb, 0x01, 0x1a, 0x60];
+        assert_eq!(
+            Listing::build(&program, &[PROGRAM_START], None).to_ca65(&Labels::new()),
+            "; Recursive-traversal disassembly\n.setcpu \"6502\"\n.org $0600\n\n\
+             L0600:\n    .byte $A7, $10 ; LAX $10\n    .byte $EB, $01 ; SBC #$01\n    .byte $1A ; NOP\n    RTS\n"
+        );
+    }
+}
@@ -1,144 +1,912 @@
-pub mod cpu;
-pub mod bus;
-pub mod opcodes;
+mod capture;
+mod config;
+mod display;
+mod monitor;
+mod pacing;
+mod slots;
+mod tui;
 
-use cpu::Mem;
-use cpu::CPU;
-use rand::Rng;
-use bus::Bus;
+use enes::cpu::CPU;
+use enes::filter::FilterChain;
+use enes::error::EmulationError;
+use enes::labels::Labels;
+use enes::nes::{self, Nes, INSTRUCTIONS_PER_FRAME};
+use enes::coverage::Coverage;
+use enes::crashreport::{self, RecentInstructions};
+use enes::heatmap::Heatmap;
+use enes::listing::{self, Listing};
+use enes::metrics::{FrameTiming, Metrics};
+use enes::profiler::Profiler;
+use enes::replay::{InputLog, Session};
+use enes::rominfo::RomInfo;
+use enes::snake::SNAKE_CODE;
+use enes::stackcheck::StackChecker;
+use enes::stats::InstructionStats;
+use enes::tracediff::TraceChecker;
+use enes::unofficial::UnofficialOpcodes;
+use enes::trace::Tracer;
+use capture::{GifRecorder, VideoDumper};
+use config::{Command, Config, ConfigWatcher, InputProfile, ResumeMode};
+use display::DisplaySettings;
+use pacing::{FramePacer, PacingMode, SpeedControl};
+use slots::{AutoSave, SaveSlots};
 
 use sdl2::event::Event;
 use sdl2::EventPump;
 use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
 use sdl2::pixels::PixelFormatEnum;
-use std::time::Duration;
+use sdl2::render::WindowCanvas;
+use std::fs::File;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
 
-#[macro_use]
-extern crate lazy_static;
+// Save the session every minute, so not much is lost if the emulator crashes
+const AUTOSAVE_INTERVAL_FRAMES: u64 = 60 * 60;
 
+// How often the performance metrics in the title are refreshed
+const METRICS_INTERVAL_FRAMES: u64 = 30;
 
-fn color(byte: u8) -> Color {
-    match byte {
-        0 => sdl2::pixels::Color::BLACK,
-        1 => sdl2::pixels::Color::WHITE,
-        2 | 9 => sdl2::pixels::Color::GREY,
-        3 | 10 => sdl2::pixels::Color::RED,
-        4 | 11 => sdl2::pixels::Color::GREEN,
-        5 | 12 => sdl2::pixels::Color::BLUE,
-        6 | 13 => sdl2::pixels::Color::MAGENTA,
-        7 | 14 => sdl2::pixels::Color::YELLOW,
-        _ => sdl2::pixels::Color::CYAN,
+// How often the config file is checked for changes
+const CONFIG_CHECK_INTERVAL_FRAMES: u64 = 60;
+
+// Stack problems printed by --stack-check before it goes quiet, a broken
+// stack tends to report the same problem over and over
+const MAX_STACK_REPORTS: u32 = 20;
+
+// Instructions shown in a crash report
+const CRASH_TRACE_LINES: usize = 1000;
+
+
+fn save_screenshot(screen_state: &[u8]) {
+    let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+    let path = PathBuf::from(format!("screenshot_{}.png", timestamp));
+    match capture::save_png(&path, screen_state, 32, 32) {
+        Ok(()) => println!("Screenshot saved to {}", path.display()),
+        Err(e) => println!("Could not save screenshot to {}: {}", path.display(), e),
+    }
+}
+
+
+// Recordings started from the frontend, closed properly before exiting.
+struct Capture {
+    gif: Option<GifRecorder>,
+    video: Option<VideoDumper>,
+}
+
+impl Capture {
+    fn finish(&mut self) {
+        if self.gif.is_some() {
+            toggle_gif_recording(self);
+        }
+        if let Some(video) = self.video.take() {
+            if let Err(e) = video.finish() {
+                println!("Could not finish video dump: {}", e);
+            }
+        }
+    }
+}
+
+
+// Tools that look at every instruction before it runs, reported when the
+// emulator exits.
+struct Instruments {
+    // For crash reports
+    recent: RecentInstructions,
+    trace: Option<Tracer>,
+    profile: Option<Profile>,
+    heatmap: Option<(PathBuf, Heatmap)>,
+    stats: Option<(PathBuf, InstructionStats)>,
+    // With the addresses of the program
+    coverage: Option<(PathBuf, Coverage, Range<u16>)>,
+    stack_check: Option<StackChecker>,
+    stack_reports: u32,
+    unofficial: Option<UnofficialOpcodes>,
+}
+
+struct Profile {
+    path: PathBuf,
+    profiler: Profiler,
+    labels: Labels,
+}
+
+impl Instruments {
+    fn new() -> Self {
+        Instruments {
+            recent: RecentInstructions::new(CRASH_TRACE_LINES),
+            trace: None,
+            profile: None,
+            heatmap: None,
+            stats: None,
+            coverage: None,
+            stack_check: None,
+            stack_reports: 0,
+            unofficial: None,
+        }
+    }
+
+    fn before_instruction(&mut self, cpu: &CPU) {
+        self.recent.record(cpu);
+        if let Some(tracer) = self.trace.as_mut() {
+            if let Err(e) = tracer.trace(cpu) {
+                println!("Tracing stopped: {}", e);
+                self.trace = None;
+            }
+        }
+        if let Some(profile) = self.profile.as_mut() {
+            profile.profiler.record(cpu);
+        }
+        if let Some((_, heatmap)) = self.heatmap.as_mut() {
+            heatmap.record(cpu.program_counter);
+        }
+        if let Some((_, stats)) = self.stats.as_mut() {
+            stats.record(cpu);
+        }
+        if let Some((_, coverage, _)) = self.coverage.as_mut() {
+            coverage.record(cpu);
+        }
+        if let Some(issue) = self.stack_check.as_mut().and_then(|checker| checker.check(cpu)) {
+            self.stack_reports += 1;
+            println!("{}", issue);
+            if self.stack_reports == MAX_STACK_REPORTS {
+                println!("Too many stack problems, no longer checking the stack");
+                self.stack_check = None;
+            }
+        }
+        if let Some(instruction) = self.unofficial.as_mut().and_then(|unofficial| unofficial.check(cpu)) {
+            println!(
+                "Unofficial opcode ${:02X} at ${:04X}: {}",
+                instruction.bytes[0],
+                instruction.address,
+                instruction.text()
+            );
+        }
+    }
+
+    fn finish(&mut self) {
+        if let Some(trace) = self.trace.take() {
+            if let Err(e) = trace.finish() {
+                println!("Could not finish trace: {}", e);
+            }
+        }
+        if let Some(profile) = self.profile.take() {
+            match std::fs::write(&profile.path, profile.profiler.report(&profile.labels)) {
+                Ok(()) => println!("Profile written to {}", profile.path.display()),
+                Err(e) => println!("Could not write profile to {}: {}", profile.path.display(), e),
+            }
+        }
+        if let Some((path, heatmap)) = self.heatmap.take() {
+            let result = if path.extension().is_some_and(|ext| ext == "png") {
+                capture::save_png(&path, &heatmap.to_rgb(), 256, 256)
+            } else {
+                std::fs::write(&path, heatmap.to_csv())
+            };
+            match result {
+                Ok(()) => println!("Heatmap written to {}", path.display()),
+                Err(e) => println!("Could not write heatmap to {}: {}", path.display(), e),
+            }
+        }
+        if let Some((path, stats)) = self.stats.take() {
+            // Addressing modes go after the opcodes, separated by an empty line
+            let mut csv = stats.to_csv();
+            csv += "\nmode,count\n";
+            for (mode, count) in stats.by_mode() {
+                csv += &format!("{},{}\n", mode, count);
+            }
+            match std::fs::write(&path, csv) {
+                Ok(()) => println!("Opcode statistics written to {}", path.display()),
+                Err(e) => println!("Could not write opcode statistics to {}: {}", path.display(), e),
+            }
+        }
+        if let Some((path, coverage, program)) = self.coverage.take() {
+            let report = if path.extension().is_some_and(|ext| ext == "csv") {
+                coverage.to_csv()
+            } else {
+                coverage.report(program)
+            };
+            match std::fs::write(&path, report) {
+                Ok(()) => println!("Memory coverage written to {}", path.display()),
+                Err(e) => println!("Could not write memory coverage to {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+
+// Runs one frame of the game with the instruments looking at every
+// instruction
+fn run_frame(nes: &mut Nes, instruments: &mut Instruments) -> Result<bool, EmulationError> {
+    nes.run_frame_with(|cpu| instruments.before_instruction(cpu))
+}
+
+
+fn toggle_gif_recording(capture: &mut Capture) {
+    match capture.gif.take() {
+        Some(gif) => match gif.finish() {
+            Ok(()) => println!("GIF recording stopped"),
+            Err(e) => println!("Could not finish GIF recording: {}", e),
+        },
+        None => {
+            let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs();
+            let path = PathBuf::from(format!("recording_{}.gif", timestamp));
+            match GifRecorder::new(&path, 32, 32, &nes::DEFAULT_PALETTE) {
+                Ok(gif) => {
+                    println!("Recording GIF to {}", path.display());
+                    capture.gif = Some(gif);
+                }
+                Err(e) => println!("Could not start GIF recording to {}: {}", path.display(), e),
+            }
+        }
+    }
+}
+
+
+// Frontend state that hotkeys can change while the game is running.
+struct Frontend {
+    input: InputProfile,
+    display: DisplaySettings,
+    speed: SpeedControl,
+    capture: Capture,
+    instruments: Instruments,
+    slots: SaveSlots,
+    autosave: AutoSave,
+    input_log: Option<PathBuf>,
+    redraw: bool,
+    paused: bool,
+    advance_frame: bool,
+    show_metrics: bool,
+    metrics: Metrics,
+}
+
+
+fn slot_number(key: Keycode) -> Option<u8> {
+    match key {
+        Keycode::Num0 => Some(0),
+        Keycode::Num1 => Some(1),
+        Keycode::Num2 => Some(2),
+        Keycode::Num3 => Some(3),
+        Keycode::Num4 => Some(4),
+        Keycode::Num5 => Some(5),
+        Keycode::Num6 => Some(6),
+        Keycode::Num7 => Some(7),
+        Keycode::Num8 => Some(8),
+        Keycode::Num9 => Some(9),
+        _ => None,
+    }
+}
+
+
+fn should_resume(mode: ResumeMode) -> bool {
+    match mode {
+        ResumeMode::Always => true,
+        ResumeMode::Never => false,
+        ResumeMode::Ask => {
+            print!("Resume where you left off last time? [Y/n] ");
+            std::io::stdout().flush().unwrap();
+            let mut answer = String::new();
+            std::io::stdin().read_line(&mut answer).unwrap();
+            !answer.trim().eq_ignore_ascii_case("n")
+        }
+    }
+}
+
+
+fn update_title(canvas: &mut WindowCanvas, frontend: &Frontend) {
+    let mut title = if frontend.paused { "eNES (paused)" } else { "eNES" }.to_string();
+    if let Some(summary) = frontend.metrics.summary().filter(|_| frontend.show_metrics) {
+        title.push_str(&format!(
+            " - {:.1} fps, {:.0}% speed, emulation {:.2} ms, render {:.2} ms",
+            summary.fps,
+            summary.speed,
+            summary.emulation.as_secs_f64() * 1000.0,
+            summary.render.as_secs_f64() * 1000.0
+        ));
+    }
+    canvas.window_mut().set_title(&title).unwrap();
+}
+
+
+// Runs the game headless against a reference trace and reports the first
+// divergence. Returns true if the whole reference matched.
+fn diff_trace(cpu: &mut CPU, mut session: Session, path: &Path) -> bool {
+    let reference = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", path.display(), e);
+        std::process::exit(1)
+    });
+    let mut checker = TraceChecker::new(&reference);
+    loop {
+        if let Err(divergence) = checker.check(cpu) {
+            println!("{}", divergence);
+            return false;
+        }
+        if checker.is_done() {
+            println!("All {} lines of {} match", checker.matched(), path.display());
+            return true;
+        }
+        session.before_instruction(cpu);
+        if !cpu.step() {
+            println!("BRK executed after {} matching lines, before the end of {}", checker.matched(), path.display());
+            return false;
+        }
     }
 }
 
 
-fn read_screen_state(cpu: &CPU, frame: &mut [u8; 32 * 3 * 32]) -> bool {
-    let mut frame_idx = 0;
-    let mut update = false;
-    for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
-        let (b1, b2, b3) = color(color_idx).rgb();
-        if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
-            frame[frame_idx] = b1;
-            frame[frame_idx + 1] = b2;
-            frame[frame_idx + 2] = b3;
-            update = true;
+// Saves and closes everything before the emulator exits, whichever way it
+// does: the player quitting, the program stopping or a crash
+fn shutdown(nes: &Nes, frontend: &mut Frontend) {
+    if let Err(e) = frontend.autosave.save(nes) {
+        println!("Could not save the session: {}", e);
+    }
+    frontend.capture.finish();
+    frontend.instruments.finish();
+    if let Some(path) = &frontend.input_log {
+        match nes.session().log().save(path) {
+            Ok(()) => println!("Input log written to {}", path.display()),
+            Err(e) => println!("Could not save the input log: {}", e),
         }
-        frame_idx += 3;
     }
-    update
 }
 
 
-fn handle_user_input(cpu: &mut CPU, event_pump: &mut EventPump) {
+fn handle_user_input(nes: &mut Nes, event_pump: &mut EventPump, canvas: &mut WindowCanvas,
+                     frontend: &mut Frontend) {
+    let input = frontend.input;
     for event in event_pump.poll_iter() {
         match event {
             Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. } => {
+                shutdown(nes, frontend);
                 std::process::exit(0)
             },
-            Event::KeyDown { keycode: Some(Keycode::W), .. } => {
-                cpu.mem_write(0xff, 0x77);
+            Event::KeyDown { keycode: Some(key), .. } if key == input.up => {
+                nes.set_input(0x77);
             },
-            Event::KeyDown { keycode: Some(Keycode::S), .. } => {
-                cpu.mem_write(0xff, 0x73);
+            Event::KeyDown { keycode: Some(key), .. } if key == input.down => {
+                nes.set_input(0x73);
             },
-            Event::KeyDown { keycode: Some(Keycode::A), .. } => {
-                cpu.mem_write(0xff, 0x61);
+            Event::KeyDown { keycode: Some(key), .. } if key == input.left => {
+                nes.set_input(0x61);
             },
-            Event::KeyDown { keycode: Some(Keycode::D), .. } => {
-                cpu.mem_write(0xff, 0x64);
+            Event::KeyDown { keycode: Some(key), .. } if key == input.right => {
+                nes.set_input(0x64);
+            }
+            Event::KeyDown { keycode: Some(Keycode::F12), .. } => {
+                save_screenshot(nes.frame());
+            }
+            Event::KeyDown { keycode: Some(Keycode::F9), .. } => {
+                toggle_gif_recording(&mut frontend.capture);
+            }
+            Event::KeyDown { keycode: Some(Keycode::F11), .. } => {
+                frontend.display.fullscreen = !frontend.display.fullscreen;
+                display::apply_fullscreen(canvas, &frontend.display);
+                frontend.redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F10), .. } => {
+                frontend.display.aspect_correction = !frontend.display.aspect_correction;
+                frontend.redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F3), .. } => {
+                frontend.show_metrics = !frontend.show_metrics;
+                update_title(canvas, frontend);
+            }
+            Event::KeyDown { keycode: Some(Keycode::F4), .. } => {
+                frontend.display.input_display = !frontend.display.input_display;
+                frontend.redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F8), .. } => {
+                frontend.display.integer_scaling = !frontend.display.integer_scaling;
+                frontend.redraw = true;
+            }
+            Event::KeyDown { keycode: Some(Keycode::Tab), repeat: false, .. } => {
+                frontend.speed.fast_forward = true;
+            }
+            Event::KeyUp { keycode: Some(Keycode::Tab), .. } => {
+                frontend.speed.fast_forward = false;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F6), .. } => {
+                frontend.speed.slow_motion = !frontend.speed.slow_motion;
+            }
+            Event::KeyDown { keycode: Some(Keycode::F5), .. } => {
+                match frontend.slots.save(nes) {
+                    Ok(path) => println!("State saved to {}", path.display()),
+                    Err(e) => println!("Could not save state: {}", e),
+                }
+            }
+            Event::KeyDown { keycode: Some(Keycode::F7), .. } => {
+                match frontend.slots.load(nes) {
+                    Ok(path) => {
+                        println!("State loaded from {}", path.display());
+                        // The log can only be replayed from power on
+                        if frontend.input_log.take().is_some() {
+                            println!("Input recording stopped because a state was loaded");
+                        }
+                    }
+                    Err(e) => println!("Could not load state: {}", e),
+                }
+            }
+            Event::KeyDown { keycode: Some(key), .. } if slot_number(key).is_some() => {
+                frontend.slots.current = slot_number(key).unwrap();
+                println!("Save slot {} selected", frontend.slots.current);
+            }
+            Event::KeyDown { keycode: Some(Keycode::P), .. } => {
+                frontend.paused = !frontend.paused;
+                update_title(canvas, frontend);
+            }
+            Event::KeyDown { keycode: Some(Keycode::N), .. } => {
+                // Pauses first if the game is running, then advances one frame per press
+                if frontend.paused {
+                    frontend.advance_frame = true;
+                } else {
+                    frontend.paused = true;
+                    update_title(canvas, frontend);
+                }
+            }
+            Event::Window { .. } => {
+                frontend.redraw = true;
             }
             _ => {/* do nothing */}
         }
     }
 }
 
+fn export_disassembly(rom: &Path, cdl: Option<&Path>, labels: Option<&Path>, output: Option<&Path>) -> Result<(), String> {
+    let program = nes::read_rom(rom).map_err(|e| e.to_string())?;
+    let cdl = match cdl {
+        Some(path) => Some(std::fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?),
+        None => None,
+    };
+    let labels = match labels {
+        Some(path) => Labels::load(path)?,
+        None => Labels::new(),
+    };
+    let listing = Listing::build(&program, &[listing::PROGRAM_START], cdl.as_deref()).to_ca65(&labels);
+    match output {
+        Some(path) => std::fs::write(path, listing).map_err(|e| format!("Could not write {}: {}", path.display(), e)),
+        None => {
+            print!("{}", listing);
+            Ok(())
+        }
+    }
+}
 
 fn main() {
+    let config = Config::load().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+
+    if let Some(Command::RomInfo { rom }) = &config.command {
+        let data = std::fs::read(rom).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", rom.display(), e);
+            std::process::exit(1)
+        });
+        let info = RomInfo::inspect(&data);
+        print!("{}", info);
+        std::process::exit(if info.problem.is_none() { 0 } else { 1 });
+    }
+    if let Some(Command::Disasm { rom, cdl, labels, output }) = &config.command {
+        if let Err(e) = export_disassembly(rom, cdl.as_deref(), labels.as_deref(), output.as_deref()) {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+        return;
+    }
+
+    // For crash reports, before parts of the config are moved out
+    let config_text = format!("{:#?}", config);
+    let crash_dir = config.crash_dir.clone();
+
+    //load the game
+    let game_code = match &config.rom {
+        Some(path) => nes::read_rom(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }),
+        None => SNAKE_CODE.to_vec(),
+    };
+    let game_name = match &config.rom {
+        Some(path) => path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+        None => "snake".to_string(),
+    };
+    let autosave = AutoSave::new(&config.state_dir, &game_code, config.state_compression);
+    let slots = SaveSlots::new(config.state_dir, &game_name, &game_code, config.state_compression);
+    let seed = config.seed.unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos() as u64);
+    // The programs loaded at $0600 end on BRK, there's nothing at $FFFE
+    let mut builder = Nes::builder().seed(seed).ram_pattern(config.ram_pattern).stop_on_brk(true);
+    for code in config.game_genie.iter().chain(&config.freezes) {
+        builder = builder.cheat(code);
+    }
+    let mut nes = builder.build().unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+    if let Err(e) = nes.load_rom(&game_code) {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    }
+    if let Some(path) = &config.replay {
+        if let Err(e) = InputLog::load(path).and_then(|log| nes.replay(log)) {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }
+    }
+    let program = 0x0600..0x0600 + game_code.len() as u16;
+
+    let labels = match &config.labels {
+        Some(path) => Labels::load(path).unwrap_or_else(|e| {
+            eprintln!("{}", e);
+            std::process::exit(1)
+        }),
+        None => Labels::new(),
+    };
+
+    if config.debug {
+        let (cpu, session) = nes.into_parts();
+        monitor::run(cpu, labels, session);
+        return;
+    }
+    if let Some(path) = &config.diff_trace {
+        let (mut cpu, session) = nes.into_parts();
+        let matched = diff_trace(&mut cpu, session, path);
+        std::process::exit(if matched { 0 } else { 1 });
+    }
+    if config.tui {
+        let (cpu, session) = nes.into_parts();
+        if let Err(e) = tui::run(cpu, labels, session) {
+            println!("Terminal debugger failed: {}", e);
+        }
+        return;
+    }
+
+    // A replay has to start from power on, and so does a recording to be replayable
+    let mut input_log = config.record_input;
+    if !nes.session().is_replaying() && autosave.exists() && should_resume(config.resume) {
+        match autosave.load(&mut nes) {
+            Ok(()) => {
+                if input_log.take().is_some() {
+                    println!("Not recording input, the last session was resumed");
+                }
+            }
+            Err(e) => println!("Could not resume the last session: {}", e),
+        }
+    }
+
     // init sdl2
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
     let window = video_subsystem
-        .window("eNES", (32.0 * 10.0) as u32, (32.0 * 10.0) as u32)
+        .window("eNES", (32.0 * config.scale as f64 * config.display.pixel_width()) as u32, 32 * config.scale)
         .position_centered()
+        .resizable()
         .build().unwrap();
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut canvas = match config.pacing {
+        PacingMode::Vsync => window.into_canvas().present_vsync().build().unwrap(),
+        _ => window.into_canvas().build().unwrap(),
+    };
     let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
+    display::apply_fullscreen(&mut canvas, &config.display);
 
+    let mut watcher = config.path.clone().map(ConfigWatcher::new);
+    let mut filter_list = config.filters.clone();
+    let mut filters = FilterChain::new(&filter_list, 32, 32);
+    let (mut texture_width, texture_height) = filters.output_size();
     let creator = canvas.texture_creator();
     let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 32, 32).unwrap();
-
-    let game_code = vec![
-        0x20, 0x06, 0x06, 0x20, 0x38, 0x06, 0x20, 0x0d, 0x06, 0x20, 0x2a, 0x06, 0x60, 0xa9, 0x02, 0x85,
-        0x02, 0xa9, 0x04, 0x85, 0x03, 0xa9, 0x11, 0x85, 0x10, 0xa9, 0x10, 0x85, 0x12, 0xa9, 0x0f, 0x85,
-        0x14, 0xa9, 0x04, 0x85, 0x11, 0x85, 0x13, 0x85, 0x15, 0x60, 0xa5, 0xfe, 0x85, 0x00, 0xa5, 0xfe,
-        0x29, 0x03, 0x18, 0x69, 0x02, 0x85, 0x01, 0x60, 0x20, 0x4d, 0x06, 0x20, 0x8d, 0x06, 0x20, 0xc3,
-        0x06, 0x20, 0x19, 0x07, 0x20, 0x20, 0x07, 0x20, 0x2d, 0x07, 0x4c, 0x38, 0x06, 0xa5, 0xff, 0xc9,
-        0x77, 0xf0, 0x0d, 0xc9, 0x64, 0xf0, 0x14, 0xc9, 0x73, 0xf0, 0x1b, 0xc9, 0x61, 0xf0, 0x22, 0x60,
-        0xa9, 0x04, 0x24, 0x02, 0xd0, 0x26, 0xa9, 0x01, 0x85, 0x02, 0x60, 0xa9, 0x08, 0x24, 0x02, 0xd0,
-        0x1b, 0xa9, 0x02, 0x85, 0x02, 0x60, 0xa9, 0x01, 0x24, 0x02, 0xd0, 0x10, 0xa9, 0x04, 0x85, 0x02,
-        0x60, 0xa9, 0x02, 0x24, 0x02, 0xd0, 0x05, 0xa9, 0x08, 0x85, 0x02, 0x60, 0x60, 0x20, 0x94, 0x06,
-        0x20, 0xa8, 0x06, 0x60, 0xa5, 0x00, 0xc5, 0x10, 0xd0, 0x0d, 0xa5, 0x01, 0xc5, 0x11, 0xd0, 0x07,
-        0xe6, 0x03, 0xe6, 0x03, 0x20, 0x2a, 0x06, 0x60, 0xa2, 0x02, 0xb5, 0x10, 0xc5, 0x10, 0xd0, 0x06,
-        0xb5, 0x11, 0xc5, 0x11, 0xf0, 0x09, 0xe8, 0xe8, 0xe4, 0x03, 0xf0, 0x06, 0x4c, 0xaa, 0x06, 0x4c,
-        0x35, 0x07, 0x60, 0xa6, 0x03, 0xca, 0x8a, 0xb5, 0x10, 0x95, 0x12, 0xca, 0x10, 0xf9, 0xa5, 0x02,
-        0x4a, 0xb0, 0x09, 0x4a, 0xb0, 0x19, 0x4a, 0xb0, 0x1f, 0x4a, 0xb0, 0x2f, 0xa5, 0x10, 0x38, 0xe9,
-        0x20, 0x85, 0x10, 0x90, 0x01, 0x60, 0xc6, 0x11, 0xa9, 0x01, 0xc5, 0x11, 0xf0, 0x28, 0x60, 0xe6,
-        0x10, 0xa9, 0x1f, 0x24, 0x10, 0xf0, 0x1f, 0x60, 0xa5, 0x10, 0x18, 0x69, 0x20, 0x85, 0x10, 0xb0,
-        0x01, 0x60, 0xe6, 0x11, 0xa9, 0x06, 0xc5, 0x11, 0xf0, 0x0c, 0x60, 0xc6, 0x10, 0xa5, 0x10, 0x29,
-        0x1f, 0xc9, 0x1f, 0xf0, 0x01, 0x60, 0x4c, 0x35, 0x07, 0xa0, 0x00, 0xa5, 0xfe, 0x91, 0x00, 0x60,
-        0xa6, 0x03, 0xa9, 0x00, 0x81, 0x10, 0xa2, 0x00, 0xa9, 0x01, 0x81, 0x10, 0x60, 0xa2, 0x00, 0xea,
-        0xea, 0xca, 0xd0, 0xfb, 0x60
-    ];
-
-    //load the game
-    let bus = Bus::new();
-    let mut cpu = CPU::new(bus);
-    cpu.load(game_code);
-    cpu.reset();
-    cpu.program_counter = 0x0600;
+        .create_texture_target(PixelFormatEnum::RGB24, texture_width as u32, texture_height as u32).unwrap();
 
+    // The frame on the texture, to only update it when the game draws
     let mut screen_state = [0 as u8; 32 * 3 * 32];
-    let mut rng = rand::thread_rng();
+    let mut fps = config.fps;
+    let video = config.dump_video.map(|path| {
+        VideoDumper::new(&path, 32, 32, fps)
+            .unwrap_or_else(|e| panic!("Could not open {} for video dump: {}", path.display(), e))
+    });
+    let mut instruments = Instruments::new();
+    instruments.trace = config.trace.map(|settings| {
+        let out: Box<dyn Write + Send> = if settings.path.as_os_str() == "-" {
+            Box::new(std::io::stdout())
+        } else {
+            Box::new(File::create(&settings.path)
+                .unwrap_or_else(|e| panic!("Could not open {} for tracing: {}", settings.path.display(), e)))
+        };
+        Tracer::new(out, &settings.fields, settings.ranges, labels.clone())
+    });
+    instruments.heatmap = config.heatmap.map(|path| (path, Heatmap::new()));
+    instruments.stats = config.opcode_stats.map(|path| (path, InstructionStats::new()));
+    instruments.coverage = config.coverage.map(|path| (path, Coverage::new(), program));
+    instruments.profile = config.profile.map(|path| Profile {
+        path,
+        profiler: Profiler::new(),
+        labels: labels.clone(),
+    });
+    if config.stack_check {
+        instruments.stack_check = Some(StackChecker::new());
+    }
+    if config.log_unofficial {
+        instruments.unofficial = Some(UnofficialOpcodes::new());
+    }
+    let mut frontend = Frontend {
+        input: config.input,
+        display: config.display,
+        speed: config.speed,
+        capture: Capture { gif: None, video },
+        instruments,
+        slots,
+        autosave,
+        input_log,
+        redraw: true,
+        paused: false,
+        advance_frame: false,
+        show_metrics: config.show_metrics,
+        metrics: Metrics::new(fps, INSTRUCTIONS_PER_FRAME),
+    };
+    let pacing = config.pacing;
+    let mut pacer = FramePacer::new(pacing, fps);
+    let mut frame_budget = INSTRUCTIONS_PER_FRAME;
+    let mut frame_count: u64 = 0;
+    let mut desync_reported = false;
+    let mut shown_input = 0;
 
     // run the game cycle
-    cpu.run_with_callback(move |cpu| {
-        handle_user_input(cpu, &mut event_pump);
+    loop {
+        let frame_start = Instant::now();
+        nes.set_instructions_per_frame(frame_budget);
+        match run_frame(&mut nes, &mut frontend.instruments) {
+            Ok(true) => {}
+            Ok(false) => {
+                println!("The program stopped at ${:04X}", nes.cpu().program_counter);
+                shutdown(&nes, &mut frontend);
+                return;
+            }
+            Err(e) => {
+                println!("Emulation stopped: {}", e);
+                match crashreport::write(&crash_dir, &e.to_string(), nes.cpu(), &frontend.instruments.recent, &game_code, &config_text) {
+                    Ok(path) => println!("Crash report written to {}, please attach it to bug reports", path.display()),
+                    Err(e) => println!("Could not write a crash report: {}", e),
+                }
+                shutdown(&nes, &mut frontend);
+                std::process::exit(1);
+            }
+        }
+        frame_count += 1;
+
+        if !desync_reported {
+            if let Some(instruction) = nes.session().desync() {
+                println!("The replay no longer matches the recording, since instruction {}", instruction);
+                desync_reported = true;
+            }
+        }
 
-        cpu.mem_write(0xfe, rng.gen_range(1, 16));
+        let emulation = frame_start.elapsed();
+        handle_user_input(&mut nes, &mut event_pump, &mut canvas, &mut frontend);
 
-        if read_screen_state(cpu, &mut screen_state) {
-            texture.update(None, &screen_state, 32 * 3).unwrap();
+        let render_start = Instant::now();
+        if nes.frame() != &screen_state[..] {
+            screen_state.copy_from_slice(nes.frame());
+            texture.update(None, filters.apply(&screen_state), texture_width * 3).unwrap();
 
-            canvas.copy(&texture, None, None).unwrap();
+            if let Some(gif) = frontend.capture.gif.as_mut() {
+                if let Err(e) = gif.add_frame(&screen_state) {
+                    println!("GIF recording stopped: {}", e);
+                    frontend.capture.gif = None;
+                }
+            }
 
-            canvas.present();
+            frontend.redraw = true;
         }
 
-        ::std::thread::sleep(Duration::new(0, 70_000));
-    });
+        let input = nes.cpu().bus.peek(0xff);
+        if frontend.display.input_display && input != shown_input {
+            shown_input = input;
+            frontend.redraw = true;
+        }
+
+        if frontend.redraw || pacer.present_every_frame() {
+            display::present(&mut canvas, &texture, &frontend.display, 32, 32, input);
+            frontend.redraw = false;
+        }
+
+        if let Some(video) = frontend.capture.video.as_mut() {
+            if let Err(e) = video.add_frame(&screen_state) {
+                println!("Video dump stopped: {}", e);
+                frontend.capture.video = None;
+            }
+        }
+
+        frontend.metrics.record(FrameTiming {
+            emulation,
+            render: render_start.elapsed(),
+            instructions: frame_budget,
+        });
+        if frontend.show_metrics && frame_count.is_multiple_of(METRICS_INTERVAL_FRAMES) {
+            update_title(&mut canvas, &frontend);
+        }
+
+        let config_changed = frame_count.is_multiple_of(CONFIG_CHECK_INTERVAL_FRAMES)
+            && watcher.as_mut().is_some_and(|watcher| watcher.changed());
+        if config_changed {
+            match Config::load() {
+                Ok(config) => {
+                    frontend.input = config.input;
+                    if config.display.fullscreen != frontend.display.fullscreen {
+                        display::apply_fullscreen(&mut canvas, &config.display);
+                    }
+                    frontend.display = config.display;
+                    frontend.speed.fast_forward_speed = config.speed.fast_forward_speed;
+                    frontend.speed.slow_motion_speed = config.speed.slow_motion_speed;
+                    frontend.show_metrics = config.show_metrics;
+                    if config.filters != filter_list {
+                        filter_list = config.filters;
+                        filters = FilterChain::new(&filter_list, 32, 32);
+                        let (width, height) = filters.output_size();
+                        texture_width = width;
+                        texture = creator
+                            .create_texture_target(PixelFormatEnum::RGB24, width as u32, height as u32).unwrap();
+                        texture.update(None, filters.apply(&screen_state), texture_width * 3).unwrap();
+                    }
+                    // The pacing mode can't change, a vsynced canvas is chosen when it's created
+                    if config.fps != fps {
+                        fps = config.fps;
+                        pacer = FramePacer::new(pacing, fps);
+                        frontend.metrics = Metrics::new(fps, INSTRUCTIONS_PER_FRAME);
+                    }
+                    update_title(&mut canvas, &frontend);
+                    frontend.redraw = true;
+                    println!("Config reloaded");
+                }
+                Err(e) => println!("Config not reloaded: {}", e),
+            }
+        }
+
+        if frame_count.is_multiple_of(AUTOSAVE_INTERVAL_FRAMES) {
+            if let Err(e) = frontend.autosave.save(&nes) {
+                println!("Could not save the session: {}", e);
+            }
+        }
+
+        pacer.wait();
+
+        // While paused keep the window responsive until unpaused or asked to
+        // run exactly one more frame
+        while frontend.paused && !frontend.advance_frame {
+            handle_user_input(&mut nes, &mut event_pump, &mut canvas, &mut frontend);
+            if frontend.redraw || pacer.present_every_frame() {
+                display::present(&mut canvas, &texture, &frontend.display, 32, 32, nes.cpu().bus.peek(0xff));
+                frontend.redraw = false;
+            }
+            pacer.wait();
+        }
+        frontend.advance_frame = false;
+
+        frame_budget = (INSTRUCTIONS_PER_FRAME as f64 * frontend.speed.multiplier()).round().max(1.0) as u32;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use enes::filter::Filter;
+    use std::alloc::{GlobalAlloc, Layout, System};
+    use std::cell::Cell;
+
+    // Counts the allocations of each thread, so that tests running in
+    // parallel don't see each other's
+    struct CountingAllocator;
+
+    thread_local! {
+        static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    }
+
+    unsafe impl GlobalAlloc for CountingAllocator {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.alloc(layout)
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            System.dealloc(ptr, layout)
+        }
+
+        unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+            let _ = ALLOCATIONS.try_with(|count| count.set(count.get() + 1));
+            System.realloc(ptr, layout, new_size)
+        }
+    }
+
+    #[global_allocator]
+    static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+    fn allocations() -> usize {
+        ALLOCATIONS.with(|count| count.get())
+    }
+
+    // CRC32 of the screen after these many frames of the snake game, started
+    // with seed 1 and turned down at frame 20. A mismatch means the emulation
+    // changed; update the values only if that was intended.
+    const GOLDEN_FRAMES: [(u64, u32); 3] = [(1, 0x3530d53d), (30, 0xe2b37362), (90, 0xa68ea129)];
+
+    #[test]
+    fn test_snake_frames_match_goldens() {
+        let mut nes = Nes::new(1);
+        nes.load_rom(SNAKE_CODE).unwrap();
+
+        for frame in 1..=GOLDEN_FRAMES[GOLDEN_FRAMES.len() - 1].0 {
+            if frame == 20 {
+                nes.set_input(0x73);
+            }
+            assert_eq!(nes.run_frame(), Ok(true), "the game ended at frame {}", frame);
+            let screen_state = nes.frame();
+
+            if let Some(&(_, golden)) = GOLDEN_FRAMES.iter().find(|&&(golden_frame, _)| golden_frame == frame) {
+                let crc = crc32fast::hash(screen_state);
+                if crc != golden {
+                    // Keep the frame around to look at what changed
+                    let path = std::env::temp_dir().join(format!("enes_snake_frame_{}.png", frame));
+                    capture::save_png(&path, screen_state, 32, 32).unwrap();
+                    panic!("Frame {} has CRC {:08x}, expected {:08x}, see {}", frame, crc, golden, path.display());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_frame_loop_does_not_allocate() {
+        let mut nes = Nes::builder().seed(1).cheat("0700:05").build().unwrap();
+        nes.load_rom(SNAKE_CODE).unwrap();
+        let mut instruments = Instruments::new();
+        instruments.heatmap = Some((PathBuf::new(), Heatmap::new()));
+        instruments.coverage = Some((PathBuf::new(), Coverage::new(), 0x0600..0x0600 + SNAKE_CODE.len() as u16));
+        instruments.stack_check = Some(StackChecker::new());
+        let mut filters = FilterChain::new(&[Filter::Scanlines, Filter::Glow, Filter::Curvature], 32, 32);
+        let mut screen_state = [0u8; 32 * 3 * 32];
+
+        // The input log of a recording grows as keys and checkpoints are
+        // pushed, the only allocations allowed
+        let log_capacity = |nes: &Nes| {
+            let log = nes.session().log();
+            [log.events.capacity(), log.checkpoints.capacity()]
+        };
+
+        // What the frontend does every frame, without the window. Returns the
+        // number of times the input log grew.
+        let mut frame = |nes: &mut Nes, instruments: &mut Instruments| {
+            let capacity = log_capacity(nes);
+            assert_eq!(run_frame(nes, instruments), Ok(true));
+            if nes.frame() != &screen_state[..] {
+                screen_state.copy_from_slice(nes.frame());
+                filters.apply(&screen_state);
+            }
+            capacity.iter().zip(log_capacity(nes)).filter(|(before, after)| **before != *after).count()
+        };
+
+        // Recording, with a key pressed and checkpoints logged on the way. The
+        // first frame lets the lazy statics (the opcode table) initialize.
+        frame(&mut nes, &mut instruments);
+        let before = allocations();
+        let mut log_growth = 0;
+        for i in 0..60 {
+            if i == 20 {
+                let capacity = log_capacity(&nes);
+                nes.set_input(0x73);
+                log_growth += usize::from(capacity != log_capacity(&nes));
+            }
+            log_growth += frame(&mut nes, &mut instruments);
+        }
+        assert_eq!(allocations() - before, log_growth);
+        let log = nes.session().log().clone();
+        assert_eq!(log.events.len(), 1);
+        assert!(log.checkpoints.len() > 1);
+
+        // Replaying what was just recorded
+        nes.replay(log).unwrap();
+        frame(&mut nes, &mut instruments);
+        let before = allocations();
+        for _ in 0..60 {
+            frame(&mut nes, &mut instruments);
+        }
+        assert_eq!(allocations() - before, 0);
+        assert_eq!(nes.session().desync(), None);
+    }
 }
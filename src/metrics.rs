@@ -0,0 +1,124 @@
+use std::time::{Duration, Instant};
+
+// Frames the averages are taken over
+const WINDOW: usize = 60;
+
+// Where the time of one frame went
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FrameTiming {
+    // Running the CPU, including tracing and other per-instruction work
+    pub emulation: Duration,
+    // Converting, filtering and presenting the picture
+    pub render: Duration,
+    // Instructions run in the frame, which changes with the speed setting
+    pub instructions: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub fps: f64,
+    pub emulation: Duration,
+    pub render: Duration,
+    // Emulation speed compared to the real console, 100.0 at full speed
+    pub speed: f64,
+}
+
+// Averages the timings of the last frames. Frontends record one FrameTiming
+// per frame and show the summary:
+//
+//   let mut metrics = Metrics::new(60.0, INSTRUCTIONS_PER_FRAME);
+//   metrics.record(timing);
+//   println!("{:.1} fps", metrics.summary().fps);
+pub struct Metrics {
+    target_fps: f64,
+    instructions_per_frame: u32,
+    timings: [FrameTiming; WINDOW],
+    // When each of the frames above was recorded
+    times: [Option<Instant>; WINDOW],
+    next: usize,
+}
+
+impl Metrics {
+    pub fn new(target_fps: f64, instructions_per_frame: u32) -> Self {
+        Metrics {
+            target_fps,
+            instructions_per_frame,
+            timings: [FrameTiming::default(); WINDOW],
+            times: [None; WINDOW],
+            next: 0,
+        }
+    }
+
+    pub fn record(&mut self, timing: FrameTiming) {
+        self.record_at(timing, Instant::now());
+    }
+
+    fn record_at(&mut self, timing: FrameTiming, now: Instant) {
+        self.timings[self.next] = timing;
+        self.times[self.next] = Some(now);
+        self.next = (self.next + 1) % WINDOW;
+    }
+
+    // None until two frames have been recorded
+    pub fn summary(&self) -> Option<Summary> {
+        let newest = self.times[(self.next + WINDOW - 1) % WINDOW]?;
+        // The oldest recorded frame, the slot about to be overwritten once
+        // the window is full
+        let oldest_index = if self.times[self.next].is_some() { self.next } else { 0 };
+        let oldest = self.times[oldest_index]?;
+        let frames = (self.next + WINDOW - oldest_index - 1) % WINDOW;
+        if frames == 0 {
+            return None;
+        }
+
+        // Time is measured between frames, so the oldest one only opens it
+        let elapsed = (newest - oldest).as_secs_f64();
+        let counted = || (1..=frames).map(|i| &self.timings[(oldest_index + i) % WINDOW]);
+        let instructions: u64 = counted().map(|timing| timing.instructions as u64).sum();
+        let fps = frames as f64 / elapsed;
+        let expected = self.instructions_per_frame as f64 * self.target_fps * elapsed;
+        Some(Summary {
+            fps,
+            emulation: counted().map(|timing| timing.emulation).sum::<Duration>() / frames as u32,
+            render: counted().map(|timing| timing.render).sum::<Duration>() / frames as u32,
+            speed: 100.0 * instructions as f64 / expected,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_summary_averages_recent_frames() {
+        let mut metrics = Metrics::new(50.0, 100);
+        let start = Instant::now();
+        let timing = |instructions, ms| FrameTiming {
+            emulation: Duration::from_millis(ms),
+            render: Duration::from_millis(1),
+            instructions,
+        };
+        metrics.record_at(timing(100, 9), start);
+        assert_eq!(metrics.summary(), None);
+
+        // 25 fps at twice the instructions of a frame: full speed at half
+        // the frame rate
+        for i in 1..=4 {
+            metrics.record_at(timing(200, 4 * i), start + Duration::from_millis(40 * i));
+        }
+        let summary = metrics.summary().unwrap();
+        assert!((summary.fps - 25.0).abs() < 0.001);
+        assert!((summary.speed - 100.0).abs() < 0.001);
+        assert_eq!(summary.emulation, Duration::from_millis(10));
+        assert_eq!(summary.render, Duration::from_millis(1));
+
+        // Only the last frames count once the window is full
+        for i in 5..=200 {
+            metrics.record_at(timing(100, 2), start + Duration::from_millis(40 * i));
+        }
+        let summary = metrics.summary().unwrap();
+        assert!((summary.speed - 50.0).abs() < 0.001);
+        assert_eq!(summary.emulation, Duration::from_millis(2));
+    }
+}
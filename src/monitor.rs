@@ -0,0 +1,418 @@
+use enes::cheats::{CheatSearch, Comparison};
+use enes::cpu::CPU;
+use enes::debugger::{Debugger, StopReason};
+use enes::disasm;
+use enes::expr::Watch;
+use enes::jsonstate;
+use enes::labels::Labels;
+use enes::replay::Session;
+use enes::savestate;
+use enes::statediff;
+use std::io::{self, BufRead, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+const HELP: &str = "\
+Commands (addresses and values are hex, e.g. 0600, $0600 or 0x0600, or label names):
+  s, step [count]         execute instructions, default 1
+  sb, back [count]        step backwards, default 1 instruction
+  c, continue             run until a breakpoint, BRK or Ctrl-C
+  g, go <addr>            run until the PC reaches the address, a breakpoint, BRK or Ctrl-C
+  b, break [addr]         set a breakpoint, or list them without address
+  d, delete <addr>        remove a breakpoint
+  r, regs                 show registers and flags
+  m, mem <addr> [len]     hex dump memory, default 64 bytes
+  u, dis [addr] [count]   disassemble, default from PC, 10 instructions
+  p, poke <addr> <val>..  write bytes to RAM
+  gg [code]               add a Game Genie or address:value code, or list the cheats
+  gg on|off|del <index>   enable, disable or remove a cheat
+  search start            snapshot RAM to look for the address of a value
+  search <cmp>            keep the addresses that changed as given since the
+                          last snapshot: =05, changed, unchanged, inc, dec, +1, -1
+  search                  list the remaining addresses
+  w, watch [name = expr]  show an expression after every step or stop, e.g. 'w lives = ram[$075A]',
+                          or list them; expressions use a x y p sp pc, ram[addr], labels,
+                          decimal or $hex numbers, + - * & | ^ == != < <= > >= && || !
+  uw, unwatch <name>      remove a watch expression
+  diff mark               remember the machine state
+  diff                    show the registers and RAM changed since the mark
+  json save <file> [start-end]..  write the registers and RAM, or the given ranges, as JSON
+  json load <file>        load registers and memory from a JSON file
+  h, help                 show this help
+  q, quit                 leave the debugger";
+
+// Command line monitor reading commands from stdin, for --debug
+pub fn run(mut cpu: CPU, labels: Labels, session: Session) {
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let flag = interrupted.clone();
+    ctrlc::set_handler(move || flag.store(true, Ordering::SeqCst)).expect("Could not install Ctrl-C handler");
+
+    let mut debugger = Debugger::with_session(session);
+    let mut search: Option<CheatSearch> = None;
+    let mut mark: Option<Vec<u8>> = None;
+    let mut watches: Vec<Watch> = Vec::new();
+    println!("eNES debugger, type 'help' for the list of commands");
+    print_registers(&cpu);
+    print_instruction(&cpu, &labels, cpu.program_counter);
+
+    let stdin = io::stdin();
+    loop {
+        print!("> ");
+        io::stdout().flush().unwrap();
+
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            return;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => continue,
+        };
+
+        let result = match command {
+            "s" | "step" => step(&mut cpu, &mut debugger, &labels, args),
+            "sb" | "back" => step_back(&mut cpu, &mut debugger, &labels, args),
+            "c" | "continue" => {
+                interrupted.store(false, Ordering::SeqCst);
+                let reason = debugger.run(&mut cpu, None, |_| interrupted.load(Ordering::SeqCst));
+                report_stop(&cpu, &labels, reason);
+                Ok(())
+            }
+            "g" | "go" => {
+                interrupted.store(false, Ordering::SeqCst);
+                address_arg(&labels, args, 0).map(|address| {
+                    let reason = debugger.run_to(&mut cpu, address, |_| interrupted.load(Ordering::SeqCst));
+                    report_stop(&cpu, &labels, reason);
+                })
+            }
+            "b" | "break" => set_breakpoint(&mut debugger, &labels, args),
+            "d" | "delete" => delete_breakpoint(&mut debugger, &labels, args),
+            "r" | "regs" => {
+                print_registers(&cpu);
+                Ok(())
+            }
+            "m" | "mem" => dump_memory(&cpu, &labels, args),
+            "u" | "dis" => disassemble(&cpu, &labels, args),
+            "p" | "poke" => {
+                // Going back to before the poke would not undo it
+                debugger.clear_history();
+                poke(&mut cpu, &labels, args)
+            }
+            "gg" => game_genie(&mut cpu, args),
+            "search" => search_ram(&cpu, &mut search, args),
+            "diff" => diff_state(&cpu, &mut mark, args),
+            "json" => {
+                // Going back to before a load would not undo it
+                if args.first() == Some(&"load") {
+                    debugger.clear_history();
+                }
+                json_state(&mut cpu, &labels, args)
+            }
+            "w" | "watch" => add_watch(&cpu, &labels, &mut watches, args),
+            "uw" | "unwatch" => {
+                let before = watches.len();
+                watches.retain(|watch| Some(&watch.name.as_str()) != args.first());
+                if watches.len() < before {
+                    Ok(())
+                } else {
+                    Err(format!("No watch named '{}'", args.first().unwrap_or(&"")))
+                }
+            }
+            "h" | "help" => {
+                println!("{}", HELP);
+                Ok(())
+            }
+            "q" | "quit" => return,
+            _ => Err(format!("Unknown command '{}', type 'help' for the list of commands", command)),
+        };
+
+        if let Err(e) = result {
+            println!("{}", e);
+        } else if matches!(command, "s" | "step" | "sb" | "back" | "c" | "continue" | "g" | "go") {
+            print_watches(&cpu, &watches);
+        }
+    }
+}
+
+fn parse_number(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$').trim_start_matches("0x");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex number", text))
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_number(text)?;
+    if value > 0xff {
+        return Err(format!("'{}' does not fit in a byte", text));
+    }
+    Ok(value as u8)
+}
+
+fn address_arg(labels: &Labels, args: &[&str], index: usize) -> Result<u16, String> {
+    let arg = args.get(index).ok_or("Missing address".to_string())?;
+    match labels.address(arg) {
+        Some(address) => Ok(address),
+        None => parse_number(arg),
+    }
+}
+
+fn count_arg(args: &[&str]) -> Result<u64, String> {
+    match args.first() {
+        Some(arg) => arg.parse::<u64>().map_err(|_| format!("'{}' is not a count", arg)),
+        None => Ok(1),
+    }
+}
+
+fn step(cpu: &mut CPU, debugger: &mut Debugger, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let count = count_arg(args)?;
+    if count == 0 {
+        return Ok(());
+    }
+
+    let reason = debugger.run(cpu, Some(count), |_| false);
+    report_stop(cpu, labels, reason);
+    Ok(())
+}
+
+fn step_back(cpu: &mut CPU, debugger: &mut Debugger, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let count = count_arg(args)?;
+    let undone = debugger.step_back(cpu, count)?;
+    if undone < count {
+        println!("Went back {} instructions, the history does not go further", undone);
+    }
+    print_registers(cpu);
+    print_instruction(cpu, labels, cpu.program_counter);
+    Ok(())
+}
+
+fn report_stop(cpu: &CPU, labels: &Labels, reason: StopReason) {
+    match reason {
+        StopReason::Breakpoint(address) => println!("Breakpoint at {}", describe_address(labels, address)),
+        StopReason::Break => println!("BRK executed"),
+        StopReason::Interrupted => println!("Interrupted"),
+        StopReason::Error(e) => println!("{}", e),
+        StopReason::StepsDone => {}
+    }
+    print_registers(cpu);
+    print_instruction(cpu, labels, cpu.program_counter);
+}
+
+// "$0600" or "$0600 (reset)"
+fn describe_address(labels: &Labels, address: u16) -> String {
+    match labels.name(address) {
+        Some(name) => format!("${:04X} ({})", address, name),
+        None => format!("${:04X}", address),
+    }
+}
+
+fn set_breakpoint(debugger: &mut Debugger, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    if args.is_empty() {
+        for &address in debugger.breakpoints() {
+            println!("{}", describe_address(labels, address));
+        }
+        return Ok(());
+    }
+
+    let address = address_arg(labels, args, 0)?;
+    if !debugger.add_breakpoint(address) {
+        return Err(format!("There already is a breakpoint at ${:04X}", address));
+    }
+    Ok(())
+}
+
+fn delete_breakpoint(debugger: &mut Debugger, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let address = address_arg(labels, args, 0)?;
+    if !debugger.remove_breakpoint(address) {
+        return Err(format!("No breakpoint at ${:04X}", address));
+    }
+    Ok(())
+}
+
+fn add_watch(cpu: &CPU, labels: &Labels, watches: &mut Vec<Watch>, args: &[&str]) -> Result<(), String> {
+    if args.is_empty() {
+        for watch in watches.iter() {
+            println!("{}", watch);
+        }
+        return Ok(());
+    }
+
+    let watch = Watch::parse(&args.join(" "), labels)?;
+    // A new expression for an existing name replaces it
+    watches.retain(|other| other.name != watch.name);
+    watches.push(watch);
+    print_watches(cpu, &watches[watches.len() - 1..]);
+    Ok(())
+}
+
+fn print_watches(cpu: &CPU, watches: &[Watch]) {
+    for watch in watches {
+        let value = watch.expr.eval(cpu);
+        println!("{} = {} (${:X})", watch.name, value, value);
+    }
+}
+
+fn game_genie(cpu: &mut CPU, args: &[&str]) -> Result<(), String> {
+    let cheats = &mut cpu.bus.cheats;
+    match args {
+        [] => {
+            for (index, cheat) in cheats.list().iter().enumerate() {
+                println!("{}: {} {}", index, cheat.code, if cheat.enabled { "on" } else { "off" });
+            }
+            Ok(())
+        }
+        [code] => {
+            let index = cheats.add(code)?;
+            println!("Cheat {} added", index);
+            Ok(())
+        }
+        [action, index] => {
+            let index = index.parse::<usize>().map_err(|_| format!("'{}' is not a cheat index", index))?;
+            let found = match *action {
+                "on" => cheats.set_enabled(index, true),
+                "off" => cheats.set_enabled(index, false),
+                "del" => cheats.remove(index).is_some(),
+                _ => return Err(format!("Unknown cheat action '{}'", action)),
+            };
+            if !found {
+                return Err(format!("No cheat {}", index));
+            }
+            Ok(())
+        }
+        _ => Err("Usage: gg [code] or gg on|off|del <index>".to_string()),
+    }
+}
+
+// Addresses listed at most, the search has to go on before that
+const MAX_CANDIDATES: usize = 32;
+
+fn search_ram(cpu: &CPU, search: &mut Option<CheatSearch>, args: &[&str]) -> Result<(), String> {
+    match args.first() {
+        Some(&"start") => {
+            *search = Some(CheatSearch::new(cpu.bus.ram()));
+            println!("RAM snapshot taken, {} candidates", cpu.bus.ram().len());
+        }
+        Some(arg) => {
+            let comparison = arg.parse::<Comparison>()?;
+            let current = search.as_mut().ok_or("No search started, use 'search start'")?;
+            println!("{} candidates left", current.filter(cpu.bus.ram(), comparison));
+        }
+        None => {
+            let current = search.as_ref().ok_or("No search started, use 'search start'")?;
+            for (address, value) in current.candidates().take(MAX_CANDIDATES) {
+                println!("${:04X}: {:02X}", address, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn diff_state(cpu: &CPU, mark: &mut Option<Vec<u8>>, args: &[&str]) -> Result<(), String> {
+    match args.first() {
+        Some(&"mark") => {
+            *mark = Some(savestate::save(cpu, 0, None));
+            println!("State marked");
+        }
+        Some(arg) => return Err(format!("Unknown diff argument '{}'", arg)),
+        None => {
+            let before = mark.as_ref().ok_or("No state marked, use 'diff mark'")?;
+            let diff = statediff::diff_states(before, &savestate::save(cpu, 0, None)).map_err(|e| e.to_string())?;
+            print!("{}", diff);
+        }
+    }
+    Ok(())
+}
+
+fn json_state(cpu: &mut CPU, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    match args {
+        ["save", path, ranges @ ..] => {
+            let ranges = ranges
+                .iter()
+                .map(|range| {
+                    let (start, end) = range.split_once('-').ok_or(format!("'{}' is not a start-end range", range))?;
+                    let end = address_arg(labels, &[end], 0)?;
+                    Ok(address_arg(labels, &[start], 0)?..end.saturating_add(1))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            std::fs::write(path, jsonstate::export(cpu, &ranges)).map_err(|e| format!("Could not write {}: {}", path, e))?;
+            println!("State written to {}", path);
+            Ok(())
+        }
+        ["load", path] => {
+            let json = std::fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+            jsonstate::import(cpu, &json).map_err(|e| e.to_string())?;
+            print_registers(cpu);
+            Ok(())
+        }
+        _ => Err("Usage: json save <file> [start-end].. or json load <file>".to_string()),
+    }
+}
+
+fn print_registers(cpu: &CPU) {
+    let flags: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| if cpu.status & (0b1000_0000 >> i) != 0 { flag } else { flag.to_ascii_lowercase() })
+        .collect();
+    println!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} P:{:02X} [{}]",
+        cpu.program_counter, cpu.register_a, cpu.register_x, cpu.register_y, cpu.stack_pointer, cpu.status, flags
+    );
+}
+
+// Prints one disassembled line, preceded by the label of the address if it
+// has one, and returns the address of the next instruction
+fn print_instruction(cpu: &CPU, labels: &Labels, address: u16) -> u16 {
+    if let Some(name) = labels.name(address) {
+        println!("{}:", name);
+    }
+    let instruction = disasm::disassemble(cpu, address);
+    let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    println!("${:04X}  {:<9} {}", address, bytes.join(" "), instruction.labelled_text(labels));
+    address.wrapping_add(instruction.bytes.len() as u16)
+}
+
+fn disassemble(cpu: &CPU, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let mut address = if args.is_empty() { cpu.program_counter } else { address_arg(labels, args, 0)? };
+    let count = match args.get(1) {
+        Some(arg) => arg.parse::<u32>().map_err(|_| format!("'{}' is not a count", arg))?,
+        None => 10,
+    };
+    for _ in 0..count {
+        address = print_instruction(cpu, labels, address);
+    }
+    Ok(())
+}
+
+fn dump_memory(cpu: &CPU, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let start = address_arg(labels, args, 0)?;
+    let len = match args.get(1) {
+        Some(arg) => parse_number(arg)? as u32,
+        None => 0x40,
+    };
+
+    for row in (0..len).step_by(16) {
+        let row_address = start.wrapping_add(row as u16);
+        let bytes: Vec<u8> = (0..16.min(len - row))
+            .map(|i| cpu.bus.peek(row_address.wrapping_add(i as u16)))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        println!("${:04X}  {:<47}  {}", row_address, hex.join(" "), ascii);
+    }
+    Ok(())
+}
+
+fn poke(cpu: &mut CPU, labels: &Labels, args: &[&str]) -> Result<(), String> {
+    let address = address_arg(labels, args, 0)?;
+    if args.len() < 2 {
+        return Err("Missing value".to_string());
+    }
+    let values = args[1..].iter().map(|arg| parse_byte(arg)).collect::<Result<Vec<u8>, String>>()?;
+    for (i, value) in values.into_iter().enumerate() {
+        cpu.bus.poke(address.wrapping_add(i as u16), value);
+    }
+    Ok(())
+}
@@ -0,0 +1,634 @@
+use crate::bus::{Bus, RamPattern};
+use crate::cpu::{Mem, CPU};
+use crate::error::{ConfigError, EmulationError, ReplayError, RomError, StateError};
+use crate::events::{Event, Observers, SubscriptionId};
+use crate::instructions;
+use crate::region::Region;
+use crate::replay::{InputLog, Session};
+use crate::labels::Labels;
+use crate::savestate;
+use crate::trace::{TraceField, Tracer};
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+// There is no vblank to sync with yet, so a frame is a fixed slice of
+// instructions. At 60 fps this runs the snake game at a comfortable speed.
+pub const INSTRUCTIONS_PER_FRAME: u32 = 240;
+
+pub const SCREEN_WIDTH: usize = 32;
+pub const SCREEN_HEIGHT: usize = 32;
+
+// The screen is one byte per pixel at $0200-$05FF, each one a colour index
+const SCREEN_START: u16 = 0x0200;
+
+// Programs are loaded at $0600, up to the end of the RAM
+const PROGRAM_START: u16 = 0x0600;
+pub const MAX_PROGRAM_SIZE: usize = 0x0800 - PROGRAM_START as usize;
+
+// Reads a raw 6502 program and checks that it fits in RAM
+pub fn read_rom(path: &Path) -> Result<Vec<u8>, RomError> {
+    let rom = std::fs::read(path).map_err(|e| RomError::Unreadable {
+        path: path.to_path_buf(),
+        message: e.to_string(),
+    })?;
+    check_rom(&rom)?;
+    Ok(rom)
+}
+
+pub fn check_rom(rom: &[u8]) -> Result<(), RomError> {
+    if rom.is_empty() {
+        return Err(RomError::Empty);
+    }
+    if rom.len() > MAX_PROGRAM_SIZE {
+        return Err(RomError::TooLarge {
+            size: rom.len(),
+            max: MAX_PROGRAM_SIZE,
+        });
+    }
+    Ok(())
+}
+
+// RGB of the 16 colours of the screen
+pub type Palette = [(u8, u8, u8); 16];
+
+pub const DEFAULT_PALETTE: Palette = [
+    (0, 0, 0),
+    (255, 255, 255),
+    (128, 128, 128),
+    (255, 0, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 0, 255),
+    (255, 255, 0),
+    (0, 255, 255),
+    (128, 128, 128),
+    (255, 0, 0),
+    (0, 255, 0),
+    (0, 0, 255),
+    (255, 0, 255),
+    (255, 255, 0),
+    (0, 255, 255),
+];
+
+// Indexes past the palette show its last colour
+pub fn color(palette: &Palette, byte: u8) -> (u8, u8, u8) {
+    palette[(byte as usize).min(palette.len() - 1)]
+}
+
+// Converts the screen memory to RGB and returns whether anything changed
+pub fn read_screen(cpu: &CPU, palette: &Palette, frame: &mut [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3]) -> bool {
+    let mut update = false;
+    for (i, pixel) in frame.chunks_exact_mut(3).enumerate() {
+        let (r, g, b) = color(palette, cpu.mem_read(SCREEN_START + i as u16));
+        if pixel != [r, g, b] {
+            pixel.copy_from_slice(&[r, g, b]);
+            update = true;
+        }
+    }
+    update
+}
+
+// The whole machine behind one type: load a program, feed it keys, run it a
+// frame at a time and read the picture back.
+//
+//   let mut nes = Nes::new(seed);
+//   nes.load_rom(&program);
+//   nes.set_input(0x77);
+//   nes.run_frame();
+//   let rgb = nes.frame();
+//
+// Any number of callbacks can follow the execution with subscribe(), e.g. a
+// tracer for every instruction and the frontend for every frame.
+pub struct Nes {
+    cpu: CPU,
+    session: Session,
+    rom: Vec<u8>,
+    seed: u64,
+    // Replayed instead of recording from every power on, when set
+    replay: Option<InputLog>,
+    region: Region,
+    palette: Palette,
+    ram_pattern: RamPattern,
+    instructions_per_frame: u32,
+    tracer: Option<Tracer>,
+    frame: [u8; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+    frame_count: u64,
+    observers: Observers,
+}
+
+impl Nes {
+    // `seed` sets the random numbers the program sees
+    pub fn new(seed: u64) -> Self {
+        let mut nes = Nes {
+            cpu: CPU::new(Bus::new()),
+            session: Session::record(&[], seed),
+            rom: Vec::new(),
+            seed,
+            replay: None,
+            region: Region::Ntsc,
+            palette: DEFAULT_PALETTE,
+            ram_pattern: RamPattern::Zero,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            tracer: None,
+            frame: [0; SCREEN_WIDTH * SCREEN_HEIGHT * 3],
+            frame_count: 0,
+            observers: Observers::new(),
+        };
+        nes.power_cycle();
+        nes
+    }
+
+    pub fn builder() -> NesBuilder {
+        NesBuilder::new()
+    }
+
+    // A raw 6502 program, loaded at $0600 and started from power on
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<(), RomError> {
+        check_rom(rom)?;
+        self.rom = rom.to_vec();
+        self.replay = None;
+        self.power_cycle();
+        Ok(())
+    }
+
+    // Runs the loaded program again from power on with the keys, random
+    // numbers and power on RAM of a recorded session, until another program
+    // is loaded. Fails if the log was recorded with another program.
+    pub fn replay(&mut self, log: InputLog) -> Result<(), ReplayError> {
+        Session::replay(&self.rom, log.clone())?;
+        self.seed = log.seed;
+        self.replay = Some(log);
+        self.power_cycle();
+        Ok(())
+    }
+
+    // Turns the machine off and on: RAM is filled with the power on pattern
+    // and the program starts over with the same random numbers. Cheats and
+    // the CPU options are kept.
+    pub fn power_cycle(&mut self) {
+        let cheats = self.cpu.bus.cheats.clone();
+        let stop_on_brk = self.cpu.stop_on_brk;
+        let lenient_opcodes = self.cpu.lenient_opcodes;
+        self.cpu = CPU::new(Bus::new());
+        self.cpu.bus.cheats = cheats;
+        self.cpu.stop_on_brk = stop_on_brk;
+        self.cpu.lenient_opcodes = lenient_opcodes;
+        match &self.replay {
+            Some(log) => self.cpu.bus.fill_ram(log.ram_pattern, log.ram_seed),
+            None => self.cpu.bus.fill_ram(self.ram_pattern, self.seed),
+        }
+        self.cpu.load(self.rom.clone());
+        self.cpu.reset();
+        self.cpu.program_counter = PROGRAM_START;
+        self.session = match &self.replay {
+            // Checked against the ROM by replay()
+            Some(log) => Session::replay(&self.rom, log.clone()).unwrap(),
+            None => Session::record(&self.rom, self.seed).with_ram(self.ram_pattern, self.seed),
+        };
+        self.frame_count = 0;
+        read_screen(&self.cpu, &self.palette, &mut self.frame);
+    }
+
+    // Presses the reset button: the program starts over from $0600 but the
+    // RAM is kept, so it may find things where it left them. Input recorded
+    // since power on can't be replayed past a soft reset.
+    pub fn soft_reset(&mut self) {
+        self.cpu.soft_reset();
+        self.cpu.program_counter = PROGRAM_START;
+    }
+
+    // A key for the program, as the ASCII code of w, a, s or d for the snake
+    pub fn set_input(&mut self, key: u8) {
+        self.session.input(&mut self.cpu, key);
+    }
+
+    // Returns false once the program has stopped on BRK, with stop_on_brk
+    // set. On an error the frame stops at the instruction that could not run.
+    pub fn run_frame(&mut self) -> Result<bool, EmulationError> {
+        self.run_frame_with(|_| {})
+    }
+
+    // Same as run_frame, calling `before_instruction` with the machine as it
+    // is before each instruction, for tools that need more than the events
+    // (tracers, profilers, checkers...)
+    pub fn run_frame_with<F>(&mut self, mut before_instruction: F) -> Result<bool, EmulationError>
+    where
+        F: FnMut(&CPU),
+    {
+        let mut running = true;
+        for _ in 0..self.instructions_per_frame {
+            let session = &mut self.session;
+            let tracer = &mut self.tracer;
+            let (executed, still_running) = instructions::step_with(&mut self.cpu, |cpu| {
+                if let Some(Err(e)) = tracer.as_mut().map(|tracer| tracer.trace(cpu)) {
+                    println!("Stopped tracing: {}", e);
+                    *tracer = None;
+                }
+                before_instruction(cpu);
+                session.before_instruction(cpu);
+            })?;
+            self.observers.emit(&Event::InstructionRetired(&executed));
+            if !still_running {
+                running = false;
+                break;
+            }
+        }
+        self.cpu.bus.apply_freezes();
+        read_screen(&self.cpu, &self.palette, &mut self.frame);
+        self.frame_count += 1;
+        self.observers.emit(&Event::FrameComplete {
+            frame: self.frame_count,
+            pixels: &self.frame,
+        });
+        Ok(running)
+    }
+
+    // Calls `callback` with every event from now on, until unsubscribed
+    pub fn subscribe<F>(&mut self, callback: F) -> SubscriptionId
+    where
+        F: FnMut(&Event) + Send + 'static,
+    {
+        self.observers.subscribe(callback)
+    }
+
+    pub fn unsubscribe(&mut self, id: SubscriptionId) -> bool {
+        self.observers.unsubscribe(id)
+    }
+
+    // The last frame, SCREEN_WIDTH x SCREEN_HEIGHT RGB24 pixels
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    // Instructions run by each frame from now on, at least one. Frontends
+    // change it to run faster or slower than real time.
+    pub fn set_instructions_per_frame(&mut self, count: u32) {
+        self.instructions_per_frame = count.max(1);
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    // Writes out what is left of the trace set up by NesBuilder::trace and
+    // stops tracing
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        match self.tracer.take() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(()),
+        }
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        savestate::save(&self.cpu, crc32fast::hash(&self.rom), None)
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        savestate::load(&mut self.cpu, state, Some(crc32fast::hash(&self.rom)))?;
+        read_screen(&self.cpu, &self.palette, &mut self.frame);
+        Ok(())
+    }
+
+    // Frames per second the program is meant to run at
+    pub fn frame_rate(&self) -> f64 {
+        self.region.frame_rate()
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut CPU {
+        &mut self.cpu
+    }
+
+    // The powered on machine and its session, for debuggers that step the
+    // CPU themselves
+    pub fn into_parts(self) -> (CPU, Session) {
+        (self.cpu, self.session)
+    }
+}
+
+// Settings of a Nes, checked all at once by build():
+//
+//   let nes = Nes::builder().seed(7).region(Region::Pal).cheat("SXIOPO").build()?;
+pub struct NesBuilder {
+    seed: u64,
+    region: Region,
+    palette: Palette,
+    ram_pattern: RamPattern,
+    instructions_per_frame: u32,
+    stop_on_brk: bool,
+    lenient_opcodes: bool,
+    trace: Option<(Box<dyn Write + Send>, Vec<TraceField>)>,
+    trace_ranges: Vec<RangeInclusive<u16>>,
+    cheats: Vec<String>,
+}
+
+impl NesBuilder {
+    pub fn new() -> Self {
+        NesBuilder {
+            seed: 0,
+            region: Region::Ntsc,
+            palette: DEFAULT_PALETTE,
+            ram_pattern: RamPattern::Zero,
+            instructions_per_frame: INSTRUCTIONS_PER_FRAME,
+            stop_on_brk: false,
+            lenient_opcodes: true,
+            trace: None,
+            trace_ranges: Vec::new(),
+            cheats: Vec::new(),
+        }
+    }
+
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn region(mut self, region: Region) -> Self {
+        self.region = region;
+        self
+    }
+
+    pub fn palette(mut self, palette: Palette) -> Self {
+        self.palette = palette;
+        self
+    }
+
+    pub fn ram_pattern(mut self, pattern: RamPattern) -> Self {
+        self.ram_pattern = pattern;
+        self
+    }
+
+    pub fn instructions_per_frame(mut self, count: u32) -> Self {
+        self.instructions_per_frame = count;
+        self
+    }
+
+    // End the program on BRK (see CPU::stop_on_brk), for programs loaded at
+    // $0600 that have no BRK handler
+    pub fn stop_on_brk(mut self, stop: bool) -> Self {
+        self.stop_on_brk = stop;
+        self
+    }
+
+    // Run unofficial opcodes (the default), or fail the frame on them with
+    // EmulationError::UnknownOpcode (see CPU::lenient_opcodes)
+    pub fn lenient_opcodes(mut self, lenient: bool) -> Self {
+        self.lenient_opcodes = lenient;
+        self
+    }
+
+    // Writes a line to `out` for every instruction run, see Tracer
+    pub fn trace(mut self, out: Box<dyn Write + Send>, fields: &[TraceField]) -> Self {
+        self.trace = Some((out, fields.to_vec()));
+        self
+    }
+
+    // Only trace the instructions in these ranges, all of them when none is
+    // given. Needs a trace.
+    pub fn trace_range(mut self, range: RangeInclusive<u16>) -> Self {
+        self.trace_ranges.push(range);
+        self
+    }
+
+    // A Game Genie or address:value code, enabled from the start
+    pub fn cheat(mut self, code: &str) -> Self {
+        self.cheats.push(code.to_string());
+        self
+    }
+
+    pub fn build(self) -> Result<Nes, ConfigError> {
+        if self.instructions_per_frame == 0 {
+            return Err(ConfigError::NoInstructionsPerFrame);
+        }
+        if self.trace.is_none() && !self.trace_ranges.is_empty() {
+            return Err(ConfigError::TraceRangesWithoutTrace);
+        }
+        let mut nes = Nes::new(self.seed);
+        for code in &self.cheats {
+            nes.cpu.bus.cheats.add(code).map_err(|message| ConfigError::InvalidCheat { code: code.clone(), message })?;
+        }
+        let freezes: Vec<_> = nes.cpu.bus.cheats.freezes().collect();
+        for (i, freeze) in freezes.iter().enumerate() {
+            if freezes[..i].iter().any(|other| other.address == freeze.address && other.value != freeze.value) {
+                return Err(ConfigError::ConflictingCheats { address: freeze.address });
+            }
+        }
+        let ranges = self.trace_ranges;
+        nes.tracer = self.trace.map(|(out, fields)| Tracer::new(out, &fields, ranges, Labels::new()));
+        nes.region = self.region;
+        nes.palette = self.palette;
+        nes.ram_pattern = self.ram_pattern;
+        nes.instructions_per_frame = self.instructions_per_frame;
+        nes.cpu.stop_on_brk = self.stop_on_brk;
+        nes.cpu.lenient_opcodes = self.lenient_opcodes;
+        nes.power_cycle();
+        Ok(nes)
+    }
+}
+
+impl Default for NesBuilder {
+    fn default() -> Self {
+        NesBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_run_frames_and_restore_state() {
+        // Draws pixel $0200 in the colour of the last key
+        // LDA $ff; STA $0200; JMP $0600
+        let program = [0xa5, 0xff, 0x8d, 0x00, 0x02, 0x4c, 0x00, 0x06];
+        let mut nes = Nes::new(1);
+        nes.load_rom(&program).unwrap();
+
+        assert_eq!(nes.run_frame(), Ok(true));
+        assert_eq!(&nes.frame()[..3], &[0, 0, 0]);
+        let state = nes.save_state();
+
+        nes.set_input(3);
+        assert_eq!(nes.run_frame(), Ok(true));
+        assert_eq!(&nes.frame()[..3], &[255, 0, 0]);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(&nes.frame()[..3], &[0, 0, 0]);
+        nes.power_cycle();
+        assert_eq!(nes.cpu().program_counter, 0x0600);
+    }
+
+    #[test]
+    fn test_soft_reset_keeps_ram() {
+        // INC $10; JMP $0600
+        let mut nes = Nes::new(1);
+        nes.load_rom(&[0xe6, 0x10, 0x4c, 0x00, 0x06]).unwrap();
+        nes.run_frame().unwrap();
+        let counter = nes.cpu().bus.ram()[0x10];
+        assert_ne!(counter, 0);
+
+        nes.cpu_mut().program_counter = 0x0602;
+        nes.soft_reset();
+        assert_eq!(nes.cpu().program_counter, 0x0600);
+        assert_eq!(nes.cpu().stack_pointer, 0xfa);
+        assert_ne!(nes.cpu().status & 0b0000_0100, 0);
+        assert_eq!(nes.cpu().bus.ram()[0x10], counter);
+
+        nes.power_cycle();
+        assert_eq!(nes.cpu().bus.ram()[0x10], 0);
+        assert_eq!(nes.cpu().stack_pointer, 0xfd);
+    }
+
+    #[test]
+    fn test_errors_can_be_matched() {
+        let mut nes = Nes::new(1);
+        assert_eq!(nes.load_rom(&[]), Err(RomError::Empty));
+        assert_eq!(nes.load_rom(&[0xea; 513]), Err(RomError::TooLarge { size: 513, max: 512 }));
+        assert_eq!(nes.load_state(b"NES\x1a"), Err(StateError::NotAState));
+
+        // NOP; then $02, which jams the CPU
+        nes.load_rom(&[0xea, 0x02]).unwrap();
+        assert_eq!(nes.run_frame(), Err(EmulationError::Jam { opcode: 0x02, address: 0x0601 }));
+        assert_eq!(nes.cpu().program_counter, 0x0601);
+    }
+
+    #[test]
+    fn test_subscribers_get_events_independently() {
+        use std::sync::{mpsc, Arc, Mutex};
+
+        // LDX #$01; INX; BRK
+        let mut nes = Nes::builder().seed(1).stop_on_brk(true).build().unwrap();
+        nes.load_rom(&[0xa2, 0x01, 0xe8, 0x00]).unwrap();
+
+        let mnemonics = Arc::new(Mutex::new(Vec::new()));
+        let tracer = {
+            let mnemonics = mnemonics.clone();
+            nes.subscribe(move |event| {
+                if let Event::InstructionRetired(executed) = event {
+                    mnemonics.lock().unwrap().push(executed.mnemonic);
+                }
+            })
+        };
+        let (sender, frames) = mpsc::channel();
+        nes.subscribe(move |event| {
+            if let Event::FrameComplete { frame, pixels } = event {
+                sender.send((*frame, pixels.len())).unwrap();
+            }
+        });
+
+        assert_eq!(nes.run_frame(), Ok(false));
+        assert_eq!(*mnemonics.lock().unwrap(), vec!["LDX", "INX", "BRK"]);
+        assert_eq!(frames.try_recv(), Ok((1, SCREEN_WIDTH * SCREEN_HEIGHT * 3)));
+
+        assert!(nes.unsubscribe(tracer));
+        assert!(!nes.unsubscribe(tracer));
+        nes.run_frame().unwrap();
+        assert_eq!(mnemonics.lock().unwrap().len(), 3);
+        assert_eq!(frames.try_recv(), Ok((2, SCREEN_WIDTH * SCREEN_HEIGHT * 3)));
+    }
+
+    #[test]
+    fn test_machine_is_send() {
+        // Fails to compile if anything added to the machine can't leave the
+        // UI thread
+        fn assert_send<T: Send>() {}
+        assert_send::<Nes>();
+        assert_send::<CPU>();
+        assert_send::<Bus>();
+
+        let mut nes = Nes::new(1);
+        nes.load_rom(&[0xe8, 0x4c, 0x00, 0x06]).unwrap();
+        let worker = std::thread::spawn(move || {
+            nes.run_frame().unwrap();
+            nes
+        });
+        assert_ne!(worker.join().unwrap().cpu().register_x, 0);
+    }
+
+    #[test]
+    fn test_replay_uses_recorded_ram_pattern() {
+        // Adds $10 to a running sum at $11, then the key. RAM at $10 starts
+        // out with the power on pattern.
+        // LDA $11; CLC; ADC $10; ADC $ff; STA $11; JMP $0600
+        let program = [0xa5, 0x11, 0x18, 0x65, 0x10, 0x65, 0xff, 0x85, 0x11, 0x4c, 0x00, 0x06];
+        let mut nes = Nes::builder().seed(3).ram_pattern(RamPattern::Random).build().unwrap();
+        nes.load_rom(&program).unwrap();
+        assert_ne!(nes.cpu().bus.ram()[0x10], 0);
+        for key in 0..5 {
+            nes.set_input(key);
+            nes.run_frame().unwrap();
+        }
+        let log = InputLog::from_text(&nes.session().log().to_text()).unwrap();
+        assert_eq!((log.ram_pattern, log.ram_seed), (RamPattern::Random, 3));
+
+        let mut replayed = Nes::new(9);
+        replayed.load_rom(&program).unwrap();
+        replayed.replay(log).unwrap();
+        for _ in 0..5 {
+            replayed.run_frame().unwrap();
+        }
+        assert_eq!(replayed.session().desync(), None);
+        assert_eq!(replayed.cpu().bus.ram(), nes.cpu().bus.ram());
+    }
+
+    #[test]
+    fn test_builder_lenient_opcodes_and_trace() {
+        // INX; $1a (unofficial NOP); INX; JMP $0600
+        let program = [0xe8, 0x1a, 0xe8, 0x4c, 0x00, 0x06];
+        let mut nes = Nes::builder().lenient_opcodes(false).build().unwrap();
+        nes.load_rom(&program).unwrap();
+        assert_eq!(nes.run_frame(), Err(EmulationError::UnknownOpcode { opcode: 0x1a, address: 0x0601 }));
+        assert_eq!(nes.cpu().program_counter, 0x0601);
+
+        let path = std::env::temp_dir().join(format!("enes_builder_trace_{}.log", std::process::id()));
+        let mut nes = Nes::builder()
+            .instructions_per_frame(4)
+            .trace(Box::new(std::fs::File::create(&path).unwrap()), &[TraceField::Registers])
+            .trace_range(0x0601..=0x0602)
+            .build()
+            .unwrap();
+        nes.load_rom(&program).unwrap();
+        assert_eq!(nes.run_frame(), Ok(true));
+        assert_eq!(nes.cpu().register_x, 2);
+        assert_eq!(nes.cpu().program_counter, 0x0600);
+        nes.finish_trace().unwrap();
+        let trace = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let pcs: Vec<&str> = trace.lines().map(|line| &line[..4]).collect();
+        assert_eq!(pcs, ["0601", "0602"]);
+    }
+
+    #[test]
+    fn test_builder_validates_settings() {
+        let nes = Nes::builder().region(Region::Pal).cheat("0010:05").build().unwrap();
+        assert!((nes.frame_rate() - 50.007).abs() < 0.001);
+        assert_eq!(nes.cpu().bus.cheats.list().len(), 1);
+
+        assert!(matches!(
+            Nes::builder().cheat("NOT A CODE").build(),
+            Err(ConfigError::InvalidCheat { code, .. }) if code == "NOT A CODE"
+        ));
+        assert!(matches!(
+            Nes::builder().instructions_per_frame(0).build(),
+            Err(ConfigError::NoInstructionsPerFrame)
+        ));
+        assert!(matches!(
+            Nes::builder().cheat("0010:05").cheat("0010:06").build(),
+            Err(ConfigError::ConflictingCheats { address: 0x0010 })
+        ));
+        assert!(Nes::builder().cheat("0010:05").cheat("0010:05").build().is_ok());
+        assert!(matches!(
+            Nes::builder().trace_range(0x0600..=0x06ff).build(),
+            Err(ConfigError::TraceRangesWithoutTrace)
+        ));
+
+        let mut palette = DEFAULT_PALETTE;
+        palette[0] = (1, 2, 3);
+        let nes = Nes::builder().palette(palette).build().unwrap();
+        assert_eq!(&nes.frame()[..3], &[1, 2, 3]);
+    }
+}
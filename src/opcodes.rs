@@ -19,6 +19,34 @@ impl OpCode {
             mode: mode,
         }
     }
+
+    // Whether the instruction takes one more cycle when indexing crosses a
+    // page. Only reads do: writes and read-modify-writes always take that
+    // cycle, it's in their base cycles.
+    pub fn page_cross_penalty(&self) -> bool {
+        let indexed = matches!(
+            self.mode,
+            AddressingMode::Absolute_X | AddressingMode::Absolute_Y | AddressingMode::Indirect_Y
+        );
+        let reads = matches!(
+            self.mnemonic,
+            "ADC" | "AND" | "CMP" | "EOR" | "LDA" | "LDX" | "LDY" | "ORA" | "SBC" | "LAX" | "LAS" | "NOP"
+        );
+        indexed && reads
+    }
+
+    // Bytes the instruction pushes on the stack and pulls from it
+    pub fn stack_effect(&self) -> (u8, u8) {
+        match self.mnemonic {
+            "PHA" | "PHP" => (1, 0),
+            "JSR" => (2, 0),
+            "BRK" => (3, 0),
+            "PLA" | "PLP" => (0, 1),
+            "RTS" => (0, 2),
+            "RTI" => (0, 3),
+            _ => (0, 0),
+        }
+    }
 }
 
 
@@ -63,6 +91,10 @@ lazy_static! {
         OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPage_Y),
         OpCode::new(0x8e, "STX", 3, 4, AddressingMode::Absolute),
 
+        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x8c, "STY", 3, 4, AddressingMode::Absolute),
+
         /* Arithmetic */
         OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
@@ -91,7 +123,27 @@ lazy_static! {
         OpCode::new(0x21, "AND", 2, 6, AddressingMode::Indirect_X),
         OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
 
+        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0d, "ORA", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1d, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
+        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x4d, "EOR", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x5d, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+
         OpCode::new(0xca, "DEX", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xc8, "INY", 1, 2, AddressingMode::NoneAddressing),
 
         OpCode::new(0xc6, "DEC", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0xd6, "DEC", 2, 6, AddressingMode::ZeroPage_X),
@@ -102,6 +154,10 @@ lazy_static! {
         OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
 
+        OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xCC, "CPY", 3, 4, AddressingMode::Absolute),
+
         OpCode::new(0xc9, "CMP", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xc5, "CMP", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0xd5, "CMP", 2, 4, AddressingMode::ZeroPage_X),
@@ -114,6 +170,7 @@ lazy_static! {
         /* Branching */
         OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
+        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
 
         OpCode::new(0xD0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
         OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
@@ -143,6 +200,12 @@ lazy_static! {
         OpCode::new(0x9a, "TXS", 1, 2, AddressingMode::NoneAddressing),
         OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
 
+        /* Stack */
+        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
+        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
+        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
+
         OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x2c, "BIT", 3, 4, AddressingMode::Absolute),
 
@@ -153,15 +216,165 @@ lazy_static! {
         OpCode::new(0x4e, "LSR", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x5e, "LSR", 3, 7, AddressingMode::Absolute_X),
 
+        OpCode::new(0x0a, "ASL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0e, "ASL", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x1e, "ASL", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x2a, "ROL", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x2e, "ROL", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x3e, "ROL", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x6a, "ROR", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x6e, "ROR", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x7e, "ROR", 3, 7, AddressingMode::Absolute_X),
+
         OpCode::new(0xe6, "INC", 2, 5, AddressingMode::ZeroPage),
         OpCode::new(0xf6, "INC", 2, 6, AddressingMode::ZeroPage_X),
         OpCode::new(0xee, "INC", 3, 6, AddressingMode::Absolute),
         OpCode::new(0xfe, "INC", 3, 7, AddressingMode::Absolute_X),
     ];
 
+    // Every opcode the CPU runs, unofficial ones included
     pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
         let mut map = HashMap::new();
-        for cpuop in &*CPU_OPS_CODES {
+        for cpuop in CPU_OPS_CODES.iter().chain(UNOFFICIAL_OPS_CODES.iter()) {
+            map.insert(cpuop.code, cpuop);
+        }
+        map
+    };
+
+    // The opcodes left out of the 6502 documentation, which still do
+    // something and are used by a few games and test ROMs. XAA, LXA, AHX,
+    // TAS, SHX and SHY are unstable on real hardware, the CPU runs their most
+    // common behaviour. JAM locks up the CPU.
+    pub static ref UNOFFICIAL_OPS_CODES: Vec<OpCode> = vec![
+        OpCode::new(0x03, "SLO", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x07, "SLO", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x0f, "SLO", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x13, "SLO", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x17, "SLO", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x1b, "SLO", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x1f, "SLO", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x23, "RLA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x27, "RLA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x2f, "RLA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x33, "RLA", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x37, "RLA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x3b, "RLA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x3f, "RLA", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x43, "SRE", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x47, "SRE", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x4f, "SRE", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x53, "SRE", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x57, "SRE", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x5b, "SRE", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x5f, "SRE", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x63, "RRA", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0x67, "RRA", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0x6f, "RRA", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0x73, "RRA", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0x77, "RRA", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0x7b, "RRA", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0x7f, "RRA", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x83, "SAX", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0x87, "SAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x8f, "SAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x97, "SAX", 2, 4, AddressingMode::ZeroPage_Y),
+
+        OpCode::new(0xa3, "LAX", 2, 6, AddressingMode::Indirect_X),
+        OpCode::new(0xa7, "LAX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xaf, "LAX", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xb3, "LAX", 2, 5/*+1 if page crossed*/, AddressingMode::Indirect_Y),
+        OpCode::new(0xb7, "LAX", 2, 4, AddressingMode::ZeroPage_Y),
+        OpCode::new(0xbf, "LAX", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+
+        OpCode::new(0xc3, "DCP", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xc7, "DCP", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xcf, "DCP", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xd3, "DCP", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0xd7, "DCP", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xdb, "DCP", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xdf, "DCP", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0xe3, "ISC", 2, 8, AddressingMode::Indirect_X),
+        OpCode::new(0xe7, "ISC", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xef, "ISC", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xf3, "ISC", 2, 8, AddressingMode::Indirect_Y),
+        OpCode::new(0xf7, "ISC", 2, 6, AddressingMode::ZeroPage_X),
+        OpCode::new(0xfb, "ISC", 3, 7, AddressingMode::Absolute_Y),
+        OpCode::new(0xff, "ISC", 3, 7, AddressingMode::Absolute_X),
+
+        OpCode::new(0x0b, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x2b, "ANC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x4b, "ALR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x6b, "ARR", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x8b, "XAA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xab, "LXA", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xcb, "AXS", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xeb, "SBC", 2, 2, AddressingMode::Immediate),
+
+        OpCode::new(0x93, "AHX", 2, 6, AddressingMode::Indirect_Y),
+        OpCode::new(0x9f, "AHX", 3, 5, AddressingMode::Absolute_Y),
+        OpCode::new(0x9b, "TAS", 3, 5, AddressingMode::Absolute_Y),
+        OpCode::new(0xbb, "LAS", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_Y),
+        OpCode::new(0x9c, "SHY", 3, 5, AddressingMode::Absolute_X),
+        OpCode::new(0x9e, "SHX", 3, 5, AddressingMode::Absolute_Y),
+
+        OpCode::new(0x1a, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x3a, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x5a, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x7a, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xda, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xfa, "NOP", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x80, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x82, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x89, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xc2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xe2, "NOP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0x04, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x44, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x64, "NOP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0x14, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x34, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x54, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x74, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xd4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0xf4, "NOP", 2, 4, AddressingMode::ZeroPage_X),
+        OpCode::new(0x0c, "NOP", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0x1c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x3c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x5c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0x7c, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0xdc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+        OpCode::new(0xfc, "NOP", 3, 4/*+1 if page crossed*/, AddressingMode::Absolute_X),
+
+        OpCode::new(0x02, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x12, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x22, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x32, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x42, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x52, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x62, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x72, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0x92, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xb2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xd2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xf2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+    ];
+
+    pub static ref UNOFFICIAL_OPCODES_MAP: HashMap<u8, &'static OpCode> = {
+        let mut map = HashMap::new();
+        for cpuop in &*UNOFFICIAL_OPS_CODES {
             map.insert(cpuop.code, cpuop);
         }
         map
@@ -0,0 +1,112 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+
+// There's no audio clock to pace by yet, eNES has no APU
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum PacingMode {
+    // Sleep until the next frame is due, independent of the monitor refresh rate
+    Limiter,
+    // Let the blocking present of a vsynced canvas set the pace, which only
+    // runs at the right speed on a monitor refreshing at the emulated rate
+    Vsync,
+    // Run as fast as possible
+    Free,
+}
+
+pub struct FramePacer {
+    mode: PacingMode,
+    frame_duration: Duration,
+    next_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(mode: PacingMode, fps: f64) -> Self {
+        FramePacer {
+            mode,
+            frame_duration: Duration::from_secs_f64(1.0 / fps),
+            next_frame: Instant::now(),
+        }
+    }
+
+    // With vsync every frame has to be presented, even an unchanged one,
+    // because presenting is what blocks until the next refresh.
+    pub fn present_every_frame(&self) -> bool {
+        self.mode == PacingMode::Vsync
+    }
+
+    // Called once per emulated frame, after it has been presented.
+    pub fn wait(&mut self) {
+        if self.mode != PacingMode::Limiter {
+            return;
+        }
+
+        self.next_frame += self.frame_duration;
+        let now = Instant::now();
+        if self.next_frame > now {
+            std::thread::sleep(self.next_frame - now);
+        } else if now - self.next_frame > self.frame_duration {
+            // Too far behind (window dragged, debugger stop...): don't try to catch up
+            self.next_frame = now;
+        }
+    }
+}
+
+// Speed changes are applied by the frame loop as a different number of
+// instructions per frame, so pacing, input polling and presenting keep
+// running at the normal frame rate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SpeedControl {
+    pub fast_forward: bool,
+    pub slow_motion: bool,
+    pub fast_forward_speed: f64,
+    pub slow_motion_speed: f64,
+}
+
+impl SpeedControl {
+    pub fn multiplier(&self) -> f64 {
+        if self.fast_forward {
+            self.fast_forward_speed
+        } else if self.slow_motion {
+            self.slow_motion_speed
+        } else {
+            1.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn speed(fast_forward: bool, slow_motion: bool) -> SpeedControl {
+        SpeedControl {
+            fast_forward,
+            slow_motion,
+            fast_forward_speed: 4.0,
+            slow_motion_speed: 0.5,
+        }
+    }
+
+    #[test]
+    fn test_speed_multiplier() {
+        assert_eq!(speed(false, false).multiplier(), 1.0);
+        assert_eq!(speed(true, false).multiplier(), 4.0);
+        assert_eq!(speed(false, true).multiplier(), 0.5);
+        // Holding fast-forward wins over slow motion
+        assert_eq!(speed(true, true).multiplier(), 4.0);
+    }
+
+    #[test]
+    fn test_only_limiter_waits() {
+        for mode in [PacingMode::Vsync, PacingMode::Free] {
+            let mut pacer = FramePacer::new(mode, 1.0);
+            let start = Instant::now();
+            pacer.wait();
+            assert!(start.elapsed() < Duration::from_millis(500));
+        }
+        assert!(FramePacer::new(PacingMode::Vsync, 60.0).present_every_frame());
+        assert!(!FramePacer::new(PacingMode::Limiter, 60.0).present_every_frame());
+    }
+}
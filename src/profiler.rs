@@ -0,0 +1,150 @@
+use crate::callstack::CallStack;
+use crate::cpu::CPU;
+use crate::labels::Labels;
+use crate::opcodes;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RoutineStats {
+    pub calls: u64,
+    // Cycles from the JSR to the matching RTS, including called subroutines
+    pub inclusive: u64,
+    // Cycles spent in the subroutine's own instructions
+    pub exclusive: u64,
+}
+
+// Cycles per subroutine, following JSR and RTS to keep track of the call
+// stack. Cycles are the base cycles of each opcode, without page crossing
+// or branch penalties.
+pub struct Profiler {
+    calls: CallStack,
+    // Cycles at the start of each call in progress
+    start_cycles: Vec<u64>,
+    routines: HashMap<u16, RoutineStats>,
+    top_level: u64,
+    cycles: u64,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Profiler {
+            calls: CallStack::new(),
+            start_cycles: Vec::new(),
+            routines: HashMap::new(),
+            top_level: 0,
+            cycles: 0,
+        }
+    }
+
+    // Called before each instruction is executed
+    pub fn record(&mut self, cpu: &CPU) {
+        let code = cpu.bus.peek(cpu.program_counter);
+        let cycles = opcodes::OPCODES_MAP.get(&code).map_or(0, |opcode| opcode.cycles as u64);
+        self.cycles += cycles;
+
+        // The JSR belongs to the caller and the RTS to the subroutine
+        match self.calls.calls().last() {
+            Some(call) => self.routines.entry(call.target).or_default().exclusive += cycles,
+            None => self.top_level += cycles,
+        }
+
+        let depth = self.calls.calls().len();
+        self.calls.update(cpu);
+        for call in self.calls.ended() {
+            let start = self.start_cycles.pop().unwrap_or(0);
+            self.routines.entry(call.target).or_default().inclusive += self.cycles - start;
+        }
+        if self.calls.calls().len() > depth {
+            let target = self.calls.calls()[depth].target;
+            self.routines.entry(target).or_default().calls += 1;
+            self.start_cycles.push(self.cycles - cycles);
+        }
+    }
+
+    pub fn routine(&self, target: u16) -> Option<RoutineStats> {
+        self.routines.get(&target).copied()
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Table of subroutines, the most expensive first (by exclusive cycles).
+    // Subroutines called recursively count their inclusive cycles once per
+    // level of recursion.
+    pub fn report(&self, labels: &Labels) -> String {
+        let mut routines: Vec<(&u16, &RoutineStats)> = self.routines.iter().collect();
+        routines.sort_by(|a, b| b.1.exclusive.cmp(&a.1.exclusive).then(a.0.cmp(b.0)));
+
+        let total = self.cycles.max(1) as f64;
+        let mut report = format!(
+            "{:>10} {:>14} {:>14} {:>7}  {}\n",
+            "calls", "inclusive", "exclusive", "excl %", "subroutine"
+        );
+        for (&target, stats) in routines {
+            let name = match labels.name(target) {
+                Some(name) => format!("${:04X} {}", target, name),
+                None => format!("${:04X}", target),
+            };
+            report += &format!(
+                "{:>10} {:>14} {:>14} {:>6.2}%  {}\n",
+                stats.calls,
+                stats.inclusive,
+                stats.exclusive,
+                stats.exclusive as f64 * 100.0 / total,
+                name
+            );
+        }
+        report += &format!(
+            "{:>10} {:>14} {:>14} {:>6.2}%  (outside subroutines)\n",
+            "",
+            "",
+            self.top_level,
+            self.top_level as f64 * 100.0 / total
+        );
+        report
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_inclusive_and_exclusive_cycles() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        cpu.load(vec![
+            0x20, 0x05, 0x06, // $0600 JSR $0605
+            0xea, //             $0603 NOP
+            0x00, //             $0604 BRK
+            0x20, 0x09, 0x06, // $0605 JSR $0609
+            0x60, //             $0608 RTS
+            0xe8, //             $0609 INX
+            0x60, //             $060A RTS
+        ]);
+        cpu.program_counter = 0x0600;
+
+        let mut profiler = Profiler::new();
+        loop {
+            profiler.record(&cpu);
+            if !cpu.step() {
+                break;
+            }
+        }
+
+        assert_eq!(profiler.routine(0x0600), None);
+        let outer = profiler.routine(0x0605).unwrap();
+        let inner = profiler.routine(0x0609).unwrap();
+        assert_eq!(inner, RoutineStats { calls: 1, inclusive: 6 + 8, exclusive: 8 });
+        assert_eq!(outer, RoutineStats { calls: 1, inclusive: 6 + 6 + 8 + 6, exclusive: 12 });
+        assert_eq!(profiler.total_cycles(), 15 + 12 + 8);
+    }
+}
@@ -0,0 +1,80 @@
+use serde::Deserialize;
+use std::str::FromStr;
+
+// TV system of the console. The clocks come from one master oscillator,
+// divided down for the CPU and the PPU, and a frame is a fixed number of PPU
+// dots, which sets the frame rate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Region {
+    Ntsc,
+    Pal,
+    // The Russian famiclone: PAL timing with an NTSC-like CPU divider
+    Dendy,
+}
+
+impl Region {
+    pub fn master_clock(self) -> f64 {
+        match self {
+            Region::Ntsc => 21_477_272.0,
+            Region::Pal | Region::Dendy => 26_601_712.0,
+        }
+    }
+
+    pub fn cpu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 12,
+            Region::Pal => 16,
+            Region::Dendy => 15,
+        }
+    }
+
+    pub fn ppu_divider(self) -> u32 {
+        match self {
+            Region::Ntsc => 4,
+            Region::Pal | Region::Dendy => 5,
+        }
+    }
+
+    pub fn cpu_clock(self) -> f64 {
+        self.master_clock() / self.cpu_divider() as f64
+    }
+
+    // Scanlines per frame, of 341 dots each
+    pub fn scanlines(self) -> u32 {
+        match self {
+            Region::Ntsc => 262,
+            Region::Pal | Region::Dendy => 312,
+        }
+    }
+
+    pub fn frame_rate(self) -> f64 {
+        self.master_clock() / (self.ppu_divider() * 341 * self.scanlines()) as f64
+    }
+}
+
+impl FromStr for Region {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text {
+            "ntsc" => Ok(Region::Ntsc),
+            "pal" => Ok(Region::Pal),
+            "dendy" => Ok(Region::Dendy),
+            _ => Err(format!("Unknown region '{}', expected ntsc, pal or dendy", text)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_rates() {
+        assert!((Region::Ntsc.frame_rate() - 60.0988).abs() < 0.001);
+        assert!((Region::Pal.frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Dendy.frame_rate() - 50.0070).abs() < 0.001);
+        assert!((Region::Pal.cpu_clock() - 1_662_607.0).abs() < 1.0);
+    }
+}
@@ -0,0 +1,398 @@
+use crate::bus::RamPattern;
+use crate::cpu::{Mem, CPU};
+use crate::error::ReplayError;
+use crate::savestate;
+use std::fs;
+use std::path::Path;
+
+// Memory mapped "devices" of the snake game: a random number refreshed
+// before every instruction, and the last key pressed
+pub const RANDOM_ADDR: u16 = 0xfe;
+pub const INPUT_ADDR: u16 = 0xff;
+
+// Instructions between two hashes of the machine state in the log
+pub const CHECKPOINT_INTERVAL: u64 = 10_000;
+
+const HEADER: &str = "enes-input-log 3";
+// Logs without the power on RAM pattern, which was always zero
+const HEADER_V2: &str = "enes-input-log 2";
+// Logs without checkpoints
+const HEADER_V1: &str = "enes-input-log 1";
+
+// Small xorshift generator, so that a seed gives the same numbers on every
+// platform and with any version of the rand crate
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> Self {
+        // The state must not be zero
+        let state = seed ^ 0x9e37_79b9_7f4a_7c15;
+        XorShift {
+            state: if state == 0 { 1 } else { state },
+        }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+        self.state
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct InputEvent {
+    // Number of instructions executed before the key was written to $ff
+    pub instruction: u64,
+    pub value: u8,
+}
+
+// Hash of the machine state (see savestate::hash) before an instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Checkpoint {
+    pub instruction: u64,
+    pub hash: u64,
+}
+
+// Everything needed to run a session again from power on: the ROM it was
+// recorded with, the seed of the random numbers, what RAM held at power on
+// and the keys pressed. The checkpoints tell whether a replay still runs like
+// the recording did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputLog {
+    pub rom_crc32: u32,
+    pub seed: u64,
+    pub ram_pattern: RamPattern,
+    // Only used by RamPattern::Random
+    pub ram_seed: u64,
+    pub events: Vec<InputEvent>,
+    pub checkpoints: Vec<Checkpoint>,
+}
+
+impl InputLog {
+    // Plain text so that it can be attached to a bug report and read:
+    //
+    //   enes-input-log 3
+    //   rom 3c5a1f0e
+    //   seed 1234
+    //   ram random 1234
+    //   check 0 8d0e2a3bd4c71f2a
+    //   1200 77
+    //   5040 64
+    //   check 10000 1b5f0c0d9e8a7342
+    pub fn to_text(&self) -> String {
+        let mut text = format!("{}\nrom {:08x}\nseed {}\n", HEADER, self.rom_crc32, self.seed);
+        text += &match self.ram_pattern {
+            RamPattern::Random => format!("ram {} {}\n", self.ram_pattern, self.ram_seed),
+            pattern => format!("ram {}\n", pattern),
+        };
+        let mut checkpoints = self.checkpoints.iter().peekable();
+        for event in &self.events {
+            while let Some(checkpoint) = checkpoints.next_if(|checkpoint| checkpoint.instruction <= event.instruction) {
+                text += &format!("check {} {:016x}\n", checkpoint.instruction, checkpoint.hash);
+            }
+            text += &format!("{} {:02x}\n", event.instruction, event.value);
+        }
+        for checkpoint in checkpoints {
+            text += &format!("check {} {:016x}\n", checkpoint.instruction, checkpoint.hash);
+        }
+        text
+    }
+
+    pub fn from_text(text: &str) -> Result<InputLog, ReplayError> {
+        let mut lines = text.lines();
+        let header = lines.next();
+        if !matches!(header, Some(HEADER) | Some(HEADER_V2) | Some(HEADER_V1)) {
+            return Err(ReplayError::NotALog);
+        }
+        let mut field = |name: &'static str| {
+            lines
+                .next()
+                .and_then(|line| line.strip_prefix(name)?.strip_prefix(' '))
+                .map(|value| value.trim().to_string())
+                .ok_or(ReplayError::MissingLine(name))
+        };
+        let rom_crc32 = u32::from_str_radix(&field("rom")?, 16).map_err(|_| ReplayError::InvalidField("ROM checksum"))?;
+        let seed = field("seed")?.parse::<u64>().map_err(|_| ReplayError::InvalidField("seed"))?;
+        let (ram_pattern, ram_seed) = if header == Some(HEADER) {
+            let ram = field("ram")?;
+            let (pattern, ram_seed) = ram.split_once(' ').unwrap_or((&ram, ""));
+            let pattern = pattern.parse::<RamPattern>().map_err(|_| ReplayError::InvalidField("RAM pattern"))?;
+            let ram_seed = match pattern {
+                RamPattern::Random => ram_seed.parse::<u64>().map_err(|_| ReplayError::InvalidField("RAM seed"))?,
+                _ => 0,
+            };
+            (pattern, ram_seed)
+        } else {
+            (RamPattern::Zero, 0)
+        };
+
+        let mut events = Vec::new();
+        let mut checkpoints = Vec::new();
+        for line in lines {
+            if let Some(checkpoint) = line.strip_prefix("check ") {
+                let checkpoint = checkpoint
+                    .split_once(' ')
+                    .and_then(|(instruction, hash)| {
+                        Some(Checkpoint {
+                            instruction: instruction.parse().ok()?,
+                            hash: u64::from_str_radix(hash, 16).ok()?,
+                        })
+                    })
+                    .ok_or_else(|| ReplayError::InvalidCheckpoint(line.to_string()))?;
+                checkpoints.push(checkpoint);
+                continue;
+            }
+            let event = line
+                .split_once(' ')
+                .and_then(|(instruction, value)| {
+                    Some(InputEvent {
+                        instruction: instruction.parse().ok()?,
+                        value: u8::from_str_radix(value, 16).ok()?,
+                    })
+                })
+                .ok_or_else(|| ReplayError::InvalidEvent(line.to_string()))?;
+            if events.last().is_some_and(|last: &InputEvent| last.instruction > event.instruction) {
+                return Err(ReplayError::OutOfOrder(line.to_string()));
+            }
+            events.push(event);
+        }
+
+        Ok(InputLog {
+            rom_crc32,
+            seed,
+            ram_pattern,
+            ram_seed,
+            events,
+            checkpoints,
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), ReplayError> {
+        fs::write(path, self.to_text())
+            .map_err(|e| ReplayError::Unwritable { path: path.to_path_buf(), message: e.to_string() })
+    }
+
+    pub fn load(path: &Path) -> Result<InputLog, ReplayError> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| ReplayError::Unreadable { path: path.to_path_buf(), message: e.to_string() })?;
+        InputLog::from_text(&text).map_err(|e| ReplayError::InFile { path: path.to_path_buf(), source: Box::new(e) })
+    }
+}
+
+// Feeds the random numbers and the keys to the program. While recording the
+// keys come from the caller and are logged, while replaying they come from the
+// log. Either way the same seed and keys give the same execution, as long as
+// the session starts from power on.
+#[derive(Clone)]
+pub struct Session {
+    rng: XorShift,
+    instructions: u64,
+    log: InputLog,
+    replaying: bool,
+    next_event: usize,
+    next_checkpoint: usize,
+    desync: Option<u64>,
+}
+
+impl Session {
+    pub fn record(rom: &[u8], seed: u64) -> Self {
+        Session {
+            rng: XorShift::new(seed),
+            instructions: 0,
+            log: InputLog {
+                rom_crc32: crc32fast::hash(rom),
+                seed,
+                ram_pattern: RamPattern::Zero,
+                ram_seed: 0,
+                events: Vec::new(),
+                checkpoints: Vec::new(),
+            },
+            replaying: false,
+            next_event: 0,
+            next_checkpoint: 0,
+            desync: None,
+        }
+    }
+
+    // The pattern the caller filled RAM with before the recording started
+    pub fn with_ram(mut self, pattern: RamPattern, seed: u64) -> Self {
+        self.log.ram_pattern = pattern;
+        self.log.ram_seed = seed;
+        self
+    }
+
+    pub fn replay(rom: &[u8], log: InputLog) -> Result<Self, ReplayError> {
+        let crc = crc32fast::hash(rom);
+        if crc != log.rom_crc32 {
+            return Err(ReplayError::WrongRom { expected: log.rom_crc32, found: crc });
+        }
+        Ok(Session {
+            rng: XorShift::new(log.seed),
+            instructions: 0,
+            log,
+            replaying: true,
+            next_event: 0,
+            next_checkpoint: 0,
+            desync: None,
+        })
+    }
+
+    pub fn is_replaying(&self) -> bool {
+        self.replaying
+    }
+
+    pub fn log(&self) -> &InputLog {
+        &self.log
+    }
+
+    // The first checkpoint where a replay stopped matching the recording
+    pub fn desync(&self) -> Option<u64> {
+        self.desync
+    }
+
+    // Called before each instruction is executed
+    pub fn before_instruction(&mut self, cpu: &mut CPU) {
+        while self.replaying {
+            match self.log.events.get(self.next_event) {
+                Some(event) if event.instruction == self.instructions => {
+                    cpu.mem_write(INPUT_ADDR, event.value);
+                    self.next_event += 1;
+                }
+                _ => break,
+            }
+        }
+        if self.replaying {
+            self.verify_checkpoint(cpu);
+        } else if self.instructions.is_multiple_of(CHECKPOINT_INTERVAL) {
+            self.log.checkpoints.push(Checkpoint {
+                instruction: self.instructions,
+                hash: savestate::hash(cpu),
+            });
+        }
+        cpu.mem_write(RANDOM_ADDR, 1 + (self.rng.next_u64() % 15) as u8);
+        self.instructions += 1;
+    }
+
+    fn verify_checkpoint(&mut self, cpu: &CPU) {
+        match self.log.checkpoints.get(self.next_checkpoint) {
+            Some(checkpoint) if checkpoint.instruction == self.instructions => {
+                if self.desync.is_none() && checkpoint.hash != savestate::hash(cpu) {
+                    self.desync = Some(self.instructions);
+                }
+                self.next_checkpoint += 1;
+            }
+            _ => {}
+        }
+    }
+
+    // A key pressed by the player, ignored while replaying
+    pub fn input(&mut self, cpu: &mut CPU, value: u8) {
+        if self.replaying {
+            return;
+        }
+        cpu.mem_write(INPUT_ADDR, value);
+        self.log.events.push(InputEvent {
+            instruction: self.instructions,
+            value,
+        });
+    }
+}
+
+// Runs `rom` from power on with the keys of `log`, without a window, for at
+// most `max_instructions` or until BRK. Returns the number of instructions
+// run, or an error as soon as the state doesn't match a checkpoint.
+pub fn replay(cpu: &mut CPU, rom: &[u8], log: &InputLog, max_instructions: u64) -> Result<u64, ReplayError> {
+    let mut session = Session::replay(rom, log.clone())?;
+    cpu.bus.fill_ram(log.ram_pattern, log.ram_seed);
+    cpu.load(rom.to_vec());
+    cpu.reset();
+    cpu.program_counter = 0x0600;
+
+    for count in 0..max_instructions {
+        session.before_instruction(cpu);
+        if let Some(instruction) = session.desync() {
+            return Err(ReplayError::Desync(instruction));
+        }
+        if !cpu.try_step()? {
+            return Ok(count + 1);
+        }
+    }
+    Ok(max_instructions)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    // Adds the random number and the last key to a running sum at $10 forever
+    const PROGRAM: [u8; 13] = [
+        0xa5, 0xfe, // LDA $fe
+        0x18, //       CLC
+        0x65, 0xff, // ADC $ff
+        0x65, 0x10, // ADC $10
+        0x85, 0x10, // STA $10
+        0x4c, 0x00, 0x06, // JMP $0600
+        0x00,
+    ];
+
+    #[test]
+    fn test_replay_is_identical_to_recording() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(PROGRAM.to_vec());
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+
+        let mut session = Session::record(&PROGRAM, 42);
+        for count in 0..10_000u64 {
+            if count % 777 == 0 {
+                session.input(&mut cpu, (count / 777) as u8);
+            }
+            session.before_instruction(&mut cpu);
+            cpu.step();
+        }
+        let log = InputLog::from_text(&session.log().to_text()).unwrap();
+        assert_eq!(&log, session.log());
+        assert_eq!(InputLog::from_text("enes-input-log 2\nrom 12345678\n"), Err(ReplayError::MissingLine("seed")));
+        assert_eq!(InputLog::from_text("not a log"), Err(ReplayError::NotALog));
+
+        let mut replayed = CPU::new(Bus::new());
+        assert_eq!(replay(&mut replayed, &PROGRAM, &log, 10_000), Ok(10_000));
+        assert_eq!(replayed.bus.ram(), cpu.bus.ram());
+        assert_eq!(replayed.program_counter, cpu.program_counter);
+        assert_eq!(replayed.register_a, cpu.register_a);
+    }
+
+    #[test]
+    fn test_replay_detects_desync() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(PROGRAM.to_vec());
+        cpu.reset();
+        cpu.program_counter = 0x0600;
+        let mut session = Session::record(&PROGRAM, 42);
+        for _ in 0..3 * CHECKPOINT_INTERVAL {
+            session.before_instruction(&mut cpu);
+            cpu.step();
+        }
+        let mut log = session.log().clone();
+        assert_eq!(log.checkpoints.len(), 3);
+
+        log.checkpoints[1].hash ^= 1;
+        let mut replayed = CPU::new(Bus::new());
+        assert_eq!(
+            replay(&mut replayed, &PROGRAM, &log, 3 * CHECKPOINT_INTERVAL),
+            Err(ReplayError::Desync(CHECKPOINT_INTERVAL))
+        );
+    }
+
+    #[test]
+    fn test_replay_rejects_other_rom() {
+        let log = Session::record(&PROGRAM, 1).log().clone();
+        let mut cpu = CPU::new(Bus::new());
+        assert!(matches!(replay(&mut cpu, &[0x00], &log, 10), Err(ReplayError::WrongRom { .. })));
+    }
+}
@@ -0,0 +1,158 @@
+use crate::cpu::CPU;
+use crate::instructions;
+use crate::replay::Session;
+use crate::savestate;
+use std::collections::VecDeque;
+
+// Snapshots are kept compressed with the fastest zstd level
+const COMPRESSION: i32 = 1;
+
+struct Snapshot {
+    instruction: u64,
+    state: Vec<u8>,
+    session: Option<Session>,
+}
+
+// Steps backwards by restoring the last snapshot before the target and
+// executing forward again up to it. This only gives the same result as long
+// as nothing outside of the CPU changed the machine in between (a poke from
+// the debugger, a loaded state...), so clear() the history when that happens.
+// With a Session, the random numbers and keys are replayed too.
+pub struct History {
+    interval: u64,
+    capacity: usize,
+    snapshots: VecDeque<Snapshot>,
+    instruction: u64,
+}
+
+impl History {
+    // A snapshot is taken every `interval` instructions and the oldest ones
+    // are dropped beyond `capacity`, so at most interval * capacity
+    // instructions can be undone.
+    pub fn new(interval: u64, capacity: usize) -> Self {
+        History {
+            interval,
+            capacity,
+            snapshots: VecDeque::new(),
+            instruction: 0,
+        }
+    }
+
+    // Instructions executed since the history started
+    pub fn position(&self) -> u64 {
+        self.instruction
+    }
+
+    // How many instructions can be stepped back from here
+    pub fn available(&self) -> u64 {
+        self.snapshots.front().map_or(0, |snapshot| self.instruction - snapshot.instruction)
+    }
+
+    // Forgets everything and starts again from the current state
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+    }
+
+    // Called before each instruction is executed, with the session that
+    // feeds the program if there is one
+    pub fn record(&mut self, cpu: &CPU, session: Option<&Session>) {
+        if self.snapshots.is_empty() || self.instruction.is_multiple_of(self.interval) {
+            if self.snapshots.len() == self.capacity {
+                self.snapshots.pop_front();
+            }
+            let state = savestate::save(cpu, 0, None);
+            self.snapshots.push_back(Snapshot {
+                instruction: self.instruction,
+                state: savestate::compress(&state, COMPRESSION).unwrap_or(state),
+                session: session.cloned(),
+            });
+        }
+        self.instruction += 1;
+    }
+
+    // Goes back `count` instructions, or as far as the history allows, and
+    // returns how many instructions were undone
+    pub fn step_back(&mut self, cpu: &mut CPU, mut session: Option<&mut Session>, count: u64) -> Result<u64, String> {
+        let count = count.min(self.available());
+        if count == 0 {
+            return Ok(0);
+        }
+        let target = self.instruction - count;
+
+        // Later snapshots would be taken again identically on the way forward
+        while self.snapshots.back().is_some_and(|snapshot| snapshot.instruction > target) {
+            self.snapshots.pop_back();
+        }
+        let snapshot = self.snapshots.pop_back().ok_or("No snapshot to go back to")?;
+        savestate::load(cpu, &snapshot.state, None).map_err(|e| e.to_string())?;
+        if let (Some(session), Some(saved)) = (session.as_deref_mut(), &snapshot.session) {
+            *session = saved.clone();
+        }
+
+        self.instruction = snapshot.instruction;
+        // record() counts the instructions up to the target
+        for _ in snapshot.instruction..target {
+            instructions::step_with(cpu, |cpu| {
+                self.record(cpu, session.as_deref());
+                if let Some(session) = session.as_deref_mut() {
+                    session.before_instruction(cpu);
+                }
+            })
+            .map_err(|e| e.to_string())?;
+        }
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_step_back_restores_earlier_state() {
+        let program = vec![
+            0xa5, 0xfe, //       LDA $fe
+            0x65, 0x10, //       ADC $10
+            0x85, 0x10, //       STA $10
+            0x4c, 0x00, 0x06, // JMP $0600
+        ];
+        let mut cpu = CPU::new(Bus::new());
+        cpu.load(program.clone());
+        cpu.program_counter = 0x0600;
+        let mut session = Session::record(&program, 7);
+        let mut history = History::new(16, 8);
+
+        let mut states = Vec::new();
+        for _ in 0..100 {
+            states.push(savestate::save(&cpu, 0, None));
+            history.record(&cpu, Some(&session));
+            session.before_instruction(&mut cpu);
+            cpu.step();
+        }
+
+        assert_eq!(history.step_back(&mut cpu, Some(&mut session), 1), Ok(1));
+        assert_eq!(savestate::save(&cpu, 0, None), states[99]);
+        assert_eq!(history.step_back(&mut cpu, Some(&mut session), 20), Ok(20));
+        assert_eq!(savestate::save(&cpu, 0, None), states[79]);
+        assert_eq!(history.position(), 79);
+
+        // Running forward again gives the same states
+        for state in &states[79..] {
+            assert_eq!(&savestate::save(&cpu, 0, None), state);
+            history.record(&cpu, Some(&session));
+            session.before_instruction(&mut cpu);
+            cpu.step();
+        }
+
+        for _ in 0..100 {
+            history.record(&cpu, Some(&session));
+            session.before_instruction(&mut cpu);
+            cpu.step();
+        }
+        // Only the last 8 snapshots are kept, from instruction 80 on
+        assert_eq!(history.available(), 120);
+        assert_eq!(history.step_back(&mut cpu, Some(&mut session), 1000), Ok(120));
+        assert_eq!(history.position(), 80);
+    }
+}
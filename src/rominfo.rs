@@ -0,0 +1,192 @@
+use crate::error::{MapperError, RomError};
+use crate::nes;
+use std::fmt;
+
+const INES_MAGIC: &[u8; 4] = b"NES\x1a";
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mirroring {
+    Horizontal,
+    Vertical,
+    FourScreen,
+}
+
+// The fields of an iNES or NES 2.0 header
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub nes2: bool,
+    pub mapper: u16,
+    pub submapper: u8,
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mirroring: Mirroring,
+    pub battery: bool,
+    pub trainer: bool,
+}
+
+impl Header {
+    // None if the data doesn't start with an iNES header
+    pub fn parse(data: &[u8]) -> Option<Header> {
+        if data.len() < HEADER_SIZE || &data[..4] != INES_MAGIC {
+            return None;
+        }
+        let flags6 = data[6];
+        let flags7 = data[7];
+        // NES 2.0 sets bits 2-3 of flags 7 to 10
+        let nes2 = flags7 & 0x0c == 0x08;
+
+        let mut mapper = (flags6 >> 4) as u16 | (flags7 & 0xf0) as u16;
+        let mut submapper = 0;
+        let mut prg_units = data[4] as usize;
+        let mut chr_units = data[5] as usize;
+        if nes2 {
+            mapper |= ((data[8] & 0x0f) as u16) << 8;
+            submapper = data[8] >> 4;
+            // The exponent-multiplier notation of huge ROMs isn't handled
+            prg_units |= ((data[9] & 0x0f) as usize) << 8;
+            chr_units |= ((data[9] >> 4) as usize) << 8;
+        }
+
+        let mirroring = if flags6 & 0x08 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0x01 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
+        Some(Header {
+            nes2,
+            mapper,
+            submapper,
+            prg_rom_size: prg_units * 16 * 1024,
+            chr_rom_size: chr_units * 8 * 1024,
+            mirroring,
+            battery: flags6 & 0x02 != 0,
+            trainer: flags6 & 0x04 != 0,
+        })
+    }
+
+    // Size of the whole file the header announces
+    pub fn expected_size(&self) -> usize {
+        HEADER_SIZE + if self.trainer { TRAINER_SIZE } else { 0 } + self.prg_rom_size + self.chr_rom_size
+    }
+}
+
+// Common name of the most used mappers
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    let name = match mapper {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        19 => "Namco 163",
+        24 | 26 => "VRC6",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica",
+        85 => "VRC7",
+        99 => "Vs. System",
+        _ => return None,
+    };
+    Some(name)
+}
+
+// What eNES makes of a ROM file, for "why won't it load" questions
+#[derive(Debug, Clone, PartialEq)]
+pub struct RomInfo {
+    pub size: usize,
+    pub crc32: u32,
+    pub sha1: String,
+    pub header: Option<Header>,
+    // Why the file can't be run, if it can't
+    pub problem: Option<String>,
+}
+
+impl RomInfo {
+    pub fn inspect(data: &[u8]) -> RomInfo {
+        let header = Header::parse(data);
+        let problem = match &header {
+            Some(header) if header.expected_size() > data.len() => Some(format!(
+                "The file is {} bytes but its header announces {}",
+                data.len(),
+                header.expected_size()
+            )),
+            Some(header) => Some(MapperError::Unsupported(header.mapper).to_string()),
+            None => nes::check_rom(data).err().map(|e: RomError| e.to_string()),
+        };
+        RomInfo {
+            size: data.len(),
+            crc32: crc32fast::hash(data),
+            sha1: sha1_smol::Sha1::from(data).digest().to_string(),
+            header,
+            problem,
+        }
+    }
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size:       {} bytes", self.size)?;
+        writeln!(f, "CRC32:      {:08X}", self.crc32)?;
+        writeln!(f, "SHA-1:      {}", self.sha1)?;
+        match &self.header {
+            None => writeln!(f, "Format:     raw 6502 program, loaded at $0600")?,
+            Some(header) => {
+                writeln!(f, "Format:     {}", if header.nes2 { "NES 2.0" } else { "iNES" })?;
+                let name = mapper_name(header.mapper).unwrap_or("unknown");
+                if header.nes2 {
+                    writeln!(f, "Mapper:     {} ({}), submapper {}", header.mapper, name, header.submapper)?;
+                } else {
+                    writeln!(f, "Mapper:     {} ({})", header.mapper, name)?;
+                }
+                writeln!(f, "PRG ROM:    {} KiB", header.prg_rom_size / 1024)?;
+                writeln!(f, "CHR ROM:    {} KiB", header.chr_rom_size / 1024)?;
+                writeln!(f, "Mirroring:  {:?}", header.mirroring)?;
+                writeln!(f, "Battery:    {}", if header.battery { "yes" } else { "no" })?;
+                writeln!(f, "Trainer:    {}", if header.trainer { "yes" } else { "no" })?;
+            }
+        }
+        match &self.problem {
+            None => writeln!(f, "Status:     OK"),
+            Some(problem) => writeln!(f, "Status:     {}", problem),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inspect_ines_and_raw_programs() {
+        // Mapper 4, 2 x 16 KiB PRG, 1 x 8 KiB CHR, vertical, battery
+        let mut rom = vec![0x4e, 0x45, 0x53, 0x1a, 2, 1, 0x43, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+        rom.resize(16 + 2 * 16384 + 8192, 0);
+        let info = RomInfo::inspect(&rom);
+        let header = info.header.clone().unwrap();
+        assert_eq!((header.mapper, header.prg_rom_size, header.chr_rom_size), (4, 32768, 8192));
+        assert_eq!(header.mirroring, Mirroring::Vertical);
+        assert!(header.battery && !header.trainer && !header.nes2);
+        assert!(info.to_string().contains("Mapper:     4 (MMC3)\n"));
+        assert_eq!(info.problem, Some(MapperError::Unsupported(4).to_string()));
+
+        rom.truncate(1000);
+        assert_eq!(
+            RomInfo::inspect(&rom).problem,
+            Some("The file is 1000 bytes but its header announces 40976".to_string())
+        );
+
+        let info = RomInfo::inspect(&[0xa9, 0x01, 0x00]);
+        assert_eq!(info.header, None);
+        assert_eq!(info.problem, None);
+        assert_eq!(info.sha1.len(), 40);
+    }
+}
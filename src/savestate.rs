@@ -0,0 +1,403 @@
+use crate::cpu::CPU;
+use crate::error::StateError;
+
+// Layout of a save state (all numbers little endian):
+//
+//   magic     "ENES"
+//   version   u8
+//   rom       crc32 of the ROM the state was saved with: u32
+//   chunks    until the end of the data, each one a 4 byte tag, the length
+//             of its payload as u32 and the payload
+//
+// Chunks:
+//
+//   "CPU "    a, x, y, status, sp: u8 each, pc: u16, cycles: u64 (missing
+//             from older states, read as 0)
+//   "RAM "    2048 bytes
+//   "THMB"    width: u16, height: u16, then width * height RGB24 pixels
+//             (optional)
+//   "ZSTD"    size of the chunks once decompressed: u32, then more chunks
+//             compressed with zstd (see compress())
+//
+// Chunks with an unknown tag are skipped and bytes past the end of a known
+// payload are ignored, so new chunks (PPU, APU, mapper) and new fields at the
+// end of a chunk can be added without changing the version. The version only
+// changes when older code can not make sense of the state anymore.
+//
+// Version 1 states had no ROM checksum nor chunks: the CPU registers, the RAM
+// and the thumbnail followed the version directly, in the same encoding.
+const MAGIC: &[u8; 4] = b"ENES";
+const VERSION: u8 = 2;
+
+const CPU_CHUNK: &[u8; 4] = b"CPU ";
+const RAM_CHUNK: &[u8; 4] = b"RAM ";
+const THUMBNAIL_CHUNK: &[u8; 4] = b"THMB";
+const ZSTD_CHUNK: &[u8; 4] = b"ZSTD";
+
+// Largest decompressed ZSTD chunk accepted, far more than the chunks of this
+// machine take, so that a broken or crafted state can't make us allocate
+// gigabytes
+const MAX_DECOMPRESSED_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Thumbnail {
+    pub width: u16,
+    pub height: u16,
+    pub rgb: Vec<u8>,
+}
+
+pub fn save(cpu: &CPU, rom_crc32: u32, thumbnail: Option<&Thumbnail>) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(MAGIC);
+    data.push(VERSION);
+    data.extend_from_slice(&rom_crc32.to_le_bytes());
+
+    let mut registers = vec![cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer];
+    registers.extend_from_slice(&cpu.program_counter.to_le_bytes());
+    registers.extend_from_slice(&cpu.cycles.to_le_bytes());
+    write_chunk(&mut data, CPU_CHUNK, &registers);
+    write_chunk(&mut data, RAM_CHUNK, cpu.bus.ram());
+
+    if let Some(thumbnail) = thumbnail {
+        let mut payload = Vec::with_capacity(4 + thumbnail.rgb.len());
+        payload.extend_from_slice(&thumbnail.width.to_le_bytes());
+        payload.extend_from_slice(&thumbnail.height.to_le_bytes());
+        payload.extend_from_slice(&thumbnail.rgb);
+        write_chunk(&mut data, THUMBNAIL_CHUNK, &payload);
+    }
+    data
+}
+
+// A hash of everything that decides how emulation continues: the CPU
+// registers and the RAM. Two machines with the same hash run the same way
+// given the same input, which is how replays and netplay peers spot a desync.
+// FNV-1a, so that it's the same on every platform and Rust version.
+pub fn hash(cpu: &CPU) -> u64 {
+    let registers = [cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer];
+    let program_counter = cpu.program_counter.to_le_bytes();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for &byte in registers.iter().chain(program_counter.iter()).chain(cpu.bus.ram()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+    hash
+}
+
+fn write_chunk(data: &mut Vec<u8>, tag: &[u8; 4], payload: &[u8]) {
+    data.extend_from_slice(tag);
+    data.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    data.extend_from_slice(payload);
+}
+
+// The checksum of the ROM a state was saved with, or None for the version 1
+// states that didn't record it
+pub fn rom_crc32(data: &[u8]) -> Result<Option<u32>, StateError> {
+    let mut reader = Reader { data, pos: 0 };
+    match read_version(&mut reader)? {
+        1 => Ok(None),
+        _ => Ok(Some(reader.u32()?)),
+    }
+}
+
+// Restores the machine from a save state and returns its thumbnail, if any.
+// The state must have been saved with the ROM of checksum `rom_crc32`,
+// unless it's None (e.g. to compare states). Version 1 states are trusted.
+// The CPU is left untouched when the state can not be read.
+pub fn load(cpu: &mut CPU, data: &[u8], rom_crc32: Option<u32>) -> Result<Option<Thumbnail>, StateError> {
+    let mut reader = Reader { data, pos: 0 };
+    let mut contents = Contents::default();
+    match read_version(&mut reader)? {
+        1 => read_version_1(&mut reader, cpu.bus.ram().len(), &mut contents)?,
+        _ => {
+            let found = reader.u32()?;
+            if let Some(expected) = rom_crc32.filter(|&expected| expected != found) {
+                return Err(StateError::WrongRom { expected, found });
+            }
+            read_chunks(&mut reader, cpu.bus.ram().len(), false, &mut contents)?
+        }
+    }
+    let (registers, program_counter) = contents.cpu.ok_or(StateError::MissingChunk("CPU"))?;
+    let ram = contents.ram.ok_or(StateError::MissingChunk("RAM"))?;
+
+    cpu.register_a = registers[0];
+    cpu.register_x = registers[1];
+    cpu.register_y = registers[2];
+    cpu.status = registers[3];
+    cpu.stack_pointer = registers[4];
+    cpu.program_counter = program_counter;
+    cpu.cycles = contents.cycles.unwrap_or(0);
+    cpu.bus.load_ram(&ram);
+    Ok(contents.thumbnail)
+}
+
+// Packs the chunks of a state into a single zstd compressed chunk. Level 0
+// leaves the state as it is, otherwise it goes from 1 (fastest) to 22.
+// States of version 1 can't be compressed.
+pub fn compress(state: &[u8], level: i32) -> Result<Vec<u8>, StateError> {
+    let mut reader = Reader { data: state, pos: 0 };
+    if read_version(&mut reader)? == 1 {
+        return Err(StateError::NotCompressible);
+    }
+    reader.u32()?;
+    if level == 0 {
+        return Ok(state.to_vec());
+    }
+
+    let (header, chunks) = state.split_at(reader.pos);
+    let compressed = zstd::bulk::compress(chunks, level).map_err(|e| StateError::Compression(e.to_string()))?;
+    let mut data = header.to_vec();
+    let mut payload = (chunks.len() as u32).to_le_bytes().to_vec();
+    payload.extend_from_slice(&compressed);
+    write_chunk(&mut data, ZSTD_CHUNK, &payload);
+    Ok(data)
+}
+
+#[derive(Default)]
+struct Contents {
+    cpu: Option<(Vec<u8>, u16)>,
+    cycles: Option<u64>,
+    ram: Option<Vec<u8>>,
+    thumbnail: Option<Thumbnail>,
+}
+
+fn read_version(reader: &mut Reader) -> Result<u8, StateError> {
+    if reader.take(MAGIC.len())? != MAGIC {
+        return Err(StateError::NotAState);
+    }
+    let version = reader.u8()?;
+    if version == 0 || version > VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    Ok(version)
+}
+
+fn read_version_1(reader: &mut Reader, ram_len: usize, contents: &mut Contents) -> Result<(), StateError> {
+    let registers = reader.take(5)?.to_vec();
+    contents.cpu = Some((registers, reader.u16()?));
+    contents.ram = Some(reader.take(ram_len)?.to_vec());
+    contents.thumbnail = read_thumbnail(reader)?;
+    Ok(())
+}
+
+// The chunks of a ZSTD chunk (`decompressed`) can't be compressed again
+fn read_chunks(reader: &mut Reader, ram_len: usize, decompressed: bool, contents: &mut Contents) -> Result<(), StateError> {
+    while !reader.is_empty() {
+        let tag = reader.take(4)?;
+        let len = reader.u32()? as usize;
+        let mut payload = Reader { data: reader.take(len)?, pos: 0 };
+        match tag {
+            t if t == CPU_CHUNK => {
+                contents.cpu = Some((payload.take(5)?.to_vec(), payload.u16()?));
+                contents.cycles = if payload.is_empty() { None } else { Some(payload.u64()?) };
+            }
+            t if t == RAM_CHUNK => contents.ram = Some(payload.take(ram_len)?.to_vec()),
+            t if t == THUMBNAIL_CHUNK => contents.thumbnail = read_thumbnail(&mut payload)?,
+            t if t == ZSTD_CHUNK => {
+                if decompressed {
+                    return Err(StateError::NestedCompression);
+                }
+                if len < 4 {
+                    return Err(StateError::Truncated);
+                }
+                let size = payload.u32()? as usize;
+                if size > MAX_DECOMPRESSED_SIZE {
+                    return Err(StateError::TooLarge(size));
+                }
+                let chunks = zstd::bulk::decompress(payload.take(len - 4)?, size)
+                    .map_err(|e| StateError::Decompression(e.to_string()))?;
+                read_chunks(&mut Reader { data: &chunks, pos: 0 }, ram_len, true, contents)?;
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn read_thumbnail(reader: &mut Reader) -> Result<Option<Thumbnail>, StateError> {
+    let width = reader.u16()?;
+    let height = reader.u16()?;
+    if width == 0 || height == 0 {
+        return Ok(None);
+    }
+    let rgb = reader.take(width as usize * height as usize * 3)?;
+    Ok(Some(Thumbnail { width, height, rgb: rgb.to_vec() }))
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.data.len() - self.pos < len {
+            return Err(StateError::Truncated);
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, StateError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        let bytes = self.take(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn u64(&mut self) -> Result<u64, StateError> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos == self.data.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_save_and_load_round_trip() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_a = 0x11;
+        cpu.register_x = 0x22;
+        cpu.register_y = 0x33;
+        cpu.status = 0b1100_0001;
+        cpu.stack_pointer = 0xf0;
+        cpu.program_counter = 0x0612;
+        cpu.cycles = 0x1_0000_0007;
+        cpu.mem_write(0x0010, 0x55);
+        let thumbnail = Thumbnail { width: 2, height: 1, rgb: vec![1, 2, 3, 4, 5, 6] };
+
+        let state = save(&cpu, 0x1234_5678, Some(&thumbnail));
+
+        let mut restored = CPU::new(Bus::new());
+        assert_eq!(load(&mut restored, &state, Some(0x1234_5678)), Ok(Some(thumbnail)));
+        assert_eq!(restored.register_a, 0x11);
+        assert_eq!(restored.register_x, 0x22);
+        assert_eq!(restored.register_y, 0x33);
+        assert_eq!(restored.status, 0b1100_0001);
+        assert_eq!(restored.stack_pointer, 0xf0);
+        assert_eq!(restored.program_counter, 0x0612);
+        assert_eq!(restored.cycles, 0x1_0000_0007);
+        assert_eq!(restored.mem_read(0x0010), 0x55);
+        assert_eq!(rom_crc32(&state), Ok(Some(0x1234_5678)));
+    }
+
+    #[test]
+    fn test_load_skips_unknown_chunks_and_reads_version_1() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_a = 0x11;
+        cpu.program_counter = 0x0612;
+
+        let mut state = save(&cpu, 0, None);
+        write_chunk(&mut state, b"PPU ", &[1, 2, 3]);
+        let mut restored = CPU::new(Bus::new());
+        assert_eq!(load(&mut restored, &state, Some(0)), Ok(None));
+        assert_eq!(restored.register_a, 0x11);
+
+        let mut old = b"ENES\x01".to_vec();
+        old.extend_from_slice(&[0x22, 0, 0, 0, 0xfd, 0x34, 0x12]);
+        old.extend_from_slice(&[0; 2048]);
+        old.extend_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(load(&mut restored, &old, Some(0x1234_5678)), Ok(None));
+        assert_eq!(restored.register_a, 0x22);
+        assert_eq!(restored.program_counter, 0x1234);
+        assert_eq!(rom_crc32(&old), Ok(None));
+    }
+
+    #[test]
+    fn test_compressed_state_loads_transparently() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_x = 0x33;
+        cpu.mem_write(0x0300, 0x77);
+        let thumbnail = Thumbnail { width: 32, height: 32, rgb: vec![0; 32 * 32 * 3] };
+        let state = save(&cpu, 0xabcd, Some(&thumbnail));
+
+        let compressed = compress(&state, 3).unwrap();
+        assert!(compressed.len() < state.len() / 4);
+        assert_eq!(rom_crc32(&compressed), Ok(Some(0xabcd)));
+        assert_eq!(compress(&state, 0), Ok(state));
+
+        let mut restored = CPU::new(Bus::new());
+        assert_eq!(load(&mut restored, &compressed, Some(0xabcd)), Ok(Some(thumbnail)));
+        assert_eq!(restored.register_x, 0x33);
+        assert_eq!(restored.mem_read(0x0300), 0x77);
+    }
+
+    #[test]
+    fn test_hash_follows_emulation_state() {
+        let mut cpu = CPU::new(Bus::new());
+        let mut other = CPU::new(Bus::new());
+        assert_eq!(hash(&cpu), hash(&other));
+
+        cpu.mem_write(0x07ff, 1);
+        assert_ne!(hash(&cpu), hash(&other));
+        other.mem_write(0x07ff, 1);
+        assert_eq!(hash(&cpu), hash(&other));
+
+        other.status ^= 0b0000_0001;
+        assert_ne!(hash(&cpu), hash(&other));
+    }
+
+    #[test]
+    fn test_load_rejects_truncated_state() {
+        let cpu = CPU::new(Bus::new());
+        let state = save(&cpu, 0, None);
+
+        let mut restored = CPU::new(Bus::new());
+        restored.register_a = 0x42;
+        assert_eq!(load(&mut restored, &state[..state.len() - 1], None), Err(StateError::Truncated));
+        assert_eq!(restored.register_a, 0x42);
+    }
+
+    #[test]
+    fn test_load_rejects_oversized_and_nested_compression() {
+        let state = save(&CPU::new(Bus::new()), 0, None);
+        let header = &state[..9];
+        let mut restored = CPU::new(Bus::new());
+
+        let mut huge = header.to_vec();
+        write_chunk(&mut huge, ZSTD_CHUNK, &u32::MAX.to_le_bytes());
+        assert_eq!(load(&mut restored, &huge, None), Err(StateError::TooLarge(u32::MAX as usize)));
+
+        let mut short = header.to_vec();
+        write_chunk(&mut short, ZSTD_CHUNK, &[1, 0]);
+        assert_eq!(load(&mut restored, &short, None), Err(StateError::Truncated));
+
+        // A ZSTD chunk holding another ZSTD chunk
+        let compressed = compress(&state, 3).unwrap();
+        let inner = &compressed[9..];
+        let mut payload = (inner.len() as u32).to_le_bytes().to_vec();
+        payload.extend_from_slice(&zstd::bulk::compress(inner, 3).unwrap());
+        let mut nested = header.to_vec();
+        write_chunk(&mut nested, ZSTD_CHUNK, &payload);
+        assert_eq!(load(&mut restored, &nested, None), Err(StateError::NestedCompression));
+    }
+
+    #[test]
+    fn test_load_rejects_state_of_another_rom() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.register_a = 0x11;
+        let state = compress(&save(&cpu, 0xabcd, None), 3).unwrap();
+
+        let mut restored = CPU::new(Bus::new());
+        assert_eq!(load(&mut restored, &state, Some(0x1234)), Err(StateError::WrongRom { expected: 0x1234, found: 0xabcd }));
+        assert_eq!(restored.register_a, 0);
+        assert_eq!(load(&mut restored, &state, None), Ok(None));
+        assert_eq!(restored.register_a, 0x11);
+    }
+}
@@ -0,0 +1,135 @@
+use enes::error::StateError;
+use enes::nes::{Nes, SCREEN_HEIGHT, SCREEN_WIDTH};
+use enes::savestate::{self, Thumbnail};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Numbered save states of one game, stored as <dir>/<game>.ss<slot>
+pub struct SaveSlots {
+    dir: PathBuf,
+    game: String,
+    rom_crc32: u32,
+    compression: i32,
+    pub current: u8,
+}
+
+impl SaveSlots {
+    pub fn new(dir: PathBuf, game: &str, rom: &[u8], compression: i32) -> Self {
+        SaveSlots {
+            dir,
+            game: game.to_string(),
+            rom_crc32: crc32fast::hash(rom),
+            compression,
+            current: 1,
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{}.ss{}", self.game, slot))
+    }
+
+    pub fn save(&self, nes: &Nes) -> Result<PathBuf, String> {
+        let path = self.path(self.current);
+        write_state(&path, self.rom_crc32, self.compression, nes)?;
+        Ok(path)
+    }
+
+    pub fn load(&self, nes: &mut Nes) -> Result<PathBuf, String> {
+        let path = self.path(self.current);
+        read_state(&path, nes)?;
+        Ok(path)
+    }
+}
+
+// State of the last session, stored as <dir>/autosave/<crc32 of the ROM>.state
+// so it is found again even if the ROM file is renamed or moved.
+pub struct AutoSave {
+    path: PathBuf,
+    rom_crc32: u32,
+    compression: i32,
+}
+
+impl AutoSave {
+    pub fn new(dir: &Path, rom: &[u8], compression: i32) -> Self {
+        let rom_crc32 = crc32fast::hash(rom);
+        AutoSave {
+            path: dir.join("autosave").join(format!("{:08x}.state", rom_crc32)),
+            rom_crc32,
+            compression,
+        }
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    pub fn save(&self, nes: &Nes) -> Result<(), String> {
+        write_state(&self.path, self.rom_crc32, self.compression, nes)
+    }
+
+    pub fn load(&self, nes: &mut Nes) -> Result<(), String> {
+        read_state(&self.path, nes)
+    }
+}
+
+fn write_state(path: &Path, rom_crc32: u32, compression: i32, nes: &Nes) -> Result<(), String> {
+    let thumbnail = Thumbnail { width: SCREEN_WIDTH as u16, height: SCREEN_HEIGHT as u16, rgb: nes.frame().to_vec() };
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("Could not create {}: {}", dir.display(), e))?;
+    }
+    let state = savestate::compress(&savestate::save(nes.cpu(), rom_crc32, Some(&thumbnail)), compression)
+        .map_err(|e| e.to_string())?;
+    fs::write(path, state)
+        .map_err(|e| format!("Could not write {}: {}", path.display(), e))
+}
+
+// The ROM checksum is checked by Nes::load_state against the loaded ROM
+fn read_state(path: &Path, nes: &mut Nes) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+    nes.load_state(&data).map_err(|e| match e {
+        StateError::WrongRom { found, .. } => format!("{} was saved with another ROM ({:08x})", path.display(), found),
+        e => format!("{}: {}", path.display(), e),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // LDA #$05; STA $10; BRK
+    const GAME: &[u8] = &[0xa9, 0x05, 0x85, 0x10, 0x00];
+
+    #[test]
+    fn test_states_round_trip_for_the_same_rom_only() {
+        let dir = std::env::temp_dir().join(format!("enes-slots-test-{}", std::process::id()));
+        let mut nes = Nes::builder().seed(1).stop_on_brk(true).build().unwrap();
+        nes.load_rom(GAME).unwrap();
+        assert_eq!(nes.run_frame(), Ok(false));
+
+        let mut slots = SaveSlots::new(dir.clone(), "game", GAME, 3);
+        slots.current = 2;
+        assert_eq!(slots.save(&nes).unwrap(), dir.join("game.ss2"));
+        let autosave = AutoSave::new(&dir, GAME, 3);
+        assert!(!autosave.exists());
+        autosave.save(&nes).unwrap();
+        assert!(dir.join("autosave").join(format!("{:08x}.state", crc32fast::hash(GAME))).exists());
+
+        nes.power_cycle();
+        assert_eq!(slots.load(&mut nes).unwrap(), dir.join("game.ss2"));
+        assert_eq!(nes.cpu().bus.peek(0x10), 0x05);
+        nes.power_cycle();
+        autosave.load(&mut nes).unwrap();
+        assert_eq!(nes.cpu().bus.peek(0x10), 0x05);
+
+        let other = [0xea, 0x00];
+        nes.load_rom(&other).unwrap();
+        let mut other_slots = SaveSlots::new(dir.clone(), "game", &other, 3);
+        other_slots.current = 2;
+        assert_eq!(
+            other_slots.load(&mut nes),
+            Err(format!("{} was saved with another ROM ({:08x})", dir.join("game.ss2").display(), crc32fast::hash(GAME)))
+        );
+        assert!(!AutoSave::new(&dir, &other, 3).exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
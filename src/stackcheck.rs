@@ -0,0 +1,156 @@
+use crate::callstack::{CallStack, Return};
+use crate::cpu::CPU;
+use crate::opcodes;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackProblem {
+    // A push wraps the stack pointer from $00 to $FF
+    Overflow,
+    // A pull wraps the stack pointer from $FF to $00
+    Underflow,
+    // An RTS returns somewhere else than after the last JSR, or with no JSR
+    // to return from
+    UnbalancedReturn { expected: Option<u16>, actual: u16 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackIssue {
+    pub problem: StackProblem,
+    pub pc: u16,
+    pub stack_pointer: u8,
+    // Addresses of the JSRs that led here, innermost first
+    pub backtrace: Vec<u16>,
+}
+
+impl fmt::Display for StackIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.problem {
+            StackProblem::Overflow => write!(f, "Stack overflow")?,
+            StackProblem::Underflow => write!(f, "Stack underflow")?,
+            StackProblem::UnbalancedReturn { expected: Some(expected), actual } => write!(
+                f,
+                "RTS returns to ${:04X} instead of ${:04X}",
+                actual, expected
+            )?,
+            StackProblem::UnbalancedReturn { expected: None, actual } => {
+                write!(f, "RTS to ${:04X} without a JSR", actual)?
+            }
+        }
+        write!(f, " at ${:04X} (SP=${:02X})", self.pc, self.stack_pointer)?;
+        for (i, site) in self.backtrace.iter().enumerate() {
+            let separator = if i == 0 { ", called from" } else { " <-" };
+            write!(f, "{} ${:04X}", separator, site)?;
+        }
+        Ok(())
+    }
+}
+
+// Watches the stack for the classic homebrew bugs: too many pushes or pulls,
+// and subroutines that leave something on the stack (or take too much from
+// it) so that RTS doesn't go back to the caller. Code that jumps by pushing
+// an address and executing RTS is reported too.
+pub struct StackChecker {
+    calls: CallStack,
+}
+
+impl StackChecker {
+    pub fn new() -> Self {
+        StackChecker { calls: CallStack::new() }
+    }
+
+    // Called before each instruction is executed
+    pub fn check(&mut self, cpu: &CPU) -> Option<StackIssue> {
+        let pc = cpu.program_counter;
+        let sp = cpu.stack_pointer;
+        let (pushes, pulls) = match opcodes::OPCODES_MAP.get(&cpu.bus.peek(pc)) {
+            Some(opcode) => opcode.stack_effect(),
+            None => return None,
+        };
+        if pushes == 0 && pulls == 0 {
+            return None;
+        }
+
+        let mut problem = None;
+        if sp < pushes {
+            problem = Some(StackProblem::Overflow);
+        } else if sp as u16 + pulls as u16 > 0xff {
+            problem = Some(StackProblem::Underflow);
+        }
+
+        if let Some(Return::Unbalanced { expected, actual }) = self.calls.update(cpu) {
+            problem = problem.or(Some(StackProblem::UnbalancedReturn { expected, actual }));
+        }
+
+        problem.map(|problem| StackIssue {
+            problem,
+            pc,
+            stack_pointer: sp,
+            backtrace: self.calls.calls().iter().rev().map(|call| call.site).collect(),
+        })
+    }
+}
+
+impl Default for StackChecker {
+    fn default() -> Self {
+        StackChecker::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    fn run(program: Vec<u8>, checker: &mut StackChecker, cpu: &mut CPU) -> Vec<StackIssue> {
+        cpu.load(program);
+        cpu.program_counter = 0x0600;
+        let mut issues = Vec::new();
+        loop {
+            issues.extend(checker.check(cpu));
+            if !cpu.step() {
+                return issues;
+            }
+        }
+    }
+
+    #[test]
+    fn test_reports_unbalanced_return_with_backtrace() {
+        let mut cpu = CPU::new(Bus::new());
+        // JSR $0606; BRK; BRK; BRK; $0606: JSR $060a; RTS; $060a: RTS
+        cpu.load(vec![0x20, 0x06, 0x06, 0x00, 0x00, 0x00, 0x20, 0x0a, 0x06, 0x60, 0x60]);
+        cpu.program_counter = 0x0600;
+        let mut checker = StackChecker::new();
+        for _ in 0..2 {
+            assert_eq!(checker.check(&cpu), None);
+            cpu.step();
+        }
+
+        // As if the subroutine had pushed a byte and not pulled it
+        cpu.stack_pointer -= 1;
+        let issue = checker.check(&cpu).unwrap();
+        assert_eq!(issue.problem, StackProblem::UnbalancedReturn { expected: Some(0x0608), actual: 0x0800 });
+        assert_eq!(issue.backtrace, vec![0x0606, 0x0600]);
+        assert_eq!(
+            issue.to_string(),
+            "RTS returns to $0800 instead of $0608 at $060A (SP=$F8), called from $0606 <- $0600"
+        );
+    }
+
+    #[test]
+    fn test_reports_wrapping_stack_pointer() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        cpu.stack_pointer = 0x01;
+        // JSR $0603; BRK
+        let issues = run(vec![0x20, 0x03, 0x06, 0x00], &mut StackChecker::new(), &mut cpu);
+        assert_eq!(issues[0].problem, StackProblem::Overflow);
+
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        cpu.stack_pointer = 0xfe;
+        // RTS
+        let issues = run(vec![0x60], &mut StackChecker::new(), &mut cpu);
+        assert_eq!(issues[0].problem, StackProblem::Underflow);
+    }
+}
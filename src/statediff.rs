@@ -0,0 +1,130 @@
+use crate::bus::Bus;
+use crate::cpu::CPU;
+use crate::error::StateError;
+use crate::savestate;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub name: &'static str,
+    pub before: u16,
+    pub after: u16,
+}
+
+// A run of consecutive RAM bytes that differ, with their values in the
+// second state
+#[derive(Debug, Clone, PartialEq)]
+pub struct RamChange {
+    pub start: u16,
+    pub after: Vec<u8>,
+}
+
+impl RamChange {
+    pub fn end(&self) -> u16 {
+        self.start + self.after.len() as u16 - 1
+    }
+}
+
+// What differs between two machine states. The RAM changes are enough to
+// turn the first state into the second, so they double as a delta.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StateDiff {
+    pub registers: Vec<RegisterChange>,
+    pub ram: Vec<RamChange>,
+}
+
+impl StateDiff {
+    pub fn is_empty(&self) -> bool {
+        self.registers.is_empty() && self.ram.is_empty()
+    }
+}
+
+impl fmt::Display for StateDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "The states are identical");
+        }
+        for change in &self.registers {
+            let width = if change.name == "PC" { 4 } else { 2 };
+            writeln!(f, "{:<3} {:0w$X} -> {:0w$X}", change.name, change.before, change.after, w = width)?;
+        }
+        for change in &self.ram {
+            if change.after.len() == 1 {
+                writeln!(f, "RAM ${:04X}", change.start)?;
+            } else {
+                writeln!(f, "RAM ${:04X}-${:04X} ({} bytes)", change.start, change.end(), change.after.len())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn diff(before: &CPU, after: &CPU) -> StateDiff {
+    let registers = [
+        ("A", before.register_a as u16, after.register_a as u16),
+        ("X", before.register_x as u16, after.register_x as u16),
+        ("Y", before.register_y as u16, after.register_y as u16),
+        ("P", before.status as u16, after.status as u16),
+        ("SP", before.stack_pointer as u16, after.stack_pointer as u16),
+        ("PC", before.program_counter, after.program_counter),
+    ];
+    let registers = registers
+        .iter()
+        .filter(|(_, before, after)| before != after)
+        .map(|&(name, before, after)| RegisterChange { name, before, after })
+        .collect();
+
+    let mut ram: Vec<RamChange> = Vec::new();
+    for (addr, (old, new)) in before.bus.ram().iter().zip(after.bus.ram()).enumerate() {
+        if old == new {
+            continue;
+        }
+        match ram.last_mut() {
+            Some(change) if change.end() as usize + 1 == addr => change.after.push(*new),
+            _ => ram.push(RamChange {
+                start: addr as u16,
+                after: vec![*new],
+            }),
+        }
+    }
+    StateDiff { registers, ram }
+}
+
+// Same as diff(), for two save states
+pub fn diff_states(before: &[u8], after: &[u8]) -> Result<StateDiff, StateError> {
+    let mut first = CPU::new(Bus::new());
+    savestate::load(&mut first, before, None)?;
+    let mut second = CPU::new(Bus::new());
+    savestate::load(&mut second, after, None)?;
+    Ok(diff(&first, &second))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::Mem;
+
+    #[test]
+    fn test_diff_reports_registers_and_ram_ranges() {
+        let mut cpu = CPU::new(Bus::new());
+        let before = savestate::save(&cpu, 0, None);
+
+        cpu.register_x = 0x10;
+        cpu.program_counter = 0x0612;
+        cpu.mem_write(0x0010, 1);
+        cpu.mem_write(0x0011, 2);
+        cpu.mem_write(0x0200, 3);
+        let diff = diff_states(&before, &savestate::save(&cpu, 0, None)).unwrap();
+
+        assert_eq!(diff.registers.iter().map(|change| change.name).collect::<Vec<_>>(), vec!["X", "PC"]);
+        assert_eq!(
+            diff.ram,
+            vec![RamChange { start: 0x0010, after: vec![1, 2] }, RamChange { start: 0x0200, after: vec![3] }]
+        );
+        assert_eq!(
+            diff.to_string(),
+            "X   00 -> 10\nPC  0000 -> 0612\nRAM $0010-$0011 (2 bytes)\nRAM $0200\n"
+        );
+        assert!(diff_states(&before, &before).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,102 @@
+use crate::cpu::CPU;
+use crate::opcodes;
+use std::collections::BTreeMap;
+
+// How often each opcode was executed
+pub struct InstructionStats {
+    counts: Vec<u64>,
+}
+
+impl InstructionStats {
+    pub fn new() -> Self {
+        InstructionStats { counts: vec![0; 256] }
+    }
+
+    // Called before each instruction is executed
+    pub fn record(&mut self, cpu: &CPU) {
+        self.counts[cpu.bus.peek(cpu.program_counter) as usize] += 1;
+    }
+
+    pub fn count(&self, opcode: u8) -> u64 {
+        self.counts[opcode as usize]
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Executions per addressing mode, by the name of the mode. Opcodes the
+    // CPU doesn't know are counted as "unknown".
+    pub fn by_mode(&self) -> BTreeMap<String, u64> {
+        let mut modes = BTreeMap::new();
+        for (code, &count) in self.counts.iter().enumerate().filter(|(_, &count)| count > 0) {
+            *modes.entry(mode_name(code as u8)).or_insert(0) += count;
+        }
+        modes
+    }
+
+    // "opcode,mnemonic,mode,count" lines for the executed opcodes, the most
+    // used first
+    pub fn to_csv(&self) -> String {
+        let mut executed: Vec<(u8, u64)> = (0..=255u8)
+            .map(|code| (code, self.count(code)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        executed.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+
+        let mut csv = String::from("opcode,mnemonic,mode,count\n");
+        for (code, count) in executed {
+            let mnemonic = opcodes::OPCODES_MAP.get(&code).map_or("???", |opcode| opcode.mnemonic);
+            csv += &format!("{:02X},{},{},{}\n", code, mnemonic, mode_name(code), count);
+        }
+        csv
+    }
+}
+
+impl Default for InstructionStats {
+    fn default() -> Self {
+        InstructionStats::new()
+    }
+}
+
+fn mode_name(code: u8) -> String {
+    match opcodes::OPCODES_MAP.get(&code) {
+        Some(opcode) => format!("{:?}", opcode.mode),
+        None => "unknown".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_counts_by_opcode_and_mode() {
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDX #$03; loop: DEX; BNE loop; STX $10; BRK
+        cpu.load(vec![0xa2, 0x03, 0xca, 0xd0, 0xfd, 0x86, 0x10, 0x00]);
+        cpu.program_counter = 0x0600;
+
+        let mut stats = InstructionStats::new();
+        loop {
+            stats.record(&cpu);
+            if !cpu.step() {
+                break;
+            }
+        }
+
+        assert_eq!(stats.count(0xca), 3);
+        assert_eq!(stats.count(0xd0), 3);
+        assert_eq!(stats.total(), 1 + 3 + 3 + 1 + 1);
+
+        let modes = stats.by_mode();
+        assert_eq!(modes["Immediate"], 1);
+        assert_eq!(modes["ZeroPage"], 1);
+        assert_eq!(modes["NoneAddressing"], 3 + 3 + 1);
+
+        let csv = stats.to_csv();
+        assert!(csv.starts_with("opcode,mnemonic,mode,count\nCA,DEX,NoneAddressing,3\nD0,BNE,NoneAddressing,3\n"));
+    }
+}
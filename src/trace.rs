@@ -0,0 +1,171 @@
+use crate::cpu::CPU;
+use crate::disasm;
+use crate::labels::Labels;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// Traces are written in large chunks, a line per instruction adds up quickly
+const BUFFER_SIZE: usize = 1 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TraceField {
+    // Instruction bytes and disassembly
+    Disassembly,
+    // A, X, Y, P and SP before the instruction runs
+    Registers,
+    // CPU cycles since the trace started
+    Cycles,
+}
+
+impl FromStr for TraceField {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, String> {
+        match text {
+            "disassembly" => Ok(TraceField::Disassembly),
+            "registers" => Ok(TraceField::Registers),
+            "cycles" => Ok(TraceField::Cycles),
+            _ => Err(format!("Unknown trace field '{}', expected disassembly, registers or cycles", text)),
+        }
+    }
+}
+
+// Writes one line per executed instruction, in the spirit of the nestest log:
+//
+//   0600  20 06 06  JSR init          A:00 X:00 Y:00 P:00 SP:FD  CYC:0
+pub struct Tracer {
+    out: BufWriter<Box<dyn Write + Send>>,
+    fields: Vec<TraceField>,
+    ranges: Vec<RangeInclusive<u16>>,
+    labels: Labels,
+    // CPU cycles when the trace started
+    start: Option<u64>,
+}
+
+impl Tracer {
+    // Only instructions inside one of `ranges` are logged, or all of them when
+    // there are no ranges. Fields are written in a fixed order.
+    pub fn new(out: Box<dyn Write + Send>, fields: &[TraceField], ranges: Vec<RangeInclusive<u16>>, labels: Labels) -> Self {
+        Tracer {
+            out: BufWriter::with_capacity(BUFFER_SIZE, out),
+            fields: fields.to_vec(),
+            ranges,
+            labels,
+            start: None,
+        }
+    }
+
+    // Called before each instruction is executed
+    pub fn trace(&mut self, cpu: &CPU) -> io::Result<()> {
+        let pc = cpu.program_counter;
+        let cycles = cpu.cycles - *self.start.get_or_insert(cpu.cycles);
+
+        if !self.ranges.is_empty() && !self.ranges.iter().any(|range| range.contains(&pc)) {
+            return Ok(());
+        }
+
+        write!(self.out, "{:04X}", pc)?;
+        if self.fields.contains(&TraceField::Disassembly) {
+            let instruction = disasm::disassemble(&cpu.bus, pc);
+            write!(self.out, "  ")?;
+            for i in 0..3 {
+                match instruction.bytes.get(i) {
+                    Some(byte) => write!(self.out, "{:02X} ", byte)?,
+                    None => write!(self.out, "   ")?,
+                }
+            }
+            write!(self.out, " {:<16}", instruction.labelled_text(&self.labels))?;
+        }
+        if self.fields.contains(&TraceField::Registers) {
+            write!(
+                self.out,
+                " A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+                cpu.register_a, cpu.register_x, cpu.register_y, cpu.status, cpu.stack_pointer
+            )?;
+        }
+        if self.fields.contains(&TraceField::Cycles) {
+            write!(self.out, "  CYC:{}", cycles)?;
+        }
+        if let Some(name) = self.labels.name(pc) {
+            write!(self.out, "  ; {}", name)?;
+        }
+        writeln!(self.out)
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// "0600-06ff" or a single address "0600", in hex
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |hex: &str| {
+        let digits = hex.trim_start_matches('$').trim_start_matches("0x");
+        u16::from_str_radix(digits, 16).map_err(|_| format!("'{}' is not a hex address", hex))
+    };
+    let (start, end) = match text.split_once('-') {
+        Some((start, end)) => (parse(start)?, parse(end)?),
+        None => (parse(text)?, parse(text)?),
+    };
+    if start > end {
+        return Err(format!("Range {} ends before it starts", text));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+    use std::sync::{Arc, Mutex};
+
+    // Keeps what was written so the test can look at it after the tracer is done
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_trace_fields_and_ranges() {
+        let buffer = SharedBuffer::default();
+        let mut cpu = CPU::new(Bus::new());
+        cpu.stop_on_brk = true;
+        // LDA #$05; TAX; INX; BRK
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]);
+        cpu.program_counter = 0x0600;
+
+        let fields = [TraceField::Disassembly, TraceField::Registers, TraceField::Cycles];
+        let mut tracer = Tracer::new(Box::new(buffer.clone()), &fields, vec![0x0602..=0x0603], Labels::new());
+        loop {
+            tracer.trace(&cpu).unwrap();
+            if !cpu.step() {
+                break;
+            }
+        }
+        tracer.finish().unwrap();
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0], "0602  AA        TAX              A:05 X:00 Y:00 P:00 SP:FD  CYC:2");
+        assert!(lines[1].starts_with("0603  E8        INX"));
+        assert!(lines[1].ends_with("X:05 Y:00 P:00 SP:FD  CYC:4"));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("0600-06ff"), Ok(0x0600..=0x06ff));
+        assert_eq!(parse_range("$0010"), Ok(0x0010..=0x0010));
+        assert!(parse_range("06ff-0600").is_err());
+    }
+}
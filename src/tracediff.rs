@@ -0,0 +1,214 @@
+use crate::cpu::CPU;
+use crate::disasm;
+use std::collections::VecDeque;
+use std::fmt;
+
+// Lines of context shown before a divergence
+const CONTEXT: usize = 5;
+
+// CPU state read from one line of a reference trace. Registers missing from
+// the line are not compared.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Expected {
+    pub pc: u16,
+    pub a: Option<u8>,
+    pub x: Option<u8>,
+    pub y: Option<u8>,
+    pub p: Option<u8>,
+    pub sp: Option<u8>,
+}
+
+impl Expected {
+    // Understands the nestest golden log and Mesen's default trace format: the
+    // line starts with the program counter, and registers appear anywhere as
+    // A:xx X:xx Y:xx P:xx and SP:xx (or S:xx in Mesen). A flag string in
+    // place of the hex P value is ignored.
+    pub fn parse(line: &str) -> Option<Expected> {
+        let mut words = line.split_whitespace();
+        let pc = u16::from_str_radix(words.next()?.trim_start_matches('$'), 16).ok()?;
+        let mut expected = Expected { pc, ..Expected::default() };
+        for word in words {
+            // Registers are always two hex digits, anything else is another field
+            let (name, value) = match word.split_once(':') {
+                Some((name, value)) if value.len() == 2 => (name, value),
+                _ => continue,
+            };
+            let value = match u8::from_str_radix(value, 16) {
+                Ok(value) => value,
+                Err(_) => continue,
+            };
+            match name {
+                "A" => expected.a = Some(value),
+                "X" => expected.x = Some(value),
+                "Y" => expected.y = Some(value),
+                "P" => expected.p = Some(value),
+                "SP" | "S" => expected.sp = Some(value),
+                _ => {}
+            }
+        }
+        Some(expected)
+    }
+
+    fn mismatches(&self, cpu: &CPU) -> Vec<String> {
+        let mut mismatches = Vec::new();
+        if self.pc != cpu.program_counter {
+            mismatches.push(format!("PC is {:04X}, expected {:04X}", cpu.program_counter, self.pc));
+        }
+        let registers = [
+            ("A", self.a, cpu.register_a),
+            ("X", self.x, cpu.register_x),
+            ("Y", self.y, cpu.register_y),
+            ("P", self.p, cpu.status),
+            ("SP", self.sp, cpu.stack_pointer),
+        ];
+        for (name, expected, actual) in registers.iter() {
+            if let Some(expected) = expected {
+                if expected != actual {
+                    mismatches.push(format!("{} is {:02X}, expected {:02X}", name, actual, expected));
+                }
+            }
+        }
+        mismatches
+    }
+}
+
+// First instruction where the emulator and the reference disagree
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    // 1-based line of the reference trace
+    pub line: usize,
+    pub reference: String,
+    pub actual: String,
+    pub mismatches: Vec<String>,
+    // The instructions before the divergence, as executed by the emulator
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Trace diverges at line {}: {}", self.line, self.mismatches.join(", "))?;
+        for line in &self.context {
+            writeln!(f, "    {}", line)?;
+        }
+        writeln!(f, "  > {}", self.actual)?;
+        write!(f, "  reference: {}", self.reference)
+    }
+}
+
+// Compares the CPU state before each instruction with the next line of a
+// reference trace.
+pub struct TraceChecker {
+    lines: Vec<(usize, String, Expected)>,
+    position: usize,
+    history: VecDeque<String>,
+}
+
+impl TraceChecker {
+    // Lines that don't start with an address (headers, blank lines) are skipped
+    pub fn new(reference: &str) -> Self {
+        let lines = reference
+            .lines()
+            .enumerate()
+            .filter_map(|(number, line)| Expected::parse(line).map(|expected| (number + 1, line.to_string(), expected)))
+            .collect();
+        TraceChecker {
+            lines,
+            position: 0,
+            history: VecDeque::with_capacity(CONTEXT),
+        }
+    }
+
+    // Number of reference lines matched so far
+    pub fn matched(&self) -> usize {
+        self.position
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.position == self.lines.len()
+    }
+
+    // Called before each instruction is executed. Once the reference is
+    // exhausted every state matches.
+    pub fn check(&mut self, cpu: &CPU) -> Result<(), Divergence> {
+        let (line, reference, expected) = match self.lines.get(self.position) {
+            Some(entry) => entry,
+            None => return Ok(()),
+        };
+
+        let actual = describe(cpu);
+        let mismatches = expected.mismatches(cpu);
+        if !mismatches.is_empty() {
+            return Err(Divergence {
+                line: *line,
+                reference: reference.clone(),
+                actual,
+                mismatches,
+                context: self.history.iter().cloned().collect(),
+            });
+        }
+
+        if self.history.len() == CONTEXT {
+            self.history.pop_front();
+        }
+        self.history.push_back(actual);
+        self.position += 1;
+        Ok(())
+    }
+}
+
+// The CPU state in the nestest layout
+fn describe(cpu: &CPU) -> String {
+    let instruction = disasm::disassemble(&cpu.bus, cpu.program_counter);
+    let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    format!(
+        "{:04X}  {:<9} {:<16} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+        cpu.program_counter,
+        bytes.join(" "),
+        instruction.text(),
+        cpu.register_a,
+        cpu.register_x,
+        cpu.register_y,
+        cpu.status,
+        cpu.stack_pointer
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_parse_nestest_and_mesen_lines() {
+        let nestest = Expected::parse("C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7").unwrap();
+        assert_eq!(nestest, Expected { pc: 0xc000, a: Some(0), x: Some(0), y: Some(0), p: Some(0x24), sp: Some(0xfd) });
+
+        let mesen = Expected::parse("8000 $78     SEI                  A:00 X:00 Y:00 S:FD P:nvUbdIzc V:0   H:27  Fr:0 Cycle:8").unwrap();
+        assert_eq!(mesen, Expected { pc: 0x8000, a: Some(0), x: Some(0), y: Some(0), p: None, sp: Some(0xfd) });
+
+        assert_eq!(Expected::parse("Trace started"), None);
+    }
+
+    #[test]
+    fn test_stops_at_first_divergence() {
+        let mut cpu = CPU::new(Bus::new());
+        // LDA #$05; TAX; INX; BRK
+        cpu.load(vec![0xa9, 0x05, 0xaa, 0xe8, 0x00]);
+        cpu.program_counter = 0x0600;
+
+        let reference = "0600 A:00 X:00\n0602 A:05 X:00\n0603 A:05 X:06\n0604 A:05 X:06\n";
+        let mut checker = TraceChecker::new(reference);
+        let divergence = loop {
+            if let Err(divergence) = checker.check(&cpu) {
+                break divergence;
+            }
+            assert!(cpu.step());
+        };
+
+        assert_eq!(checker.matched(), 2);
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.mismatches, vec!["X is 05, expected 06".to_string()]);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.actual.starts_with("0603  E8        INX"));
+    }
+}
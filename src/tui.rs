@@ -0,0 +1,351 @@
+use enes::cpu::CPU;
+use enes::debugger::{Debugger, StopReason};
+use enes::disasm;
+use enes::expr::Watch;
+use enes::heatmap::Heatmap;
+use enes::labels::Labels;
+use enes::replay::Session;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::VecDeque;
+use std::io;
+use std::time::Duration;
+
+// Instructions run between two redraws while the program is running, which is
+// about the speed of the game window at 30 redraws per second
+const INSTRUCTIONS_PER_TICK: u32 = 480;
+const TICK: Duration = Duration::from_millis(33);
+
+// Executed instructions shown above the program counter
+const HISTORY_LEN: usize = 6;
+
+const KEYS: &str = "space run/pause  g run to  s step  r/R step back/frame back  b breakpoint  w watch  W unwatch  q quit";
+
+enum Prompt {
+    Breakpoint,
+    RunTo,
+    Watch,
+    Unwatch,
+}
+
+struct App {
+    debugger: Debugger,
+    labels: Labels,
+    heatmap: Heatmap,
+    running: bool,
+    // Where the program stops when run with 'g'
+    run_to: Option<u16>,
+    history: VecDeque<u16>,
+    watches: Vec<u16>,
+    expressions: Vec<Watch>,
+    prompt: Option<(Prompt, String)>,
+    message: String,
+}
+
+// Full screen debugger for --tui, redrawing the panes while the program runs
+pub fn run(mut cpu: CPU, labels: Labels, session: Session) -> io::Result<()> {
+    let mut terminal = ratatui::init();
+    let result = event_loop(&mut terminal, &mut cpu, labels, session);
+    ratatui::restore();
+    result
+}
+
+fn event_loop(terminal: &mut DefaultTerminal, cpu: &mut CPU, labels: Labels, session: Session) -> io::Result<()> {
+    let mut app = App {
+        debugger: Debugger::with_session(session),
+        labels,
+        heatmap: Heatmap::new(),
+        running: false,
+        run_to: None,
+        history: VecDeque::with_capacity(HISTORY_LEN),
+        watches: Vec::new(),
+        expressions: Vec::new(),
+        prompt: None,
+        message: "Paused".to_string(),
+    };
+
+    loop {
+        terminal.draw(|frame| draw(frame, cpu, &app))?;
+
+        if event::poll(TICK)? {
+            if let Event::Key(key) = event::read()? {
+                if key.kind == KeyEventKind::Press && !handle_key(cpu, &mut app, key.code) {
+                    return Ok(());
+                }
+            }
+        }
+
+        if app.running {
+            let reason = run_instructions(cpu, &mut app, INSTRUCTIONS_PER_TICK);
+            if reason != StopReason::StepsDone {
+                app.running = false;
+                app.run_to = None;
+                app.message = stop_message(reason);
+            }
+        }
+    }
+}
+
+// Returns false when the user asked to quit
+fn handle_key(cpu: &mut CPU, app: &mut App, code: KeyCode) -> bool {
+    if let Some((prompt, mut text)) = app.prompt.take() {
+        match code {
+            KeyCode::Enter => app.message = submit_prompt(cpu, app, prompt, &text),
+            KeyCode::Esc => app.message.clear(),
+            KeyCode::Backspace => {
+                text.pop();
+                app.prompt = Some((prompt, text));
+            }
+            KeyCode::Char(c) => {
+                text.push(c);
+                app.prompt = Some((prompt, text));
+            }
+            _ => app.prompt = Some((prompt, text)),
+        }
+        return true;
+    }
+
+    match code {
+        KeyCode::Char('q') | KeyCode::Esc => return false,
+        KeyCode::Char(' ') => {
+            app.running = !app.running;
+            app.run_to = None;
+            app.message = if app.running { "Running" } else { "Paused" }.to_string();
+        }
+        KeyCode::Char('s') if !app.running => {
+            let reason = run_instructions(cpu, app, 1);
+            app.message = stop_message(reason);
+        }
+        KeyCode::Char('r') if !app.running => step_back(cpu, app, 1),
+        KeyCode::Char('R') if !app.running => step_back(cpu, app, crate::INSTRUCTIONS_PER_FRAME as u64),
+        KeyCode::Char('b') => app.prompt = Some((Prompt::Breakpoint, String::new())),
+        KeyCode::Char('g') if !app.running => app.prompt = Some((Prompt::RunTo, String::new())),
+        KeyCode::Char('w') => app.prompt = Some((Prompt::Watch, String::new())),
+        KeyCode::Char('W') => app.prompt = Some((Prompt::Unwatch, String::new())),
+        _ => {}
+    }
+    true
+}
+
+fn submit_prompt(cpu: &CPU, app: &mut App, prompt: Prompt, text: &str) -> String {
+    // "name = expression" watches an expression instead of an address
+    if matches!(prompt, Prompt::Watch) && text.contains('=') {
+        return match Watch::parse(text, &app.labels) {
+            Ok(watch) => {
+                let message = format!("Watching {}", watch);
+                app.expressions.retain(|other| other.name != watch.name);
+                app.expressions.push(watch);
+                message
+            }
+            Err(e) => e,
+        };
+    }
+    if matches!(prompt, Prompt::Unwatch) && app.expressions.iter().any(|watch| watch.name == text) {
+        app.expressions.retain(|watch| watch.name != text);
+        return format!("Stopped watching {}", text);
+    }
+
+    // An empty breakpoint address means the current instruction
+    let address = if text.is_empty() {
+        match prompt {
+            Prompt::Breakpoint => cpu.program_counter,
+            _ => return String::new(),
+        }
+    } else if let Some(address) = app.labels.address(text) {
+        address
+    } else {
+        let digits = text.trim_start_matches('$').trim_start_matches("0x");
+        match u16::from_str_radix(digits, 16) {
+            Ok(address) => address,
+            Err(_) => return format!("'{}' is not a hex number", text),
+        }
+    };
+
+    match prompt {
+        Prompt::Breakpoint => {
+            if app.debugger.add_breakpoint(address) {
+                format!("Breakpoint set at ${:04X}", address)
+            } else {
+                app.debugger.remove_breakpoint(address);
+                format!("Breakpoint removed at ${:04X}", address)
+            }
+        }
+        Prompt::RunTo => {
+            app.run_to = Some(address);
+            app.running = true;
+            format!("Running to ${:04X}", address)
+        }
+        Prompt::Watch => {
+            if !app.watches.contains(&address) {
+                app.watches.push(address);
+            }
+            format!("Watching ${:04X}", address)
+        }
+        Prompt::Unwatch => {
+            app.watches.retain(|&watch| watch != address);
+            format!("Stopped watching ${:04X}", address)
+        }
+    }
+}
+
+fn step_back(cpu: &mut CPU, app: &mut App, count: u64) {
+    app.message = match app.debugger.step_back(cpu, count) {
+        Ok(undone) => {
+            // The executed instructions shown above the program counter go back too
+            for _ in 0..undone {
+                app.history.pop_back();
+            }
+            format!("Went back {} instructions", undone)
+        }
+        Err(e) => e,
+    };
+}
+
+// Runs one instruction at a time to keep track of the last executed ones
+fn run_instructions(cpu: &mut CPU, app: &mut App, count: u32) -> StopReason {
+    for _ in 0..count {
+        if app.history.len() == HISTORY_LEN {
+            app.history.pop_front();
+        }
+        app.history.push_back(cpu.program_counter);
+        app.heatmap.record(cpu.program_counter);
+
+        let reason = app.debugger.run(cpu, Some(1), |_| false);
+        if reason != StopReason::StepsDone {
+            return reason;
+        }
+        if app.run_to == Some(cpu.program_counter) {
+            return StopReason::Breakpoint(cpu.program_counter);
+        }
+    }
+    StopReason::StepsDone
+}
+
+fn stop_message(reason: StopReason) -> String {
+    match reason {
+        StopReason::Breakpoint(address) => format!("Breakpoint at ${:04X}", address),
+        StopReason::Break => "BRK executed".to_string(),
+        StopReason::Error(e) => e.to_string(),
+        StopReason::StepsDone | StopReason::Interrupted => "Paused".to_string(),
+    }
+}
+
+fn draw(frame: &mut Frame, cpu: &CPU, app: &App) {
+    let [main, footer] = Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    let [code, side] = Layout::horizontal([Constraint::Min(30), Constraint::Length(28)]).areas(main);
+    let [registers, stack, watches] = Layout::vertical([
+        Constraint::Length(5),
+        Constraint::Percentage(50),
+        Constraint::Min(3),
+    ])
+    .areas(side);
+
+    draw_disassembly(frame, code, cpu, app);
+    draw_registers(frame, registers, cpu);
+    draw_stack(frame, stack, cpu);
+    draw_watches(frame, watches, cpu, app);
+
+    let status = match &app.prompt {
+        Some((Prompt::Breakpoint, text)) => format!("Toggle breakpoint at (empty for PC): {}", text),
+        Some((Prompt::RunTo, text)) => format!("Run to address: {}", text),
+        Some((Prompt::Watch, text)) => format!("Watch address or name = expression: {}", text),
+        Some((Prompt::Unwatch, text)) => format!("Stop watching address or name: {}", text),
+        None => format!("{}  |  {}", app.message, KEYS),
+    };
+    frame.render_widget(Paragraph::new(status), footer);
+}
+
+// Pushes the label of the address, if it has one, and the disassembled
+// instruction, and returns the address of the next instruction
+fn push_disassembly(lines: &mut Vec<Line<'static>>, cpu: &CPU, app: &App, address: u16) -> u16 {
+    if let Some(name) = app.labels.name(address) {
+        lines.push(Line::from(format!(" {}:", name)));
+    }
+    let instruction = disasm::disassemble(cpu, address);
+    let bytes: Vec<String> = instruction.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+    let marker = if app.debugger.breakpoints().any(|&b| b == address) { '*' } else { ' ' };
+    let text = format!("{}${:04X}  {:<9} {}", marker, address, bytes.join(" "), instruction.labelled_text(&app.labels));
+    lines.push(Line::from(text).style(heat_style(&app.heatmap, address)));
+    address.wrapping_add(instruction.bytes.len() as u16)
+}
+
+// Instructions that never ran are dimmed, and the ones that ran the most
+// turn from green to yellow and red
+fn heat_style(heatmap: &Heatmap, address: u16) -> Style {
+    let count = heatmap.count(address);
+    if count == 0 {
+        return Style::new().fg(Color::DarkGray);
+    }
+    let heat = (count as f64).ln_1p() / (heatmap.max_count() as f64).ln_1p();
+    let color = if heat > 0.9 {
+        Color::Red
+    } else if heat > 0.6 {
+        Color::Yellow
+    } else {
+        Color::Green
+    };
+    Style::new().fg(color)
+}
+
+// Disassembling backwards from the program counter is ambiguous with variable
+// length instructions, so the lines above it are the last executed ones.
+fn draw_disassembly(frame: &mut Frame, area: Rect, cpu: &CPU, app: &App) {
+    let height = area.height.saturating_sub(2) as usize;
+    let mut lines = Vec::new();
+    for &address in &app.history {
+        push_disassembly(&mut lines, cpu, app, address);
+    }
+    if lines.len() > height / 2 {
+        lines.drain(..lines.len() - height / 2);
+    }
+
+    let mut address = push_disassembly(&mut lines, cpu, app, cpu.program_counter);
+    if let Some(current) = lines.last_mut() {
+        current.style = current.style.add_modifier(Modifier::REVERSED);
+    }
+    while lines.len() < height {
+        address = push_disassembly(&mut lines, cpu, app, address);
+    }
+
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Disassembly ")), area);
+}
+
+fn draw_registers(frame: &mut Frame, area: Rect, cpu: &CPU) {
+    let flags: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| if cpu.status & (0b1000_0000 >> i) != 0 { flag } else { flag.to_ascii_lowercase() })
+        .collect();
+    let lines = vec![
+        Line::from(format!("PC:{:04X}  SP:{:02X}", cpu.program_counter, cpu.stack_pointer)),
+        Line::from(format!("A:{:02X} X:{:02X} Y:{:02X}", cpu.register_a, cpu.register_x, cpu.register_y)),
+        Line::from(format!("P:{:02X} [{}]", cpu.status, flags)),
+    ];
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Registers ")), area);
+}
+
+// Pushed bytes from the top of the stack, most recent first
+fn draw_stack(frame: &mut Frame, area: Rect, cpu: &CPU) {
+    let lines: Vec<Line> = (0x0100 + cpu.stack_pointer as u16 + 1..=0x01ff)
+        .map(|address| Line::from(format!("${:04X}  {:02X}", address, cpu.bus.peek(address))))
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Stack ")), area);
+}
+
+fn draw_watches(frame: &mut Frame, area: Rect, cpu: &CPU, app: &App) {
+    let lines: Vec<Line> = app
+        .watches
+        .iter()
+        .map(|&address| {
+            let value = cpu.bus.peek(address);
+            let name = app.labels.name(address).unwrap_or_default();
+            Line::from(format!("${:04X}  {:02X}  {:3}  {}", address, value, value, name))
+        })
+        .chain(app.expressions.iter().map(|watch| Line::from(format!("{} = {}", watch.name, watch.expr.eval(cpu)))))
+        .collect();
+    frame.render_widget(Paragraph::new(lines).block(Block::bordered().title(" Watch ")), area);
+}
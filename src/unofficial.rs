@@ -0,0 +1,56 @@
+use crate::cpu::CPU;
+use crate::disasm::{self, Instruction};
+use crate::opcodes;
+use std::collections::HashSet;
+
+// Spots the unofficial opcodes a program runs, each place once, to tell
+// glitches caused by them from other bugs
+pub struct UnofficialOpcodes {
+    seen: HashSet<(u16, u8)>,
+}
+
+impl UnofficialOpcodes {
+    pub fn new() -> Self {
+        UnofficialOpcodes { seen: HashSet::new() }
+    }
+
+    // Called before each instruction is executed. Returns the instruction at
+    // the program counter the first time an unofficial opcode runs there.
+    pub fn check(&mut self, cpu: &CPU) -> Option<Instruction> {
+        let pc = cpu.program_counter;
+        let code = cpu.bus.peek(pc);
+        if !opcodes::UNOFFICIAL_OPCODES_MAP.contains_key(&code) || !self.seen.insert((pc, code)) {
+            return None;
+        }
+        Some(disasm::disassemble(cpu, pc))
+    }
+}
+
+impl Default for UnofficialOpcodes {
+    fn default() -> Self {
+        UnofficialOpcodes::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::bus::Bus;
+
+    #[test]
+    fn test_reports_each_place_once() {
+        let mut cpu = CPU::new(Bus::new());
+        // LDA #$01; LAX $10; NOP $20,X
+        cpu.load(vec![0xa9, 0x01, 0xa7, 0x10, 0x34, 0x20]);
+        cpu.program_counter = 0x0600;
+        let mut unofficial = UnofficialOpcodes::new();
+        assert!(unofficial.check(&cpu).is_none());
+
+        cpu.program_counter = 0x0602;
+        assert_eq!(unofficial.check(&cpu).unwrap().text(), "LAX $10");
+        assert!(unofficial.check(&cpu).is_none());
+
+        cpu.program_counter = 0x0604;
+        assert_eq!(unofficial.check(&cpu).unwrap().text(), "NOP $20,X");
+    }
+}